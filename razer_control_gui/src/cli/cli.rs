@@ -1,6 +1,8 @@
 #[path = "../comms.rs"]
 mod comms;
 use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::convert::TryInto;
+use std::io::{Read, Write};
 
 #[derive(Parser)]
 #[command(version="0.5.0", about="razer laptop configuration for linux", name="razer-cli")]
@@ -31,6 +33,155 @@ enum Args {
         #[command(subcommand)]
         effect: Effect,
     },
+    /// Manage named performance profiles
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Manage the layers of custom effects
+    Layer {
+        #[command(subcommand)]
+        action: LayerAction,
+    },
+    /// Manage the time of day schedule
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleAction,
+    },
+    /// Flash the keyboard for desktop notifications
+    Notifications {
+        #[command(subcommand)]
+        action: NotificationAction,
+    },
+    /// Stream frames from stdin to the keyboard until the input ends
+    Stream(StreamParams),
+    /// Render effects without showing them on the keyboard
+    Preview(PreviewParams),
+    /// Find the colour correction of the keyboard with test patterns
+    Calibrate,
+    /// Work with keyframe animation files
+    Animation {
+        #[command(subcommand)]
+        action: AnimationAction,
+    },
+    /// Export all settings and effects to a bundle file
+    Export(BundleParams),
+    /// Import settings and effects from a bundle file
+    Import(BundleParams),
+}
+
+#[derive(Parser)]
+struct StreamParams {
+    /// draw the frames instead of this layer's effect, rather than over the whole keyboard
+    #[arg(long)]
+    layer: Option<u32>,
+}
+
+#[derive(Parser)]
+struct PreviewParams {
+    /// effect ID, see `effect list`. Without it the current layers are rendered
+    effect: Option<String>,
+    /// effect parameters (0-255), in the order given by `effect list`
+    params: Vec<u8>,
+    /// file parameter of the effect, like the image to draw. Goes after the other parameters
+    #[arg(long)]
+    file: Option<String>,
+    /// effects save to render instead, like effects.json
+    #[arg(long, conflicts_with = "effect")]
+    effects: Option<String>,
+    /// number of frames to render
+    #[arg(long, default_value_t = 1)]
+    frames: u32,
+    /// time between frames in ms
+    #[arg(long, default_value_t = 33)]
+    interval: u32,
+    /// write the frames to a .gif, or to .png files numbered when there are several frames
+    #[arg(long)]
+    output: Option<String>,
+    /// size of a key in the written images, in pixels
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(2..=128))]
+    key_size: u32,
+}
+
+#[derive(Subcommand)]
+enum ScheduleAction {
+    /// Show the schedule rules and which one is active
+    Show,
+    /// Replace the schedule with the rules of a JSON file
+    Set(ScheduleFileParam),
+    /// Remove every schedule rule
+    Clear,
+}
+
+#[derive(Parser)]
+struct ScheduleFileParam {
+    /// JSON file with the rules, and the location for sunrise and sunset
+    path: String,
+}
+
+/// Contents of a schedule file
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleFile {
+    #[serde(default)]
+    location: Option<[f64; 2]>, // Latitude and longitude in degrees
+    rules: Vec<comms::ScheduleRule>,
+}
+
+#[derive(Subcommand)]
+enum NotificationAction {
+    /// Show whether notifications are shown, and the rules
+    Show,
+    /// Start flashing the keyboard for notifications
+    On,
+    /// Stop flashing the keyboard for notifications
+    Off,
+    /// Hold back the flashes while on, without turning notifications off
+    Dnd(DndParams),
+    /// Replace the rules with the ones of a JSON file
+    Set(NotificationFileParam),
+    /// Remove every rule
+    Clear,
+}
+
+#[derive(Parser)]
+struct DndParams {
+    state: OnOff,
+}
+
+#[derive(Parser)]
+struct NotificationFileParam {
+    /// JSON file with the rules
+    path: String,
+}
+
+/// Contents of a notification rules file
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NotificationFile {
+    rules: Vec<comms::NotificationRule>,
+}
+
+#[derive(Subcommand)]
+enum AnimationAction {
+    /// Check an animation file, reporting errors with their line
+    Validate(AnimationFileParam),
+}
+
+#[derive(Parser)]
+struct KeyframesParams {
+    /// JSON animation file
+    path: String,
+    /// keep a copy of the animation in the layer instead of the path, so it
+    /// doesn't depend on the file
+    #[arg(long)]
+    embed: bool,
+}
+
+#[derive(Parser)]
+struct AnimationFileParam {
+    /// JSON animation file
+    path: String,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -55,10 +206,30 @@ enum ReadAttr {
     Brightness(AcStateParam),
     /// Read the current logo mode
     Logo(AcStateParam),
+    /// Read the brightness of the logo
+    LogoBrightness(AcStateParam),
+    /// Read whether the logo stays on when the keyboard turns off on idle
+    LogoIdle(AcStateParam),
     /// Read the current sync mode
     Sync,
     /// Read the current bho mode
     Bho,
+    /// Read the frame rate of custom effects
+    Fps(AcStateParam),
+    /// Read whether custom effects react to key presses
+    KeyEvents,
+    /// Read whether the OpenRGB SDK server is running, and its port
+    Openrgb,
+    /// Read the colour correction of custom effects
+    ColourCorrection,
+    /// Read how long crossfades and brightness fades take
+    Transitions,
+    /// Read which lock keys are lit while their lock is on
+    LockIndicators,
+    /// Read the standard effect
+    StandardEffect,
+    /// Read what changes below a battery level, and if it is low now
+    LowBattery,
 }
 
 #[derive(Subcommand)]
@@ -71,10 +242,82 @@ enum WriteAttr {
     Brightness(BrightnessParams),
     /// Set the logo mode
     Logo(LogoParams),
+    /// Set the brightness of the logo
+    LogoBrightness(BrightnessParams),
+    /// Set whether the logo stays on when the keyboard turns off on idle or screensaver
+    LogoIdle(LogoIdleParams),
     /// Set sync
     Sync(SyncParams),
     /// Set battery health optimization
     Bho(BhoParams),
+    /// Set the frame rate of custom effects
+    Fps(FpsParams),
+    /// Set whether custom effects react to key presses
+    KeyEvents(KeyEventsParams),
+    /// Start or stop the OpenRGB SDK server
+    Openrgb(OpenRgbParams),
+    /// Set the colour correction of custom effects, see also `calibrate`
+    ColourCorrection(ColourCorrectionParams),
+    /// Set how long crossfades and brightness fades take
+    Transitions(TransitionsParams),
+    /// Light lock keys over the custom effects while their lock is on
+    LockIndicators(LockIndicatorsParams),
+    /// Turn off or dim the lights, pause animations and lower power below a battery level
+    LowBattery(LowBatteryParams),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Lock {
+    Caps,
+    Num,
+    Scroll,
+}
+
+#[derive(Parser)]
+struct LockIndicatorsParams {
+    /// locks with an indicator, none to turn them off
+    locks: Vec<Lock>,
+    /// colour of the lit keys
+    #[arg(long, num_args = 3, value_names = ["RED", "GREEN", "BLUE"])]
+    colour: Option<Vec<u8>>,
+}
+
+#[derive(Parser)]
+struct LowBatteryParams {
+    /// battery level in %, 0 to turn off
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+    threshold: u8,
+    /// highest keyboard and logo brightness in %, 0 turns them off
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=100))]
+    brightness: u8,
+    /// power mode, cpu boost and gpu boost to switch to
+    #[arg(long, num_args = 3, value_names = ["PWR", "CPU", "GPU"])]
+    power: Option<Vec<u8>>,
+    /// fan speed to switch to, 0 for auto
+    #[arg(long)]
+    fan: Option<i32>,
+}
+
+#[derive(Parser)]
+struct TransitionsParams {
+    /// length in ms, 0 to switch instantly (up to 5000)
+    ms: u32,
+}
+
+#[derive(Parser)]
+struct ColourCorrectionParams {
+    /// gamma curve, below 1 lifts dark colours (0.2-5.0)
+    #[arg(long)]
+    gamma: Option<f32>,
+    /// gain of red, green and blue in % (0-200)
+    #[arg(long, num_args = 3, value_names = ["RED", "GREEN", "BLUE"])]
+    gain: Option<Vec<u8>>,
+    /// colour white is shown as
+    #[arg(long, num_args = 3, value_names = ["RED", "GREEN", "BLUE"])]
+    white_point: Option<Vec<u8>>,
+    /// go back to no correction, before applying the other options
+    #[arg(long)]
+    reset: bool,
 }
 
 #[derive(Parser)]
@@ -109,10 +352,39 @@ struct BrightnessParams {
 struct LogoParams {
     /// battery/plugged in
     ac_state: AcState,
-    /// logo mode (0, 1 or 2)
+    /// logo mode (0 off, 1 on, 2 breathing or 3 spectrum, if the logo can show it)
     logo_state: i32,
 }
 
+#[derive(Parser)]
+struct LogoIdleParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    state: OnOff,
+}
+
+#[derive(Parser)]
+struct FpsParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// frames per second (1-60)
+    #[arg(value_parser = clap::value_parser!(u8).range(1..=60))]
+    fps: u8,
+}
+
+#[derive(Parser)]
+struct OpenRgbParams {
+    state: OnOff,
+    /// local TCP port to listen on
+    #[arg(long, default_value_t = comms::OPENRGB_DEFAULT_PORT)]
+    port: u16,
+}
+
+#[derive(Parser)]
+struct KeyEventsParams {
+    state: OnOff,
+}
+
 #[derive(Parser)]
 struct SyncParams {
     sync_state: OnOff,
@@ -139,10 +411,185 @@ struct AcStateParam {
     ac_state: AcState,
 }
 
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// Save the current settings of a power state as a profile
+    Create(CreateProfileParams),
+    /// List the saved profiles
+    List,
+    /// Rename a profile
+    Rename(RenameProfileParams),
+    /// Delete a profile
+    Delete(ProfileNameParam),
+    /// Apply a profile to a power state
+    Activate(ActivateProfileParams),
+    /// Set the profile applied when switching to a power state
+    Default(DefaultProfileParams),
+}
+
+#[derive(Parser)]
+struct CreateProfileParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// profile name
+    name: String,
+    /// also save the current keyboard effects
+    #[arg(long)]
+    effects: bool,
+}
+
+#[derive(Parser)]
+struct RenameProfileParams {
+    /// profile name
+    name: String,
+    /// new profile name
+    new_name: String,
+}
+
+#[derive(Parser)]
+struct ProfileNameParam {
+    /// profile name
+    name: String,
+}
+
+#[derive(Parser)]
+struct ActivateProfileParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// profile name
+    name: String,
+}
+
+#[derive(Parser)]
+struct DefaultProfileParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// profile name, leave empty to remove the default
+    name: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum LayerAction {
+    /// List the layers, bottom layer first
+    List,
+    /// Add a layer with a custom effect
+    Add(AddLayerParams),
+    /// Change the effect of a layer, keeping its keys
+    Replace(ReplaceLayerParams),
+    /// Move a layer to another position
+    Move(MoveLayerParams),
+    /// Remove a layer
+    Remove(LayerIdParam),
+    /// Show a hidden layer
+    Show(LayerIdParam),
+    /// Hide a layer without removing it
+    Hide(LayerIdParam),
+    /// Set the keys a layer is drawn on
+    Mask(LayerMaskParams),
+    /// Set how a layer is combined with the layers below it
+    Blend(LayerBlendParams),
+}
+
+#[derive(Parser)]
+struct AddLayerParams {
+    /// effect ID, see `effect list`
+    effect: String,
+    /// effect parameters (0-255), in the order given by `effect list`
+    params: Vec<u8>,
+    /// file parameter of the effect, like the image to draw. Goes after the other parameters
+    #[arg(long)]
+    file: Option<String>,
+    /// position in the stack, 0 is the bottom. Defaults to the top
+    #[arg(long)]
+    position: Option<usize>,
+    /// keys to draw the layer on, like 0-14,30,31 (row * 15 + column). Defaults to all
+    #[arg(long)]
+    keys: Option<String>,
+}
+
+#[derive(Parser)]
+struct ReplaceLayerParams {
+    /// layer ID
+    id: u32,
+    /// effect ID, see `effect list`
+    effect: String,
+    /// effect parameters (0-255), in the order given by `effect list`
+    params: Vec<u8>,
+    /// file parameter of the effect, like the image to draw. Goes after the other parameters
+    #[arg(long)]
+    file: Option<String>,
+}
+
+#[derive(Parser)]
+struct MoveLayerParams {
+    /// layer ID
+    id: u32,
+    /// new position in the stack, 0 is the bottom
+    position: usize,
+}
+
+#[derive(Parser)]
+struct LayerIdParam {
+    /// layer ID
+    id: u32,
+}
+
+#[derive(Parser)]
+struct LayerMaskParams {
+    /// layer ID
+    id: u32,
+    /// keys to draw the layer on, like 0-14,30,31 (row * 15 + column), or all
+    keys: String,
+}
+
+#[derive(ValueEnum, Clone)]
+enum Blend {
+    /// draw over the layers below
+    Replace,
+    /// mix with the layers below by opacity
+    Alpha,
+    /// add to the layers below
+    Add,
+    /// darken the layers below
+    Multiply,
+    /// keep the brightest channels
+    Max,
+}
+
+impl Blend {
+    fn to_mode(&self) -> comms::BlendMode {
+        match self {
+            Blend::Replace => comms::BlendMode::Replace,
+            Blend::Alpha => comms::BlendMode::Alpha,
+            Blend::Add => comms::BlendMode::Add,
+            Blend::Multiply => comms::BlendMode::Multiply,
+            Blend::Max => comms::BlendMode::Max,
+        }
+    }
+}
+
+#[derive(Parser)]
+struct LayerBlendParams {
+    /// layer ID
+    id: u32,
+    /// blend mode
+    blend: Blend,
+    /// opacity (0-100 %), ignored by replace
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    opacity: u8,
+}
+
+#[derive(Parser)]
+struct BundleParams {
+    /// bundle file
+    path: String,
+}
+
 #[derive(Subcommand)]
 enum StandardEffect {
     Off,
     Wave(WaveParams),
+    /// Pressed keys light up
     Reactive(ReactiveParams),
     Breathing(BreathingParams),
     Spectrum,
@@ -150,15 +597,25 @@ enum StandardEffect {
     Starlight(StarlightParams),
 }
 
+#[derive(ValueEnum, Clone)]
+enum StandardWaveDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
 #[derive(Parser)]
 struct WaveParams {
-    /// direction (0 or 1)
-    direction: u8,
+    /// direction
+    direction: StandardWaveDirection,
+    /// speed (1-255)
+    #[arg(long, default_value_t = comms::StandardEffect::DEFAULT_WAVE_SPEED, value_parser = clap::value_parser!(u8).range(1..=255))]
+    speed: u8,
 }
 
 #[derive(Parser)]
 struct ReactiveParams {
-    /// speed (0-255)
+    /// speed (1-4)
+    #[arg(value_parser = clap::value_parser!(u8).range(1..=4))]
     speed: u8,
     /// red (0-255)
     red: u8,
@@ -170,28 +627,31 @@ struct ReactiveParams {
 
 #[derive(Parser)]
 struct BreathingParams {
-    /// kind (0-2)
-    kind: u8,
-    /// red1 (0-255)
-    red1: u8,
-    /// green1 (0-255)
-    green1: u8,
-    /// blue1 (0-255)
-    blue1: u8,
-    /// red2 (0-255)
-    red2: u8,
-    /// green2 (0-255)
-    green2: u8,
-    /// blue2 (0-255)
-    blue2: u8,
+    #[command(subcommand)]
+    colours: EffectColours,
 }
 
 #[derive(Parser)]
 struct StarlightParams {
-    /// kind (0-2)
-    kind: u8,
-    /// speed (0-255)
+    /// speed (1-3)
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=3))]
     speed: u8,
+    #[command(subcommand)]
+    colours: EffectColours,
+}
+
+#[derive(Subcommand)]
+enum EffectColours {
+    /// One colour
+    Single(StaticParams),
+    /// Two colours
+    Dual(DualColourParams),
+    /// Random colours
+    Random,
+}
+
+#[derive(Parser)]
+struct DualColourParams {
     /// red1 (0-255)
     red1: u8,
     /// green1 (0-255)
@@ -206,12 +666,42 @@ struct StarlightParams {
     blue2: u8,
 }
 
+impl EffectColours {
+    fn to_comms(&self) -> comms::EffectColours {
+        match self {
+            EffectColours::Single(c) => comms::EffectColours::Single([c.red, c.green, c.blue]),
+            EffectColours::Dual(c) => comms::EffectColours::Dual([c.red1, c.green1, c.blue1], [c.red2, c.green2, c.blue2]),
+            EffectColours::Random => comms::EffectColours::Random,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Effect {
+    /// Lists the custom effects the daemon knows about and their parameters
+    List,
     Static(StaticParams),
     StaticGradient(StaticGradientParams),
     WaveGradient(WaveGradientParams),
     BreathingSingle(BreathingSingleParams),
+    Spectrum(SpectrumParams),
+    RainbowWave(RainbowWaveParams),
+    /// Rings spreading from pressed keys (needs key events on)
+    Ripple(RippleParams),
+    /// Pressed keys light up and fade out (needs key events on)
+    Afterglow(AfterglowParams),
+    /// Keys get hotter the more they are pressed (needs key events on)
+    Heatmap(HeatmapParams),
+    /// CPU load as a bar on the number keys
+    CpuLoad(CpuLoadParams),
+    /// Battery percentage as a gauge on the F-keys
+    BatteryGauge(BatteryGaugeParams),
+    /// CPU temperature as a colour on the whole keyboard
+    CpuTemperature(CpuTemperatureParams),
+    /// PNG picture, or animated GIF/APNG, scaled to the keyboard
+    Image(ImageParams),
+    /// Plays a keyframe animation file
+    Keyframes(KeyframesParams),
 }
 
 #[derive(Parser)]
@@ -268,6 +758,165 @@ struct BreathingSingleParams {
     duration: u8,
 }
 
+#[derive(Parser)]
+struct SpectrumParams {
+    /// speed (1-100), 10 takes 10 seconds per cycle
+    #[arg(default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=100))]
+    speed: u8,
+}
+
+#[derive(ValueEnum, Clone)]
+enum WaveDirection {
+    Horizontal,
+    Vertical,
+    Diagonal,
+    /// from the centre key outwards
+    Radial,
+}
+
+#[derive(Parser)]
+struct RainbowWaveParams {
+    /// direction of the wave
+    direction: WaveDirection,
+    /// speed (1-100), 10 takes 10 seconds per cycle
+    #[arg(default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=100))]
+    speed: u8,
+    /// centre key of radial waves (row * 15 + column)
+    #[arg(long, default_value_t = 37, value_parser = clap::value_parser!(u8).range(0..90))]
+    centre: u8,
+}
+
+#[derive(Parser)]
+struct RippleParams {
+    /// red (0-255)
+    red: u8,
+    /// green (0-255)
+    green: u8,
+    /// blue (0-255)
+    blue: u8,
+    /// speed in keys per second (1-50)
+    #[arg(default_value_t = 15, value_parser = clap::value_parser!(u8).range(1..=50))]
+    speed: u8,
+}
+
+#[derive(Parser)]
+struct AfterglowParams {
+    /// red (0-255)
+    red: u8,
+    /// green (0-255)
+    green: u8,
+    /// blue (0-255)
+    blue: u8,
+    /// fade out time in tenths of a second (1-255)
+    #[arg(default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..))]
+    duration: u8,
+}
+
+#[derive(Parser)]
+struct HeatmapParams {
+    /// red1 (0-255)
+    red1: u8,
+    /// green1 (0-255)
+    green1: u8,
+    /// blue1 (0-255)
+    blue1: u8,
+    /// red2 (0-255)
+    red2: u8,
+    /// green2 (0-255)
+    green2: u8,
+    /// blue2 (0-255)
+    blue2: u8,
+    /// seconds for keys to cool down (1-255)
+    #[arg(default_value_t = 60, value_parser = clap::value_parser!(u8).range(1..))]
+    cool_down: u8,
+}
+
+#[derive(Parser)]
+struct CpuLoadParams {
+    /// red1 (0-255)
+    red1: u8,
+    /// green1 (0-255)
+    green1: u8,
+    /// blue1 (0-255)
+    blue1: u8,
+    /// red2 (0-255), used above the high load
+    red2: u8,
+    /// green2 (0-255)
+    green2: u8,
+    /// blue2 (0-255)
+    blue2: u8,
+    /// high load in % (0-100)
+    #[arg(long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(0..=100))]
+    high: u8,
+    /// sample interval in tenths of a second (1-100)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=100))]
+    interval: u8,
+}
+
+#[derive(Parser)]
+struct BatteryGaugeParams {
+    /// red1 (0-255)
+    red1: u8,
+    /// green1 (0-255)
+    green1: u8,
+    /// blue1 (0-255)
+    blue1: u8,
+    /// red2 (0-255), used at low battery
+    red2: u8,
+    /// green2 (0-255)
+    green2: u8,
+    /// blue2 (0-255)
+    blue2: u8,
+    /// low battery in % (0-100)
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(0..=100))]
+    low: u8,
+    /// sample interval in tenths of a second (1-100)
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u8).range(1..=100))]
+    interval: u8,
+}
+
+#[derive(Parser)]
+struct CpuTemperatureParams {
+    /// red1 (0-255)
+    red1: u8,
+    /// green1 (0-255)
+    green1: u8,
+    /// blue1 (0-255)
+    blue1: u8,
+    /// red2 (0-255)
+    red2: u8,
+    /// green2 (0-255)
+    green2: u8,
+    /// blue2 (0-255)
+    blue2: u8,
+    /// temperature shown in the first colour, in °C (0-120)
+    #[arg(long, default_value_t = 40, value_parser = clap::value_parser!(u8).range(0..=120))]
+    cool: u8,
+    /// temperature shown in the second colour, in °C (0-120)
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(0..=120))]
+    hot: u8,
+    /// sample interval in tenths of a second (1-100)
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(1..=100))]
+    interval: u8,
+}
+
+#[derive(ValueEnum, Clone)]
+enum Scaling {
+    /// colour at the centre of each key
+    Nearest,
+    /// average of the pixels under each key
+    Area,
+}
+
+#[derive(Parser)]
+struct ImageParams {
+    /// PNG, APNG or GIF file
+    path: String,
+    /// how the image is scaled to the keys
+    #[arg(long, default_value = "area")]
+    scaling: Scaling,
+}
+
 fn main() {
     if std::fs::metadata(comms::SOCKET_PATH).is_err() {
         eprintln!("Error. Socket doesn't exit. Is daemon running?");
@@ -282,8 +931,18 @@ fn main() {
             ReadAttr::Power(AcStateParam { ac_state }) => read_power_mode(ac_state as usize),
             ReadAttr::Brightness(AcStateParam { ac_state }) => read_brightness(ac_state as usize),
             ReadAttr::Logo(AcStateParam { ac_state }) => read_logo_mode(ac_state as usize),
+            ReadAttr::LogoBrightness(AcStateParam { ac_state }) => read_logo_brightness(ac_state as usize),
+            ReadAttr::LogoIdle(AcStateParam { ac_state }) => read_logo_idle(ac_state as usize),
             ReadAttr::Sync => read_sync(),
             ReadAttr::Bho => read_bho(),
+            ReadAttr::Fps(AcStateParam { ac_state }) => read_animation_fps(ac_state as usize),
+            ReadAttr::KeyEvents => read_key_events(),
+            ReadAttr::Openrgb => read_openrgb(),
+            ReadAttr::ColourCorrection => read_colour_correction(),
+            ReadAttr::Transitions => read_transitions(),
+            ReadAttr::LockIndicators => read_lock_indicators(),
+            ReadAttr::StandardEffect => read_standard_effect(),
+            ReadAttr::LowBattery => read_low_battery(),
         },
         Args::Write { attr } => match attr {
             WriteAttr::Fan(FanParams { ac_state, speed }) => {
@@ -300,15 +959,63 @@ fn main() {
                 brightness,
             }) => write_brightness(ac_state as usize, brightness as u8),
             WriteAttr::Sync(SyncParams { sync_state }) => write_sync(sync_state.is_on()),
+            WriteAttr::Fps(FpsParams { ac_state, fps }) => write_animation_fps(ac_state as usize, fps),
+            WriteAttr::KeyEvents(KeyEventsParams { state }) => write_key_events(state.is_on()),
+            WriteAttr::Openrgb(OpenRgbParams { state, port }) => {
+                write_openrgb(if state.is_on() { Some(port) } else { None })
+            }
+            WriteAttr::Transitions(TransitionsParams { ms }) => write_transitions(ms),
+            WriteAttr::LockIndicators(LockIndicatorsParams { locks, colour }) => {
+                if let Some(mut indicators) = get_lock_indicators() {
+                    indicators.caps = locks.contains(&Lock::Caps);
+                    indicators.num = locks.contains(&Lock::Num);
+                    indicators.scroll = locks.contains(&Lock::Scroll);
+                    if let Some(colour) = colour {
+                        indicators.colour.copy_from_slice(&colour);
+                    }
+                    write_lock_indicators(indicators)
+                }
+            }
+            WriteAttr::LowBattery(LowBatteryParams { threshold, brightness, power, fan }) => {
+                let actions = comms::LowBattery {
+                    brightness,
+                    power: power.map(|p| (p[0], p[1], p[2])),
+                    fan_rpm: fan,
+                };
+                write_low_battery(threshold as f64, actions)
+            }
+            WriteAttr::ColourCorrection(ColourCorrectionParams { gamma, gain, white_point, reset }) => {
+                let mut correction = match reset {
+                    true => Some(comms::ColourCorrection::default()),
+                    false => get_colour_correction(),
+                };
+                if let Some(correction) = correction.as_mut() {
+                    correction.gamma = gamma.unwrap_or(correction.gamma);
+                    if let Some(gain) = gain {
+                        correction.gain.copy_from_slice(&gain);
+                    }
+                    if let Some(white_point) = white_point {
+                        correction.white_point.copy_from_slice(&white_point);
+                    }
+                }
+                if let Some(correction) = correction {
+                    write_colour_correction(correction)
+                }
+            }
             WriteAttr::Logo(LogoParams {
                 ac_state,
                 logo_state,
             }) => write_logo_mode(ac_state as usize, logo_state as u8),
+            WriteAttr::LogoBrightness(BrightnessParams { ac_state, brightness }) => {
+                write_logo_brightness(ac_state as usize, brightness as u8)
+            }
+            WriteAttr::LogoIdle(LogoIdleParams { ac_state, state }) => write_logo_idle(ac_state as usize, state.is_on()),
             WriteAttr::Bho(BhoParams { state, threshold }) => {
                 validate_and_write_bho(threshold, state)
             }
         },
         Args::Effect { effect } => match effect {
+            Effect::List => list_effects(),
             Effect::Static(params) => send_effect(
                 "static".to_string(),
                 vec![params.red, params.green, params.blue],
@@ -339,47 +1046,702 @@ fn main() {
                 "breathing_single".to_string(),
                 vec![params.red, params.green, params.blue, params.duration],
             ),
-        },
-        Args::StandardEffect { effect } => match effect {
-            StandardEffect::Off => send_standard_effect("off".to_string(), vec![]),
-            StandardEffect::Spectrum => send_standard_effect("spectrum".to_string(), vec![]),
-            StandardEffect::Breathing(params) => send_standard_effect(
-                "breathing".to_string(),
+            Effect::Spectrum(params) => send_effect("spectrum".to_string(), vec![params.speed]),
+            Effect::RainbowWave(params) => send_effect(
+                "rainbow_wave".to_string(),
+                vec![params.direction as u8, params.speed, params.centre],
+            ),
+            Effect::Ripple(params) => send_effect(
+                "ripple".to_string(),
+                vec![params.red, params.green, params.blue, params.speed],
+            ),
+            Effect::Afterglow(params) => send_effect(
+                "afterglow".to_string(),
+                vec![params.red, params.green, params.blue, params.duration],
+            ),
+            Effect::Heatmap(params) => send_effect(
+                "heatmap".to_string(),
                 vec![
-                    params.kind,
                     params.red1,
                     params.green1,
                     params.blue1,
                     params.red2,
                     params.green2,
                     params.blue2,
+                    params.cool_down,
                 ],
             ),
-            StandardEffect::Reactive(params) => send_standard_effect(
-                "reactive".to_string(),
-                vec![params.speed, params.red, params.green, params.blue],
+            Effect::CpuLoad(params) => send_effect(
+                "cpu_load".to_string(),
+                vec![
+                    params.red1,
+                    params.green1,
+                    params.blue1,
+                    params.red2,
+                    params.green2,
+                    params.blue2,
+                    params.high,
+                    params.interval,
+                ],
             ),
-            StandardEffect::Starlight(params) => send_standard_effect(
-                "starlight".to_string(),
+            Effect::BatteryGauge(params) => send_effect(
+                "battery_gauge".to_string(),
                 vec![
-                    params.kind,
-                    params.speed,
                     params.red1,
                     params.green1,
                     params.blue1,
                     params.red2,
                     params.green2,
                     params.blue2,
+                    params.low,
+                    params.interval,
                 ],
             ),
-            StandardEffect::Static(params) => send_standard_effect(
-                "static".to_string(),
-                vec![params.red, params.green, params.blue],
+            Effect::Keyframes(KeyframesParams { path, embed: false }) => {
+                send_effect("keyframes".to_string(), file_param(&path))
+            },
+            Effect::Keyframes(KeyframesParams { path, embed: true }) => {
+                send_effect("keyframes_embedded".to_string(), file_contents_param(&path))
+            },
+            Effect::Image(params) => {
+                let mut args = vec![params.scaling as u8];
+                args.extend(file_param(&params.path));
+                send_effect("image".to_string(), args)
+            },
+            Effect::CpuTemperature(params) => send_effect(
+                "cpu_temperature".to_string(),
+                vec![
+                    params.red1,
+                    params.green1,
+                    params.blue1,
+                    params.red2,
+                    params.green2,
+                    params.blue2,
+                    params.cool,
+                    params.hot,
+                    params.interval,
+                ],
             ),
-            StandardEffect::Wave(params) => {
-                send_standard_effect("wave".to_string(), vec![params.direction])
+        },
+        Args::StandardEffect { effect } => send_standard_effect(match effect {
+            StandardEffect::Off => comms::StandardEffect::Off,
+            StandardEffect::Spectrum => comms::StandardEffect::Spectrum,
+            StandardEffect::Breathing(params) => comms::StandardEffect::Breathing { colours: params.colours.to_comms() },
+            StandardEffect::Reactive(params) => comms::StandardEffect::Reactive {
+                speed: params.speed,
+                colour: [params.red, params.green, params.blue],
+            },
+            StandardEffect::Starlight(params) => comms::StandardEffect::Starlight {
+                speed: params.speed,
+                colours: params.colours.to_comms(),
+            },
+            StandardEffect::Static(params) => comms::StandardEffect::Static { colour: [params.red, params.green, params.blue] },
+            StandardEffect::Wave(params) => comms::StandardEffect::Wave {
+                direction: match params.direction {
+                    StandardWaveDirection::LeftToRight => comms::WaveDirection::LeftToRight,
+                    StandardWaveDirection::RightToLeft => comms::WaveDirection::RightToLeft,
+                },
+                speed: params.speed,
+            },
+        }),
+        Args::Profile { action } => match action {
+            ProfileAction::Create(CreateProfileParams { ac_state, name, effects }) => {
+                create_profile(ac_state as usize, name, effects)
+            }
+            ProfileAction::List => list_profiles(),
+            ProfileAction::Rename(RenameProfileParams { name, new_name }) => {
+                rename_profile(name, new_name)
+            }
+            ProfileAction::Delete(ProfileNameParam { name }) => delete_profile(name),
+            ProfileAction::Activate(ActivateProfileParams { ac_state, name }) => {
+                activate_profile(ac_state as usize, name)
+            }
+            ProfileAction::Default(DefaultProfileParams { ac_state, name }) => {
+                write_default_profile(ac_state as usize, name)
+            }
+        },
+        Args::Layer { action } => match action {
+            LayerAction::List => list_layers(),
+            LayerAction::Add(AddLayerParams { effect, mut params, file, position, keys }) => {
+                params.extend(file.map(|f| file_arg(&effect, &f)).unwrap_or_default());
+                add_layer(effect, params, position, keys.map(|keys| parse_keys(&keys)))
+            }
+            LayerAction::Replace(ReplaceLayerParams { id, effect, mut params, file }) => {
+                params.extend(file.map(|f| file_arg(&effect, &f)).unwrap_or_default());
+                replace_layer(id, effect, params)
+            }
+            LayerAction::Move(MoveLayerParams { id, position }) => move_layer(id, position),
+            LayerAction::Remove(LayerIdParam { id }) => remove_layer(id),
+            LayerAction::Show(LayerIdParam { id }) => set_layer_visible(id, true),
+            LayerAction::Hide(LayerIdParam { id }) => set_layer_visible(id, false),
+            LayerAction::Mask(LayerMaskParams { id, keys }) => set_layer_mask(id, parse_keys(&keys)),
+            LayerAction::Blend(LayerBlendParams { id, blend, opacity }) => {
+                set_layer_blend(id, blend.to_mode(), opacity)
+            }
+        },
+        Args::Schedule { action } => match action {
+            ScheduleAction::Show => show_schedule(),
+            ScheduleAction::Set(ScheduleFileParam { path }) => set_schedule_file(path),
+            ScheduleAction::Clear => write_schedule(vec![], None),
+        },
+        Args::Notifications { action } => match action {
+            NotificationAction::Show => show_notifications(),
+            NotificationAction::On => write_notifications(true),
+            NotificationAction::Off => write_notifications(false),
+            NotificationAction::Dnd(DndParams { state }) => write_do_not_disturb(state.is_on()),
+            NotificationAction::Set(NotificationFileParam { path }) => set_notification_file(path),
+            NotificationAction::Clear => write_notification_rules(vec![]),
+        },
+        Args::Stream(StreamParams { layer }) => stream_frames(layer),
+        Args::Preview(PreviewParams { effect, mut params, file, effects, frames, interval, output, key_size }) => {
+            if let (Some(effect), Some(file)) = (&effect, file) {
+                params.extend(file_arg(effect, &file));
+            }
+            let effects = match (effect, effects) {
+                (Some(effect), _) => Some(single_effect_save(effect, params)),
+                (None, Some(path)) => match std::fs::read_to_string(&path) {
+                    Ok(effects) => Some(effects),
+                    Err(e) => {
+                        eprintln!("Error reading {}: {}", path, e);
+                        return;
+                    }
+                },
+                (None, None) => None,
+            };
+            preview(effects, frames, interval, output, key_size)
+        }
+        Args::Animation { action } => match action {
+            AnimationAction::Validate(AnimationFileParam { path }) => validate_animation(path),
+        },
+        Args::Calibrate => calibrate(),
+        Args::Export(BundleParams { path }) => export_bundle(path),
+        Args::Import(BundleParams { path }) => import_bundle(path),
+    }
+}
+
+/// Forwards frames of `comms::STREAM_FRAME_SIZE` bytes from stdin
+fn stream_frames(layer: Option<u32>) {
+    let mut sock = match comms::start_stream(layer) {
+        Some(sock) => sock,
+        None => {
+            eprintln!("Could not start streaming. Is another client streaming, or the layer missing?");
+            return;
+        }
+    };
+    let mut stdin = std::io::stdin().lock();
+    let mut frame = [0u8; comms::STREAM_FRAME_SIZE];
+    while stdin.read_exact(&mut frame).is_ok() {
+        if let Err(e) = sock.write_all(&frame) {
+            eprintln!("Stream closed by the daemon: {}", e);
+            return;
+        }
+    }
+}
+
+const PREVIEW_ROWS: usize = 6;
+const PREVIEW_COLUMNS: usize = 15;
+
+/// An effects save with `effect` as its only layer, on every key
+fn single_effect_save(effect: String, params: Vec<u8>) -> String {
+    let key_mask = vec![true; PREVIEW_ROWS * PREVIEW_COLUMNS];
+    serde_json::json!({ "effects": [{ "name": effect, "args": params, "key_mask": key_mask }] }).to_string()
+}
+
+fn preview(effects: Option<String>, frames: u32, frame_ms: u32, output: Option<String>, key_size: u32) {
+    let rendered = match send_data(comms::DaemonCommand::Preview { effects, frames, frame_ms }) {
+        Some(comms::DaemonResponse::Preview { frames }) => frames,
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            return;
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            return;
+        },
+    };
+    match output {
+        Some(path) => match write_preview(&path, &rendered, frame_ms, key_size) {
+            Ok(files) => println!("Preview written to {}", files.join(", ")),
+            Err(e) => eprintln!("Error writing {}: {}", path, e),
+        },
+        None => draw_preview(&rendered, frame_ms),
+    }
+}
+
+/// Draws the frames with true colour escape codes. On a terminal the frames
+/// are played in place, otherwise they are printed one after the other
+fn draw_preview(frames: &[Vec<u8>], frame_ms: u32) {
+    use std::io::IsTerminal;
+    let mut stdout = std::io::stdout().lock();
+    let animate = stdout.is_terminal();
+    for (idx, frame) in frames.iter().enumerate() {
+        if idx > 0 {
+            if animate {
+                std::thread::sleep(std::time::Duration::from_millis(frame_ms as u64));
+                let _ = write!(stdout, "\x1b[{}A", PREVIEW_ROWS);
+            } else {
+                let _ = writeln!(stdout);
+            }
+        }
+        for row in frame.chunks(PREVIEW_COLUMNS * 3) {
+            for rgb in row.chunks_exact(3) {
+                let _ = write!(stdout, "\x1b[48;2;{};{};{}m    ", rgb[0], rgb[1], rgb[2]);
+            }
+            let _ = writeln!(stdout, "\x1b[0m");
+        }
+        let _ = stdout.flush();
+    }
+}
+
+/// Draws a frame with every key as a square of `key_size` pixels, with a
+/// black gap between keys
+fn preview_image(frame: &[u8], key_size: u32) -> image::RgbaImage {
+    let gap = (key_size / 8).max(1);
+    image::RgbaImage::from_fn(PREVIEW_COLUMNS as u32 * key_size, PREVIEW_ROWS as u32 * key_size, |x, y| {
+        if x % key_size < gap || y % key_size < gap {
+            return image::Rgba([0, 0, 0, 255]);
+        }
+        let pos = (y / key_size) as usize * PREVIEW_COLUMNS + (x / key_size) as usize;
+        match frame.get(pos * 3..pos * 3 + 3) {
+            Some(rgb) => image::Rgba([rgb[0], rgb[1], rgb[2], 255]),
+            None => image::Rgba([0, 0, 0, 255]),
+        }
+    })
+}
+
+/// Writes the frames to an animated GIF, or to PNG files. Returns the files written
+fn write_preview(path: &str, frames: &[Vec<u8>], frame_ms: u32, key_size: u32) -> Result<Vec<String>, String> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+    let path = std::path::Path::new(path);
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("gif") => {
+            let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+            let mut encoder = GifEncoder::new(std::io::BufWriter::new(file));
+            encoder.set_repeat(Repeat::Infinite).map_err(|e| e.to_string())?;
+            let delay = image::Delay::from_numer_denom_ms(frame_ms, 1);
+            let frames = frames.iter().map(|f| image::Frame::from_parts(preview_image(f, key_size), 0, 0, delay));
+            encoder.encode_frames(frames).map_err(|e| e.to_string())?;
+            Ok(vec![path.display().to_string()])
+        },
+        Some("png") if frames.len() == 1 => {
+            preview_image(&frames[0], key_size).save(path).map_err(|e| e.to_string())?;
+            Ok(vec![path.display().to_string()])
+        },
+        Some("png") => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let mut files = vec![];
+            for (idx, frame) in frames.iter().enumerate() {
+                let file = path.with_file_name(format!("{}-{:03}.png", stem, idx));
+                preview_image(frame, key_size).save(&file).map_err(|e| e.to_string())?;
+                files.push(file.display().to_string());
+            }
+            Ok(files)
+        },
+        _ => Err("previews can only be written to .png or .gif files".into()),
+    }
+}
+
+fn show_schedule() {
+    match send_data(comms::DaemonCommand::GetSchedule()) {
+        Some(comms::DaemonResponse::GetSchedule { rules, location, active }) => {
+            if rules.is_empty() {
+                println!("No schedule rules");
+            }
+            for rule in rules {
+                let marker = if active.as_ref() == Some(&rule.name) { " (active)" } else { "" };
+                println!("{}{}: {} {} - {}", rule.name, marker, rule.days, rule.start, rule.end);
+                for action in rule.actions {
+                    match action {
+                        comms::ScheduleAction::Profile { name } => println!("  profile {}", name),
+                        comms::ScheduleAction::Brightness { percent } => println!("  brightness {}%", percent),
+                        comms::ScheduleAction::Logo { state } => println!("  logo mode {}", state),
+                        comms::ScheduleAction::LogoBrightness { percent } => println!("  logo brightness {}%", percent),
+                        comms::ScheduleAction::Effect { name, params } => println!("  effect {} {:?}", name, params),
+                        comms::ScheduleAction::Power { pwr, cpu, gpu } => {
+                            println!("  power mode {}, cpu boost {}, gpu boost {}", pwr, cpu, gpu)
+                        }
+                        comms::ScheduleAction::Fan { rpm: 0 } => println!("  fan auto"),
+                        comms::ScheduleAction::Fan { rpm } => println!("  fan {} RPM", rpm),
+                    }
+                }
+            }
+            if let Some([lat, lon]) = location {
+                println!("Location: {}, {}", lat, lon);
+            }
+            if active.is_none() {
+                println!("No rule is active");
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_schedule_file(path: String) {
+    let file = match std::fs::read_to_string(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            return;
+        }
+    };
+    match serde_json::from_str::<ScheduleFile>(&file) {
+        Ok(schedule) => write_schedule(schedule.rules, schedule.location),
+        Err(e) => eprintln!("{}: {}", path, e),
+    }
+}
+
+fn write_schedule(rules: Vec<comms::ScheduleRule>, location: Option<[f64; 2]>) {
+    let count = rules.len();
+    match send_data(comms::DaemonCommand::SetSchedule { rules, location }) {
+        Some(comms::DaemonResponse::SetSchedule { result: true }) => println!("Schedule set with {} rules", count),
+        Some(comms::DaemonResponse::SetSchedule { result: false }) => eprintln!("Failed to set the schedule"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn show_notifications() {
+    match send_data(comms::DaemonCommand::GetNotifications()) {
+        Some(comms::DaemonResponse::GetNotifications { enabled, do_not_disturb, rules }) => {
+            println!("Notifications: {}", if enabled { "on" } else { "off" });
+            println!("Do not disturb: {}", if do_not_disturb { "on" } else { "off" });
+            if rules.is_empty() {
+                println!("No notification rules");
+            }
+            for rule in rules {
+                let app = rule.app.as_deref().unwrap_or("any app");
+                let urgency = match rule.urgency {
+                    Some(0) => "low urgency",
+                    Some(1) => "normal urgency",
+                    Some(_) => "critical urgency",
+                    None => "any urgency",
+                };
+                let [r, g, b] = rule.colour;
+                println!("{}, {}: {:?} {} {} {} on {}", app, urgency, rule.style, r, g, b, rule.keys);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_notifications(enabled: bool) {
+    match send_data(comms::DaemonCommand::SetNotifications { enabled }) {
+        Some(comms::DaemonResponse::SetNotifications { result: true }) => show_notifications(),
+        Some(comms::DaemonResponse::SetNotifications { result: false }) => eprintln!("Could not change notifications"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_do_not_disturb(enabled: bool) {
+    match send_data(comms::DaemonCommand::SetDoNotDisturb { enabled }) {
+        Some(comms::DaemonResponse::SetDoNotDisturb { result: true }) => {
+            println!("Do not disturb: {}", if enabled { "on" } else { "off" })
+        }
+        Some(comms::DaemonResponse::SetDoNotDisturb { result: false }) => eprintln!("Could not change do not disturb"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_notification_file(path: String) {
+    let file = match std::fs::read_to_string(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            return;
+        }
+    };
+    match serde_json::from_str::<NotificationFile>(&file) {
+        Ok(file) => write_notification_rules(file.rules),
+        Err(e) => eprintln!("{}: {}", path, e),
+    }
+}
+
+fn write_notification_rules(rules: Vec<comms::NotificationRule>) {
+    let count = rules.len();
+    match send_data(comms::DaemonCommand::SetNotificationRules { rules }) {
+        Some(comms::DaemonResponse::SetNotificationRules { result: true }) => println!("{} notification rules set", count),
+        Some(comms::DaemonResponse::SetNotificationRules { result: false }) => eprintln!("Failed to set the notification rules"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn validate_animation(path: String) {
+    let animation = match std::fs::read_to_string(&path) {
+        Ok(animation) => animation,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            return;
+        }
+    };
+    match send_data(comms::DaemonCommand::ValidateAnimation { animation }) {
+        Some(comms::DaemonResponse::ValidateAnimation { error: None }) => println!("{} is valid", path),
+        Some(comms::DaemonResponse::ValidateAnimation { error: Some(error) }) => eprintln!("{}: {}", path, error),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn export_bundle(path: String) {
+    match send_data(comms::DaemonCommand::ExportBundle()) {
+        Some(comms::DaemonResponse::ExportBundle { bundle: Some(bundle) }) => {
+            match std::fs::write(&path, bundle) {
+                Ok(_) => println!("Settings exported to {}", path),
+                Err(e) => eprintln!("Error writing {}: {}", path, e),
+            }
+        },
+        Some(comms::DaemonResponse::ExportBundle { bundle: None }) => eprintln!("Failed to export settings"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn import_bundle(path: String) {
+    let bundle = match std::fs::read_to_string(&path) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            return;
+        }
+    };
+    match send_data(comms::DaemonCommand::ImportBundle { bundle }) {
+        Some(comms::DaemonResponse::ImportBundle { result: true, notes }) => {
+            println!("Settings imported from {}", path);
+            for note in notes {
+                println!("  - {}", note);
+            }
+        },
+        Some(comms::DaemonResponse::ImportBundle { result: false, notes }) => {
+            eprintln!("Failed to import {}: {}", path, notes.join(", "));
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn create_profile(ac: usize, name: String, with_effects: bool) {
+    match send_data(comms::DaemonCommand::CreateProfile { ac, name: name.clone(), with_effects }) {
+        Some(comms::DaemonResponse::CreateProfile { result: true }) => println!("Profile {} saved", name),
+        Some(comms::DaemonResponse::CreateProfile { result: false }) => eprintln!("Failed to save profile {}", name),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn list_profiles() {
+    match send_data(comms::DaemonCommand::ListProfiles()) {
+        Some(comms::DaemonResponse::ListProfiles { profiles, default_profiles }) => {
+            if profiles.is_empty() {
+                println!("No profiles saved");
+            }
+            for name in profiles {
+                let mut defaults: Vec<&str> = vec![];
+                if default_profiles[1].as_ref() == Some(&name) {
+                    defaults.push("ac");
+                }
+                if default_profiles[0].as_ref() == Some(&name) {
+                    defaults.push("bat");
+                }
+                if defaults.is_empty() {
+                    println!("{}", name);
+                } else {
+                    println!("{} (default for {})", name, defaults.join(", "));
+                }
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn rename_profile(name: String, new_name: String) {
+    match send_data(comms::DaemonCommand::RenameProfile { name: name.clone(), new_name: new_name.clone() }) {
+        Some(comms::DaemonResponse::RenameProfile { result: true }) => println!("Profile {} renamed to {}", name, new_name),
+        Some(comms::DaemonResponse::RenameProfile { result: false }) => {
+            eprintln!("Failed to rename profile {}. Does it exist, or is {} already taken?", name, new_name)
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn delete_profile(name: String) {
+    match send_data(comms::DaemonCommand::DeleteProfile { name: name.clone() }) {
+        Some(comms::DaemonResponse::DeleteProfile { result: true }) => println!("Profile {} deleted", name),
+        Some(comms::DaemonResponse::DeleteProfile { result: false }) => eprintln!("No profile named {}", name),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn activate_profile(ac: usize, name: String) {
+    match send_data(comms::DaemonCommand::ActivateProfile { ac, name: name.clone() }) {
+        Some(comms::DaemonResponse::ActivateProfile { result: true }) => println!("Profile {} activated", name),
+        Some(comms::DaemonResponse::ActivateProfile { result: false }) => eprintln!("No profile named {}", name),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_default_profile(ac: usize, name: Option<String>) {
+    match send_data(comms::DaemonCommand::SetDefaultProfile { ac, name: name.clone() }) {
+        Some(comms::DaemonResponse::SetDefaultProfile { result: true }) => match name {
+            Some(name) => println!("Default profile set to {}", name),
+            None => println!("Default profile removed"),
+        },
+        Some(comms::DaemonResponse::SetDefaultProfile { result: false }) => eprintln!("No such profile"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+/// Turns a list of keys like `0-14,30` into a key mask, exits on invalid input
+fn parse_keys(keys: &str) -> Vec<bool> {
+    const KEY_COUNT: usize = 90;
+    let mut mask = vec![false; KEY_COUNT];
+    if keys == "all" {
+        return vec![true; KEY_COUNT];
+    }
+    for part in keys.split(',') {
+        let range = match part.split_once('-') {
+            Some((start, end)) => start.trim().parse::<usize>().ok().zip(end.trim().parse::<usize>().ok()),
+            None => part.trim().parse::<usize>().ok().map(|key| (key, key)),
+        };
+        match range {
+            Some((start, end)) if start <= end && end < KEY_COUNT => {
+                mask[start..=end].iter_mut().for_each(|k| *k = true)
+            }
+            _ => Cli::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("Invalid keys {}, keys go from 0 to {}", part, KEY_COUNT - 1),
+                )
+                .exit(),
+        }
+    }
+    mask
+}
+
+/// Formats a key mask the way `parse_keys` reads it
+fn format_keys(mask: &[bool]) -> String {
+    if mask.iter().all(|k| *k) {
+        return String::from("all");
+    }
+    let mut ranges: Vec<String> = vec![];
+    let mut start: Option<usize> = None;
+    for (idx, on) in mask.iter().chain([false].iter()).enumerate() {
+        match (start, *on) {
+            (None, true) => start = Some(idx),
+            (Some(s), false) => {
+                if s == idx - 1 {
+                    ranges.push(s.to_string());
+                } else {
+                    ranges.push(format!("{}-{}", s, idx - 1));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if ranges.is_empty() {
+        return String::from("none");
+    }
+    ranges.join(",")
+}
+
+fn list_layers() {
+    match send_data(comms::DaemonCommand::ListLayers()) {
+        Some(comms::DaemonResponse::ListLayers { layers }) => {
+            if layers.is_empty() {
+                println!("No layers");
             }
+            for (position, layer) in layers.iter().enumerate() {
+                println!(
+                    "{}: layer {} {} {:?}, {:?} {}%{}",
+                    position,
+                    layer.id,
+                    layer.effect,
+                    layer.params,
+                    layer.blend,
+                    layer.opacity,
+                    if layer.visible { "" } else { " (hidden)" }
+                );
+                println!("    keys: {}", format_keys(&layer.key_mask));
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn add_layer(name: String, params: Vec<u8>, position: Option<usize>, key_mask: Option<Vec<bool>>) {
+    match send_data(comms::DaemonCommand::AddLayer { position, name, params, key_mask }) {
+        Some(comms::DaemonResponse::AddLayer { id: Some(id) }) => println!("Added layer {}", id),
+        Some(comms::DaemonResponse::AddLayer { id: None }) => eprintln!("Failed to add layer. Is the position valid?"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn replace_layer(id: u32, name: String, params: Vec<u8>) {
+    match send_data(comms::DaemonCommand::ReplaceLayer { id, name, params }) {
+        Some(comms::DaemonResponse::ReplaceLayer { result: true }) => println!("Layer {} replaced", id),
+        Some(comms::DaemonResponse::ReplaceLayer { result: false }) => eprintln!("No layer {}", id),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn move_layer(id: u32, position: usize) {
+    match send_data(comms::DaemonCommand::MoveLayer { id, position }) {
+        Some(comms::DaemonResponse::MoveLayer { result: true }) => println!("Layer {} moved to {}", id, position),
+        Some(comms::DaemonResponse::MoveLayer { result: false }) => {
+            eprintln!("Failed to move layer {}. Do the layer and position exist?", id)
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn remove_layer(id: u32) {
+    match send_data(comms::DaemonCommand::RemoveLayer { id }) {
+        Some(comms::DaemonResponse::RemoveLayer { result: true }) => println!("Layer {} removed", id),
+        Some(comms::DaemonResponse::RemoveLayer { result: false }) => eprintln!("No layer {}", id),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_layer_visible(id: u32, visible: bool) {
+    match send_data(comms::DaemonCommand::SetLayerVisible { id, visible }) {
+        Some(comms::DaemonResponse::SetLayerVisible { result: true }) => {
+            println!("Layer {} {}", id, if visible { "shown" } else { "hidden" })
         },
+        Some(comms::DaemonResponse::SetLayerVisible { result: false }) => eprintln!("No layer {}", id),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_layer_mask(id: u32, key_mask: Vec<bool>) {
+    match send_data(comms::DaemonCommand::SetLayerMask { id, key_mask }) {
+        Some(comms::DaemonResponse::SetLayerMask { result: true }) => println!("Layer {} keys set", id),
+        Some(comms::DaemonResponse::SetLayerMask { result: false }) => eprintln!("No layer {}", id),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_layer_blend(id: u32, blend: comms::BlendMode, opacity: u8) {
+    match send_data(comms::DaemonCommand::SetLayerBlend { id, blend, opacity }) {
+        Some(comms::DaemonResponse::SetLayerBlend { result: true }) => println!("Layer {} blend set", id),
+        Some(comms::DaemonResponse::SetLayerBlend { result: false }) => eprintln!("No layer {}", id),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
     }
 }
 
@@ -504,8 +1866,8 @@ fn bho_toggle_off() {
     );
 }
 
-fn send_standard_effect(name: String, params: Vec<u8>) {
-    match send_data(comms::DaemonCommand::SetStandardEffect { name, params }) {
+fn send_standard_effect(effect: comms::StandardEffect) {
+    match send_data(comms::DaemonCommand::SetStandardEffect { effect }) {
         Some(comms::DaemonResponse::SetStandardEffect { result }) => {
             if result {
                 println!("Effect set OK!");
@@ -518,6 +1880,37 @@ fn send_standard_effect(name: String, params: Vec<u8>) {
     }
 }
 
+fn read_standard_effect() {
+    match send_data(comms::DaemonCommand::GetStandardEffect()) {
+        Some(comms::DaemonResponse::GetStandardEffect { effect, active }) => {
+            let colour = |[r, g, b]: [u8; 3]| format!("{} {} {}", r, g, b);
+            let colours = |colours: comms::EffectColours| match colours {
+                comms::EffectColours::Single(c) => colour(c),
+                comms::EffectColours::Dual(c1, c2) => format!("{} and {}", colour(c1), colour(c2)),
+                comms::EffectColours::Random => "random colours".into(),
+            };
+            let details = match effect {
+                comms::StandardEffect::Wave { direction, speed } => {
+                    let direction = match direction {
+                        comms::WaveDirection::LeftToRight => "left to right",
+                        comms::WaveDirection::RightToLeft => "right to left",
+                    };
+                    format!(" {}, speed {}", direction, speed)
+                }
+                comms::StandardEffect::Reactive { speed, colour: c } => format!(" {}, speed {}", colour(c), speed),
+                comms::StandardEffect::Breathing { colours: c } => format!(" {}", colours(c)),
+                comms::StandardEffect::Static { colour: c } => format!(" {}", colour(c)),
+                comms::StandardEffect::Starlight { speed, colours: c } => format!(" {}, speed {}", colours(c), speed),
+                comms::StandardEffect::Off | comms::StandardEffect::Spectrum => String::new(),
+            };
+            let active = if active { "" } else { " (custom effects are drawn over it)" };
+            println!("Standard effect: {}{}{}", effect.name(), details, active);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn send_effect(name: String, params: Vec<u8>) {
     match send_data(comms::DaemonCommand::SetEffect { name, params }) {
         Some(comms::DaemonResponse::SetEffect { result }) => {
@@ -532,9 +1925,91 @@ fn send_effect(name: String, params: Vec<u8>) {
     }
 }
 
+/// Encodes a file parameter. The path is made absolute, as the daemon
+/// doesn't run in the current directory
+/// Encodes the `--file` argument of an effect: the contents of the file for
+/// effects that keep them, the path for the others
+fn file_arg(effect: &str, path: &str) -> Vec<u8> {
+    let keeps_contents = match send_data(comms::DaemonCommand::ListEffects()) {
+        Some(comms::DaemonResponse::ListEffects { effects }) => effects
+            .iter()
+            .filter(|e| e.id == effect)
+            .flat_map(|e| e.params.iter())
+            .any(|p| matches!(p.kind, comms::EffectParamKind::FileContents)),
+        _ => false,
+    };
+    if keeps_contents {
+        file_contents_param(path)
+    } else {
+        file_param(path)
+    }
+}
+
+/// Contents of a text file for a `FileContents` effect parameter
+fn file_contents_param(path: &str) -> Vec<u8> {
+    match std::fs::read_to_string(path) {
+        Ok(text) if text.len() <= u16::MAX as usize => comms::encode_path(&text),
+        Ok(_) => Cli::command()
+            .error(ErrorKind::ValueValidation, format!("{} is too big to embed", path))
+            .exit(),
+        Err(e) => Cli::command()
+            .error(ErrorKind::ValueValidation, format!("{}: {}", path, e))
+            .exit(),
+    }
+}
+
+fn file_param(path: &str) -> Vec<u8> {
+    match std::fs::canonicalize(path) {
+        Ok(path) => comms::encode_path(&path.to_string_lossy()),
+        Err(e) => Cli::command()
+            .error(ErrorKind::ValueValidation, format!("{}: {}", path, e))
+            .exit(),
+    }
+}
+
+fn list_effects() {
+    match send_data(comms::DaemonCommand::ListEffects()) {
+        Some(comms::DaemonResponse::ListEffects { effects }) => {
+            for effect in effects {
+                println!("{} ({})", effect.id, effect.display_name);
+                for param in effect.params {
+                    let desc = match param.kind {
+                        comms::EffectParamKind::Colour => String::from("colour: red green blue (0-255)"),
+                        comms::EffectParamKind::Range { min, max, default } => {
+                            format!("{}-{}, default {}", min, max, default)
+                        },
+                        comms::EffectParamKind::Choice { options, default } => {
+                            let opts: Vec<String> = options
+                                .iter()
+                                .enumerate()
+                                .map(|(i, o)| format!("{}={}", i, o))
+                                .collect();
+                            format!("one of {}, default {}", opts.join(", "), default)
+                        },
+                        comms::EffectParamKind::File => String::from("file, passed with --file"),
+                        comms::EffectParamKind::FileContents => String::from("file, passed with --file and sent with its contents"),
+                        comms::EffectParamKind::KeyColours => {
+                            String::from("colour of every key: 270 values, red green blue for each key, row by row")
+                        },
+                    };
+                    println!("    {}: {}", param.name, desc);
+                }
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
     match comms::bind() {
-        Some(socket) => comms::send_to_daemon(opt, socket),
+        Some(socket) => match comms::send_to_daemon(opt, socket) {
+            Some(comms::DaemonResponse::Error { message }) => {
+                eprintln!("Daemon error: {}", message);
+                None
+            },
+            res => res,
+        },
         None => {
             eprintln!("Error. Cannot bind to socket");
             None
@@ -564,6 +2039,7 @@ fn read_logo_mode(ac: usize) {
                 0 => "Off",
                 1 => "On",
                 2 => "Breathing",
+                3 => "Spectrum",
                 _ => "Unknown",
             };
             println!("Current logo setting: {}", logo_state_desc);
@@ -675,6 +2151,245 @@ fn read_brightness(ac: usize) {
     }
 }
 
+fn read_animation_fps(ac: usize) {
+    match send_data(comms::DaemonCommand::GetAnimationFps { ac }) {
+        Some(comms::DaemonResponse::GetAnimationFps { fps }) => {
+            println!("Current animation frame rate: {} fps", fps);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_animation_fps(ac: usize, fps: u8) {
+    match send_data(comms::DaemonCommand::SetAnimationFps { ac, fps }) {
+        Some(comms::DaemonResponse::SetAnimationFps { result: true }) => read_animation_fps(ac),
+        Some(comms::DaemonResponse::SetAnimationFps { result: false }) => eprintln!("Invalid frame rate {}", fps),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_openrgb() {
+    match send_data(comms::DaemonCommand::GetOpenRgb()) {
+        Some(comms::DaemonResponse::GetOpenRgb { port: Some(port) }) => {
+            println!("OpenRGB server: on, port {}", port);
+        },
+        Some(comms::DaemonResponse::GetOpenRgb { port: None }) => println!("OpenRGB server: off"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_openrgb(port: Option<u16>) {
+    match send_data(comms::DaemonCommand::SetOpenRgb { port }) {
+        Some(comms::DaemonResponse::SetOpenRgb { result: true }) => read_openrgb(),
+        Some(comms::DaemonResponse::SetOpenRgb { result: false }) => eprintln!("Could not change the OpenRGB server"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn get_colour_correction() -> Option<comms::ColourCorrection> {
+    match send_data(comms::DaemonCommand::GetColourCorrection()) {
+        Some(comms::DaemonResponse::GetColourCorrection { correction }) => Some(correction),
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            None
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            None
+        },
+    }
+}
+
+fn describe_colour_correction(correction: &comms::ColourCorrection) -> String {
+    let [r, g, b] = correction.gain;
+    let [wr, wg, wb] = correction.white_point;
+    format!("gamma {:.2}, gain {}% {}% {}%, white point {} {} {}", correction.gamma, r, g, b, wr, wg, wb)
+}
+
+fn read_colour_correction() {
+    if let Some(correction) = get_colour_correction() {
+        println!("Colour correction: {}", describe_colour_correction(&correction));
+    }
+}
+
+/// Sets the colour correction, returning false if it was refused
+fn set_colour_correction(correction: comms::ColourCorrection) -> bool {
+    match send_data(comms::DaemonCommand::SetColourCorrection { correction }) {
+        Some(comms::DaemonResponse::SetColourCorrection { result }) => result,
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            false
+        },
+        None => false,
+    }
+}
+
+fn write_colour_correction(correction: comms::ColourCorrection) {
+    if set_colour_correction(correction) {
+        read_colour_correction();
+    } else {
+        eprintln!(
+            "Could not set the colour correction. Gamma goes from {} to {} and gains up to {}%",
+            comms::ColourCorrection::MIN_GAMMA,
+            comms::ColourCorrection::MAX_GAMMA,
+            comms::ColourCorrection::MAX_GAIN
+        );
+    }
+}
+
+/// Shows a test pattern on the keyboard, with the colour of every key given
+/// by its row and column
+fn show_pattern(sock: &mut std::os::unix::net::UnixStream, colour: impl Fn(usize, usize) -> [u8; 3]) -> bool {
+    let mut frame = Vec::with_capacity(comms::STREAM_FRAME_SIZE);
+    for row in 0..PREVIEW_ROWS {
+        for col in 0..PREVIEW_COLUMNS {
+            frame.extend_from_slice(&colour(row, col));
+        }
+    }
+    sock.write_all(&frame).is_ok()
+}
+
+fn parse_triple(answer: &str) -> Option<[u8; 3]> {
+    let values: Vec<u8> = answer.split_whitespace().map(|v| v.parse().ok()).collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+/// Asks for a value until the current one is kept with Enter, showing every
+/// answer on the keyboard. Returns false if the user quit
+fn calibration_step(
+    question: &str,
+    correction: &mut comms::ColourCorrection,
+    current: fn(&comms::ColourCorrection) -> String,
+    update: fn(&str, &mut comms::ColourCorrection) -> bool,
+) -> bool {
+    loop {
+        print!("{} [{}]: ", question, current(correction));
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            return false;
+        }
+        match line.trim() {
+            "" => return true,
+            "q" => return false,
+            answer => {
+                let mut candidate = *correction;
+                if update(answer, &mut candidate) && candidate.is_valid() && set_colour_correction(candidate) {
+                    *correction = candidate;
+                } else {
+                    eprintln!("Invalid value {:?}", answer);
+                }
+            }
+        }
+    }
+}
+
+/// Walks through test patterns for the white point, the balance of the
+/// channels and the gamma curve. The patterns are streamed, so the layers
+/// come back afterwards
+fn calibrate() {
+    let original = match get_colour_correction() {
+        Some(correction) => correction,
+        None => return,
+    };
+    let mut sock = match comms::start_stream(None) {
+        Some(sock) => sock,
+        None => {
+            eprintln!("Could not show test patterns. Is another client streaming?");
+            return;
+        }
+    };
+    println!("Answer with new values to see them on the keyboard, Enter to keep the current ones or q to quit without saving.");
+    let mut correction = original;
+
+    println!("\n1/3: The keyboard is white. Change the white point until it looks neutral, like white on your screen.");
+    let mut done = show_pattern(&mut sock, |_, _| [255, 255, 255])
+        && calibration_step(
+            "White point, red green blue (0-255)",
+            &mut correction,
+            |c| format!("{} {} {}", c.white_point[0], c.white_point[1], c.white_point[2]),
+            |answer, c| parse_triple(answer).map(|white| c.white_point = white).is_some(),
+        );
+
+    if done {
+        println!("\n2/3: The rows are red, green and blue at the same level. Change the gains until they look as bright as each other.");
+        done = show_pattern(&mut sock, |row, _| match row / 2 {
+            0 => [128, 0, 0],
+            1 => [0, 128, 0],
+            _ => [0, 0, 128],
+        }) && calibration_step(
+            "Gain of red green blue in %",
+            &mut correction,
+            |c| format!("{} {} {}", c.gain[0], c.gain[1], c.gain[2]),
+            |answer, c| parse_triple(answer).map(|gain| c.gain = gain).is_some(),
+        );
+    }
+
+    if done {
+        println!("\n3/3: The keys go from black on the left to white on the right. Change the gamma until the second key is lit and every key is a bit brighter than the one before.");
+        done = show_pattern(&mut sock, |_, col| {
+            let level = (col * 255 / (PREVIEW_COLUMNS - 1)) as u8;
+            [level, level, level]
+        }) && calibration_step(
+            "Gamma, lower lifts dark colours",
+            &mut correction,
+            |c| format!("{:.2}", c.gamma),
+            |answer, c| answer.parse().map(|gamma| c.gamma = gamma).is_ok(),
+        );
+    }
+
+    drop(sock);
+    if done {
+        println!("\nColour correction saved: {}", describe_colour_correction(&correction));
+    } else {
+        println!("\nCalibration stopped, keeping the previous colour correction");
+        if correction != original && !set_colour_correction(original) {
+            eprintln!("Could not restore the previous colour correction");
+        }
+    }
+}
+
+fn read_transitions() {
+    match send_data(comms::DaemonCommand::GetTransitions()) {
+        Some(comms::DaemonResponse::GetTransitions { ms: 0 }) => println!("Transitions: off"),
+        Some(comms::DaemonResponse::GetTransitions { ms }) => println!("Transitions: {} ms", ms),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_transitions(ms: u32) {
+    match send_data(comms::DaemonCommand::SetTransitions { ms }) {
+        Some(comms::DaemonResponse::SetTransitions { result: true }) => read_transitions(),
+        Some(comms::DaemonResponse::SetTransitions { result: false }) => eprintln!("Transitions can be up to 5000 ms"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_key_events() {
+    match send_data(comms::DaemonCommand::GetKeyEvents()) {
+        Some(comms::DaemonResponse::GetKeyEvents { enabled }) => {
+            println!("Key events: {}", if enabled { "on" } else { "off" });
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_key_events(enabled: bool) {
+    match send_data(comms::DaemonCommand::SetKeyEvents { enabled }) {
+        Some(comms::DaemonResponse::SetKeyEvents { result: true }) => read_key_events(),
+        Some(comms::DaemonResponse::SetKeyEvents { result: false }) => eprintln!("Could not change key events"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn read_sync() {
     match send_data(comms::DaemonCommand::GetSync()) {
         Some(comms::DaemonResponse::GetSync { sync }) => {
@@ -701,11 +2416,126 @@ fn write_fan_speed(ac: usize, x: i32) {
 
 fn write_logo_mode(ac: usize, x: u8) {
     match send_data(comms::DaemonCommand::SetLogoLedState { ac, logo_state: x }) {
+        Some(comms::DaemonResponse::SetLogoLedState { result: false }) => {
+            eprintln!("Could not set logo mode {}, is it supported by this laptop?", x)
+        }
         Some(_) => read_logo_mode(ac),
         None => eprintln!("Unknown error!"),
     }
 }
 
+fn get_lock_indicators() -> Option<comms::LockIndicators> {
+    match send_data(comms::DaemonCommand::GetLockIndicators()) {
+        Some(comms::DaemonResponse::GetLockIndicators { indicators }) => Some(indicators),
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            None
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            None
+        },
+    }
+}
+
+fn read_lock_indicators() {
+    if let Some(indicators) = get_lock_indicators() {
+        let locks: Vec<&str> = [(indicators.caps, "caps"), (indicators.num, "num"), (indicators.scroll, "scroll")]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect();
+        if locks.is_empty() {
+            println!("Lock indicators: off");
+        } else {
+            let [r, g, b] = indicators.colour;
+            println!("Lock indicators: {} in {} {} {}", locks.join(", "), r, g, b);
+        }
+    }
+}
+
+fn write_lock_indicators(indicators: comms::LockIndicators) {
+    match send_data(comms::DaemonCommand::SetLockIndicators { indicators }) {
+        Some(comms::DaemonResponse::SetLockIndicators { result: true }) => read_lock_indicators(),
+        Some(comms::DaemonResponse::SetLockIndicators { result: false }) => eprintln!("Could not set the lock indicators"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_low_battery() {
+    match send_data(comms::DaemonCommand::GetLowBattery()) {
+        Some(comms::DaemonResponse::GetLowBattery { threshold, actions, active }) => {
+            if threshold <= 0.0 {
+                println!("Low battery mode: off");
+                return;
+            }
+            let lights = match actions.brightness {
+                0 => "lights off".to_string(),
+                percent => format!("brightness up to {}%", percent),
+            };
+            let mut changes = vec![lights, "animations paused".into()];
+            if let Some((pwr, cpu, gpu)) = actions.power {
+                changes.push(format!("power {} {} {}", pwr, cpu, gpu));
+            }
+            if let Some(rpm) = actions.fan_rpm {
+                changes.push(if rpm == 0 { "fan auto".into() } else { format!("fan {} RPM", rpm) });
+            }
+            let active = if active { " (active)" } else { "" };
+            println!("Low battery mode: below {}%, {}{}", threshold, changes.join(", "), active);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_low_battery(threshold: f64, actions: comms::LowBattery) {
+    match send_data(comms::DaemonCommand::SetLowBattery { threshold, actions }) {
+        Some(comms::DaemonResponse::SetLowBattery { result: true }) => read_low_battery(),
+        Some(comms::DaemonResponse::SetLowBattery { result: false }) => eprintln!("Could not set low battery mode"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_logo_brightness(ac: usize) {
+    match send_data(comms::DaemonCommand::GetLogoBrightness { ac }) {
+        Some(comms::DaemonResponse::GetLogoBrightness { brightness }) => {
+            println!("Current logo brightness: {}", brightness);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_logo_brightness(ac: usize, val: u8) {
+    match send_data(comms::DaemonCommand::SetLogoBrightness { ac, val }) {
+        Some(comms::DaemonResponse::SetLogoBrightness { result: true }) => read_logo_brightness(ac),
+        Some(comms::DaemonResponse::SetLogoBrightness { result: false }) => eprintln!("Invalid logo brightness {}, expected 0-100, or the laptop has no logo", val),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_logo_idle(ac: usize) {
+    match send_data(comms::DaemonCommand::GetLogoOnIdle { ac }) {
+        Some(comms::DaemonResponse::GetLogoOnIdle { on }) => {
+            println!("Logo on idle: {}", if on { "on" } else { "off" });
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_logo_idle(ac: usize, on: bool) {
+    match send_data(comms::DaemonCommand::SetLogoOnIdle { ac, on }) {
+        Some(comms::DaemonResponse::SetLogoOnIdle { result: true }) => read_logo_idle(ac),
+        Some(comms::DaemonResponse::SetLogoOnIdle { result: false }) => eprintln!("Could not change the logo on idle"),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn write_sync(sync: bool) {
     match send_data(comms::DaemonCommand::SetSync { sync }) {
         Some(_) => read_sync(),
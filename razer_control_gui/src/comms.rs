@@ -1,3 +1,4 @@
+use bincode::Options;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
@@ -5,6 +6,249 @@ use std::os::unix::net::{UnixListener, UnixStream};
 /// Razer laptop control socket path
 pub const SOCKET_PATH: &str = "/tmp/razercontrol-socket";
 
+/// Largest message accepted in either direction
+const MAX_MESSAGE_SIZE: u64 = 1024 * 1024;
+
+/// Port OpenRGB clients connect to unless told otherwise
+#[allow(dead_code)]
+pub const OPENRGB_DEFAULT_PORT: u16 = 6742;
+
+/// Size of a streamed frame: red, green and blue of the 90 keys, row by row
+#[allow(dead_code)]
+pub const STREAM_FRAME_SIZE: usize = 90 * 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Type of a custom effect parameter, and how many bytes it takes in the
+/// effect's `params`
+pub enum EffectParamKind {
+    Colour, // 3 bytes: red, green, blue
+    Range { min: u8, max: u8, default: u8 }, // 1 byte, speeds, durations...
+    Choice { options: Vec<String>, default: u8 }, // 1 byte, index of the option
+    File, // Path as encoded by `encode_path`
+    KeyColours, // 3 bytes for each of the 90 keys, row by row
+    FileContents, // Text of a file read by the client, encoded like a path
+}
+
+/// Encodes a path for a `File` effect parameter: its length as 2 bytes,
+/// little endian, then the UTF-8 bytes
+#[allow(dead_code)]
+pub fn encode_path(path: &str) -> Vec<u8> {
+    let bytes = path.as_bytes();
+    let len = bytes.len().min(u16::MAX as usize);
+    let mut encoded = (len as u16).to_le_bytes().to_vec();
+    encoded.extend_from_slice(&bytes[..len]);
+    encoded
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EffectParam {
+    pub name: String,
+    pub kind: EffectParamKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Describes a custom effect so clients can build their UI from it
+pub struct EffectInfo {
+    pub id: String, // Used in `SetEffect`
+    pub display_name: String,
+    pub params: Vec<EffectParam>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// How a layer is combined with the layers below it
+pub enum BlendMode {
+    Replace,  // Draws over the layers below, ignoring opacity
+    Alpha,    // Mixes with the layers below by opacity
+    Add,      // Adds its colour to the layers below, for glows
+    Multiply, // Darkens the layers below, for dimming overlays
+    Max,      // Keeps the brightest of each channel
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Correction of custom effect colours before they are sent to the LEDs, so
+/// they look like they do on screen. The gamma curve is applied first
+pub struct ColourCorrection {
+    pub gamma: f32, // Below 1 lifts dark colours
+    pub gain: [u8; 3], // Red, green and blue, 0-200 %
+    pub white_point: [u8; 3], // Colour white is shown as
+}
+
+#[allow(dead_code)]
+impl ColourCorrection {
+    pub const MIN_GAMMA: f32 = 0.2;
+    pub const MAX_GAMMA: f32 = 5.0;
+    pub const MAX_GAIN: u8 = 200;
+
+    pub fn is_valid(&self) -> bool {
+        (Self::MIN_GAMMA..=Self::MAX_GAMMA).contains(&self.gamma) && self.gain.iter().all(|g| *g <= Self::MAX_GAIN)
+    }
+}
+
+impl Default for ColourCorrection {
+    fn default() -> ColourCorrection {
+        ColourCorrection {
+            gamma: 1.0,
+            gain: [100; 3],
+            white_point: [255; 3],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Lock keys lit in `colour` while their lock is on, over every custom effect
+pub struct LockIndicators {
+    pub caps: bool,
+    pub num: bool,
+    pub scroll: bool,
+    pub colour: [u8; 3],
+}
+
+impl Default for LockIndicators {
+    fn default() -> LockIndicators {
+        LockIndicators {
+            caps: false,
+            num: false,
+            scroll: false,
+            colour: [255; 3],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+/// What changes while the laptop runs on a low battery. Animations of custom
+/// effects are paused too
+pub struct LowBattery {
+    pub brightness: u8, // Highest keyboard and logo brightness in %, 0 turns them off
+    #[serde(default)]
+    pub power: Option<(u8, u8, u8)>, // Power mode, cpu boost and gpu boost, as in `SetPowerMode`
+    #[serde(default)]
+    pub fan_rpm: Option<i32>, // 0 for auto
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WaveDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Colours of the standard effects that can show one or two colours
+pub enum EffectColours {
+    Single([u8; 3]),
+    Dual([u8; 3], [u8; 3]),
+    Random,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Effects built into the keyboard controller
+pub enum StandardEffect {
+    Off,
+    Wave { direction: WaveDirection, speed: u8 },
+    Reactive { speed: u8, colour: [u8; 3] },
+    Breathing { colours: EffectColours },
+    Spectrum,
+    Static { colour: [u8; 3] },
+    Starlight { speed: u8, colours: EffectColours },
+}
+
+#[allow(dead_code)]
+impl StandardEffect {
+    pub const DEFAULT_WAVE_SPEED: u8 = 0x28;
+    pub const WAVE_SPEEDS: std::ops::RangeInclusive<u8> = 1..=255;
+    pub const REACTIVE_SPEEDS: std::ops::RangeInclusive<u8> = 1..=4;
+    pub const STARLIGHT_SPEEDS: std::ops::RangeInclusive<u8> = 1..=3;
+
+    /// Checks the values the firmware accepts
+    pub fn validate(&self) -> Result<(), String> {
+        let (speed, range) = match self {
+            StandardEffect::Wave { speed, .. } => (*speed, Self::WAVE_SPEEDS),
+            StandardEffect::Reactive { speed, .. } => (*speed, Self::REACTIVE_SPEEDS),
+            StandardEffect::Starlight { speed, .. } => (*speed, Self::STARLIGHT_SPEEDS),
+            _ => return Ok(()),
+        };
+        if !range.contains(&speed) {
+            return Err(format!("{} speed {} is out of range ({}-{})", self.name(), speed, range.start(), range.end()));
+        }
+        Ok(())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StandardEffect::Off => "off",
+            StandardEffect::Wave { .. } => "wave",
+            StandardEffect::Reactive { .. } => "reactive",
+            StandardEffect::Breathing { .. } => "breathing",
+            StandardEffect::Spectrum => "spectrum",
+            StandardEffect::Static { .. } => "static",
+            StandardEffect::Starlight { .. } => "starlight",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashStyle {
+    #[default]
+    Flash, // Blinks on and off
+    Pulse, // Fades in and out
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// How the keyboard shows the desktop notifications that match
+pub struct NotificationRule {
+    #[serde(default)]
+    pub app: Option<String>, // App name, any app if None
+    #[serde(default)]
+    pub urgency: Option<u8>, // 0 low, 1 normal, 2 critical, any urgency if None
+    pub colour: [u8; 3],
+    #[serde(default)]
+    pub style: FlashStyle,
+    #[serde(default = "all_keys")]
+    pub keys: String, // As in keyframe animations, like "all", "row0", "fkeys" or "30-44"
+}
+
+fn all_keys() -> String {
+    "all".into()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Something a schedule rule changes while it is active
+pub enum ScheduleAction {
+    Profile { name: String }, // Power settings of the profile, and its effects if it has any
+    Brightness { percent: u8 },
+    Logo { state: u8 }, // As in `SetLogoLedState`
+    LogoBrightness { percent: u8 },
+    Effect { name: String, params: Vec<u8> }, // Custom effect on every key, as in `SetEffect`
+    Power { pwr: u8, cpu: u8, gpu: u8 },
+    Fan { rpm: i32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Settings used on some days between two times of day
+pub struct ScheduleRule {
+    pub name: String,
+    pub days: String, // Like "mon-fri", "sat,sun" or "all"
+    pub start: String, // "HH:MM", "sunrise" or "sunset", with an offset in minutes like "sunset-30"
+    pub end: String, // Before the start for rules that go past midnight
+    pub actions: Vec<ScheduleAction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Describes one layer of the custom effect stack, bottom layer first
+pub struct LayerInfo {
+    pub id: u32, // Stays the same while the layer exists
+    pub effect: String, // Effect ID, as in `SetEffect`
+    pub params: Vec<u8>,
+    pub visible: bool,
+    pub key_mask: Vec<bool>, // One entry per key, true if the layer is drawn on it
+    pub blend: BlendMode,
+    pub opacity: u8, // 0-100 %
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 /// Represents data sent TO the daemon
 pub enum DaemonCommand {
@@ -14,11 +258,11 @@ pub enum DaemonCommand {
     GetPwrLevel { ac: usize },                 // Get (Power mode)
     GetCPUBoost { ac: usize },                 // Get (CPU boost)
     GetGPUBoost { ac: usize },                 // Get (GPU boost)
-    SetLogoLedState{ ac:usize, logo_state: u8 },
+    SetLogoLedState{ ac:usize, logo_state: u8 }, // 0 off, 1 static, 2 breathing, 3 spectrum if the logo can show it
     GetLogoLedState { ac: usize },
-    GetKeyboardRGB { layer: i32 }, // Layer ID
+    GetKeyboardRGB { layer: i32 }, // Layer ID, or -1 for the rendered keyboard
     SetEffect { name: String, params: Vec<u8> }, // Set keyboard colour
-    SetStandardEffect { name: String, params: Vec<u8> }, // Set keyboard colour
+    SetStandardEffect { effect: StandardEffect },
     SetBrightness { ac:usize, val: u8 },
     SetIdle {ac: usize, val: u32 },
     GetBrightness { ac: usize },
@@ -26,7 +270,60 @@ pub enum DaemonCommand {
     GetSync (),
     SetBatteryHealthOptimizer { is_on: bool, threshold: u8 },
     GetBatteryHealthOptimizer (),
-    GetDeviceName 
+    GetDeviceName,
+    CreateProfile { ac: usize, name: String, with_effects: bool }, // Save current settings as a profile
+    ListProfiles (),
+    RenameProfile { name: String, new_name: String },
+    DeleteProfile { name: String },
+    ActivateProfile { ac: usize, name: String },
+    SetDefaultProfile { ac: usize, name: Option<String> }, // None removes the mapping
+    ExportBundle (),
+    ImportBundle { bundle: String }, // JSON contents of a bundle file
+    ListEffects (),
+    ListLayers (),
+    AddLayer { position: Option<usize>, name: String, params: Vec<u8>, key_mask: Option<Vec<bool>> }, // None adds on top / on every key
+    ReplaceLayer { id: u32, name: String, params: Vec<u8> }, // Keeps the mask and visibility
+    MoveLayer { id: u32, position: usize },
+    RemoveLayer { id: u32 },
+    SetLayerVisible { id: u32, visible: bool },
+    SetLayerMask { id: u32, key_mask: Vec<bool> },
+    SetLayerBlend { id: u32, blend: BlendMode, opacity: u8 }, // Opacity 0-100 %
+    SetAnimationFps { ac: usize, fps: u8 },
+    GetAnimationFps { ac: usize },
+    SetKeyEvents { enabled: bool }, // Feed key presses to custom effects
+    GetKeyEvents(),
+    ValidateAnimation { animation: String }, // Contents of a keyframe animation file
+    // Keeps the connection open for frames of `STREAM_FRAME_SIZE` bytes, drawn
+    // over the keyboard or instead of a layer until the client disconnects
+    StartStream { layer: Option<u32> },
+    SetOpenRgb { port: Option<u16> }, // None stops the OpenRGB SDK server
+    GetOpenRgb(),
+    // Renders an effects save, or the current layers when None, without
+    // showing it on the keyboard
+    Preview { effects: Option<String>, frames: u32, frame_ms: u32 },
+    SetColourCorrection { correction: ColourCorrection }, // For the current laptop model
+    GetColourCorrection(),
+    SetTransitions { ms: u32 }, // Crossfades between effects and brightness fades, 0 to switch instantly
+    GetTransitions(),
+    // Rules are checked in order, the first one that matches is active.
+    // Location is latitude and longitude, needed for sunrise and sunset
+    SetSchedule { rules: Vec<ScheduleRule>, location: Option<[f64; 2]> },
+    GetSchedule(),
+    SetLogoBrightness { ac: usize, val: u8 }, // In %, separate from the keyboard brightness
+    GetLogoBrightness { ac: usize },
+    SetLogoOnIdle { ac: usize, on: bool }, // Keep the logo on when the keyboard turns off on idle or screensaver
+    GetLogoOnIdle { ac: usize },
+    SetLockIndicators { indicators: LockIndicators },
+    GetLockIndicators(),
+    SetNotifications { enabled: bool }, // Flash the keyboard for desktop notifications
+    SetNotificationRules { rules: Vec<NotificationRule> }, // The first rule that matches is used
+    SetDoNotDisturb { enabled: bool },
+    GetNotifications(),
+    GetStandardEffect(),
+    // Below `threshold` % on battery, 0 to turn off. Ends once the battery
+    // charges a few % above it, or on AC
+    SetLowBattery { threshold: f64, actions: LowBattery },
+    GetLowBattery(),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -51,7 +348,53 @@ pub enum DaemonResponse {
     GetSync { sync: bool },
     SetBatteryHealthOptimizer { result: bool },
     GetBatteryHealthOptimizer { is_on: bool, threshold: u8 },
-    GetDeviceName { name: String }
+    GetDeviceName { name: String },
+    CreateProfile { result: bool },
+    ListProfiles { profiles: Vec<String>, default_profiles: [Option<String>; 2] },
+    RenameProfile { result: bool },
+    DeleteProfile { result: bool },
+    ActivateProfile { result: bool },
+    SetDefaultProfile { result: bool },
+    ExportBundle { bundle: Option<String> },
+    ImportBundle { result: bool, notes: Vec<String> }, // Notes describe what was adjusted, or the error
+    ListEffects { effects: Vec<EffectInfo> },
+    ListLayers { layers: Vec<LayerInfo> },
+    AddLayer { id: Option<u32> }, // ID of the new layer
+    ReplaceLayer { result: bool },
+    MoveLayer { result: bool },
+    RemoveLayer { result: bool },
+    SetLayerVisible { result: bool },
+    SetLayerMask { result: bool },
+    SetLayerBlend { result: bool },
+    SetAnimationFps { result: bool },
+    GetAnimationFps { fps: u8 },
+    SetKeyEvents { result: bool },
+    GetKeyEvents { enabled: bool },
+    ValidateAnimation { error: Option<String> }, // None if the animation is valid
+    StartStream { result: bool },
+    SetOpenRgb { result: bool },
+    GetOpenRgb { port: Option<u16> },
+    Preview { frames: Vec<Vec<u8>> }, // RGB of every key, row by row, for every frame
+    SetColourCorrection { result: bool },
+    GetColourCorrection { correction: ColourCorrection },
+    SetTransitions { result: bool },
+    GetTransitions { ms: u32 },
+    SetSchedule { result: bool },
+    GetSchedule { rules: Vec<ScheduleRule>, location: Option<[f64; 2]>, active: Option<String> }, // Name of the active rule
+    SetLogoBrightness { result: bool },
+    GetLogoBrightness { brightness: u8 },
+    SetLogoOnIdle { result: bool },
+    GetLogoOnIdle { on: bool },
+    SetLockIndicators { result: bool },
+    GetLockIndicators { indicators: LockIndicators },
+    SetNotifications { result: bool },
+    SetNotificationRules { result: bool },
+    SetDoNotDisturb { result: bool },
+    GetNotifications { enabled: bool, do_not_disturb: bool, rules: Vec<NotificationRule> },
+    GetStandardEffect { effect: StandardEffect, active: bool }, // Not active while custom effects are drawn over it
+    SetLowBattery { result: bool },
+    GetLowBattery { threshold: f64, actions: LowBattery, active: bool },
+    Error { message: String }, // Sent instead of the normal response when the daemon failed
 }
 
 #[allow(dead_code)]
@@ -91,18 +434,7 @@ pub fn create() -> Option<UnixListener> {
 pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<DaemonResponse> {
     if let Ok(encoded) = bincode::serialize(&command) {
         if sock.write_all(&encoded).is_ok() {
-            let mut buf = [0u8; 4096];
-            return match sock.read(&mut buf) {
-                Ok(readed) if readed > 0 => read_from_socked_resp(&buf[0..readed]),
-                Ok(_) => {
-                    eprintln!("No response from daemon");
-                    None
-                }
-                Err(_) => {
-                    eprintln!("Read failed!");
-                    None
-                }
-            };
+            return read_from_socked_resp(&mut sock);
         } else {
             eprintln!("Socket write failed!");
         }
@@ -110,10 +442,31 @@ pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<Da
     return None;
 }
 
+/// Starts a streaming session, returning the connection to write frames to
+#[allow(dead_code)]
+pub fn start_stream(layer: Option<u32>) -> Option<UnixStream> {
+    let mut sock = bind()?;
+    let encoded = bincode::serialize(&DaemonCommand::StartStream { layer }).ok()?;
+    sock.write_all(&encoded).ok()?;
+    match read_from_socked_resp(&mut sock) {
+        Some(DaemonResponse::StartStream { result: true }) => Some(sock),
+        _ => None,
+    }
+}
+
+/// Same encoding as `bincode::serialize`, but reading stops at the end of
+/// the message, so messages of any size can be read straight from the socket
+fn bincode_options() -> impl Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE)
+}
+
 /// Deserializes incomming bytes in order to return
 /// a `DaemonResponse`. None is returned if deserializing failed
-fn read_from_socked_resp(bytes: &[u8]) -> Option<DaemonResponse> {
-    match bincode::deserialize::<DaemonResponse>(bytes) {
+fn read_from_socked_resp(reader: &mut impl Read) -> Option<DaemonResponse> {
+    match bincode_options().deserialize_from::<_, DaemonResponse>(reader) {
         Ok(res) => {
             println!("RES: {:?}", res);
             return Some(res);
@@ -128,8 +481,8 @@ fn read_from_socked_resp(bytes: &[u8]) -> Option<DaemonResponse> {
 /// Deserializes incomming bytes in order to return
 /// a `DaemonCommand`. None is returned if deserializing failed
 #[allow(dead_code)]
-pub fn read_from_socket_req(bytes: &[u8]) -> Option<DaemonCommand> {
-    match bincode::deserialize::<DaemonCommand>(bytes) {
+pub fn read_from_socket_req(reader: &mut impl Read) -> Option<DaemonCommand> {
+    match bincode_options().deserialize_from::<_, DaemonCommand>(reader) {
         Ok(res) => {
             println!("REQ: {:?}", res);
             return Some(res);
@@ -1,20 +1,51 @@
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use std::{fs, fs::File, io, env};
+use std::collections::HashMap;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+use crate::comms;
+use crate::device::RazerLaptop;
 
-const SETTINGS_FILE: &str = "/.local/share/razercontrol/daemon.json";
-const EFFECTS_FILE: &str = "/.local/share/razercontrol/effects.json";
+const CONFIG_DIR: &str = "/.local/share/razercontrol";
+pub const SETTINGS_FILE_NAME: &str = "daemon.json";
+pub const EFFECTS_FILE_NAME: &str = "effects.json";
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
+lazy_static! {
+    /// Last contents the daemon wrote or loaded for each file, so the config
+    /// watcher can ignore changes that didn't come from the outside
+    static ref KNOWN_CONTENTS: Mutex<HashMap<PathBuf, String>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
 pub struct PowerConfig {
     pub power_mode: u8,
     pub cpu_boost: u8,
     pub gpu_boost: u8,
     pub fan_rpm: i32,
     pub brightness: u8,
-    pub logo_state: u8,
+    pub logo_state: u8, // 0 off, 1 static, 2 breathing, 3 spectrum
+    #[serde(default = "default_logo_brightness")]
+    pub logo_brightness: u8,
+    #[serde(default)]
+    pub logo_on_idle: bool, // keep the logo on when the keyboard turns off on idle or screensaver
     pub screensaver: bool, // turno of keyboard light if screen is blank
     pub idle: u32,
+    #[serde(default = "default_animation_fps")]
+    pub animation_fps: u8, // frame rate of custom effects
+}
+
+/// Highest frame rate custom effects can be rendered at
+pub const MAX_ANIMATION_FPS: u8 = 60;
+
+fn default_animation_fps() -> u8 {
+    30
+}
+
+fn default_logo_brightness() -> u8 {
+    255
 }
 
 impl PowerConfig {
@@ -26,57 +57,387 @@ impl PowerConfig {
             fan_rpm: 0,
             brightness: 128,
             logo_state: 0,
+            logo_brightness: default_logo_brightness(),
+            logo_on_idle: false,
             screensaver: false,
             idle: 0,
+            animation_fps: default_animation_fps(),
         }
     }
 }
 
+/// A named set of power settings, optionally with a saved keyboard effect
+/// stack, that can be applied to either AC state on demand
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub power: PowerConfig,
+    pub effects: Option<serde_json::Value>, // same format as effects.json
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct BhoConfig {
+    pub is_on: bool,
+    pub threshold: u8,
+}
+
+pub const BUNDLE_FORMAT: &str = "razercontrol-bundle";
+pub const BUNDLE_VERSION: u32 = 2; // 2: typed standard effect
+
+/// Self-describing export of a complete setup, used to move it between laptops
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub format: String,
+    pub version: u32,
+    pub device: String, // model the bundle was exported from
+    pub power: [PowerConfig; 2],
+    pub sync: bool,
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    pub bho: Option<BhoConfig>, // None if the source laptop has no bho
+    pub standard_effect: comms::StandardEffect,
+    pub effects: serde_json::Value, // same format as effects.json
+}
+
+impl Bundle {
+    pub fn parse(json: &str) -> Result<Bundle, String> {
+        let bundle: Bundle = from_json_migrated(json).map_err(|e| e.to_string())?;
+        if bundle.format != BUNDLE_FORMAT {
+            return Err(format!("not a settings bundle (format {:?})", bundle.format));
+        }
+        if bundle.version > BUNDLE_VERSION {
+            return Err(format!("bundle version {} is newer than supported ({})", bundle.version, BUNDLE_VERSION));
+        }
+        Ok(bundle)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Configuration {
     pub power: [PowerConfig; 2],
     pub sync: bool, // sync light settings between ac and battery
     pub no_light: f64, // no light bellow this percentage of battery
-    pub standard_effect: u8,
-    pub standard_effect_params: Vec<u8>,
+    #[serde(default)]
+    pub low_battery: comms::LowBattery, // what changes below `no_light`
+    pub standard_effect: comms::StandardEffect,
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    #[serde(default)]
+    pub default_profiles: [Option<String>; 2], // profile applied when switching to battery/ac
+    #[serde(default)]
+    pub key_events: bool, // feed key presses to custom effects
+    #[serde(default)]
+    pub openrgb_port: Option<u16>, // port of the OpenRGB SDK server, off if None
+    #[serde(default)]
+    pub colour_correction: HashMap<String, comms::ColourCorrection>, // by laptop model
+    #[serde(default = "default_transition_ms")]
+    pub transition_ms: u32, // length of crossfades and brightness fades, 0 to switch instantly
+    #[serde(default)]
+    pub schedule: Vec<comms::ScheduleRule>,
+    #[serde(default)]
+    pub location: Option<[f64; 2]>, // latitude and longitude, for sunrise and sunset in the schedule
+    #[serde(default)]
+    pub lock_indicators: comms::LockIndicators,
+    #[serde(default)]
+    pub notifications: bool, // flash the keyboard for desktop notifications
+    #[serde(default)]
+    pub notification_rules: Vec<comms::NotificationRule>,
+    #[serde(default)]
+    pub do_not_disturb: bool,
+}
+
+/// Longest transition that can be set
+pub const MAX_TRANSITION_MS: u32 = 5000;
+
+fn default_transition_ms() -> u32 {
+    300
 }
 
 impl Configuration {
     pub fn new() -> Configuration {
         return Configuration {
-            power: [PowerConfig::new(), PowerConfig::new()],
+            power: [
+                PowerConfig { animation_fps: 15, ..PowerConfig::new() }, // Smooth enough, saves power on battery
+                PowerConfig::new(),
+            ],
             sync: false,
             no_light: 0.0,
-            standard_effect: 0, // off
-            standard_effect_params: vec![]
+            low_battery: comms::LowBattery::default(),
+            standard_effect: comms::StandardEffect::Off,
+            profiles: vec![],
+            default_profiles: [None, None],
+            key_events: false,
+            openrgb_port: None,
+            colour_correction: HashMap::new(),
+            transition_ms: default_transition_ms(),
+            schedule: vec![],
+            location: None,
+            lock_indicators: comms::LockIndicators::default(),
+            notifications: false,
+            notification_rules: vec![],
+            do_not_disturb: false,
         };
     }
 
+    pub fn get_profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Parses a configuration edited outside of the daemon, rejecting values
+    /// the daemon would never write itself
+    pub fn parse(json: &str) -> Result<Configuration, String> {
+        let config: Configuration = from_json_migrated(json).map_err(|e| e.to_string())?;
+        config.standard_effect.validate()?;
+        validate_low_battery(config.no_light, &config.low_battery)?;
+        let all_power = config.power.iter().chain(config.profiles.iter().map(|p| &p.power));
+        for power in all_power {
+            if power.power_mode > 4 {
+                return Err(format!("invalid power mode {}", power.power_mode));
+            }
+            if power.cpu_boost > 3 {
+                return Err(format!("invalid cpu boost {}", power.cpu_boost));
+            }
+            if power.gpu_boost > 2 {
+                return Err(format!("invalid gpu boost {}", power.gpu_boost));
+            }
+            if power.logo_state > 3 {
+                return Err(format!("invalid logo state {}", power.logo_state));
+            }
+            if power.fan_rpm < 0 {
+                return Err(format!("invalid fan rpm {}", power.fan_rpm));
+            }
+            if power.animation_fps == 0 || power.animation_fps > MAX_ANIMATION_FPS {
+                return Err(format!("invalid animation fps {}", power.animation_fps));
+            }
+        }
+        for name in config.default_profiles.iter().flatten() {
+            if config.get_profile(name).is_none() {
+                return Err(format!("default profile {} does not exist", name));
+            }
+        }
+        Ok(config)
+    }
+
     pub fn write_to_file(&mut self) -> io::Result<()> {
         let j: String = serde_json::to_string_pretty(&self)?;
-        File::create(get_home_directory() + SETTINGS_FILE)?.write_all(j.as_bytes())?;
-        Ok(())
+        write_file_atomic(&get_settings_file(), j.as_bytes())
     }
 
     pub fn read_from_config() -> io::Result<Configuration> {
-        let str = fs::read_to_string(get_home_directory() + SETTINGS_FILE)?;
-        let res: Configuration = serde_json::from_str(str.as_str())?;
-        Ok(res)
+        read_file_with_backup(&get_settings_file(), Configuration::parse)
     }
 
     pub fn write_effects_save(json: serde_json::Value) -> io::Result<()> {
         let j: String = serde_json::to_string_pretty(&json)?;
-        File::create(get_home_directory() + EFFECTS_FILE)?.write_all(j.as_bytes())?;
-        Ok(())
+        write_file_atomic(&get_effects_file(), j.as_bytes())
     }
 
     pub fn read_effects_file() -> io::Result<serde_json::Value> {
-        let str = fs::read_to_string(get_home_directory() + EFFECTS_FILE)?;
-        let res: serde_json::Value = serde_json::from_str(str.as_str())?;
-        Ok(res)
+        read_file_with_backup(&get_effects_file(), |str| serde_json::from_str(str).map_err(|e| e.to_string()))
+    }
+}
+
+/// Checks the low battery threshold and what changes below it
+pub fn validate_low_battery(threshold: f64, actions: &comms::LowBattery) -> Result<(), String> {
+    if !(0.0..=100.0).contains(&threshold) {
+        return Err(format!("invalid battery threshold {}", threshold));
+    }
+    if actions.brightness > 100 {
+        return Err("brightness goes up to 100%".into());
+    }
+    if let Some((pwr, cpu, gpu)) = actions.power {
+        if pwr > 4 || cpu > 3 || gpu > 2 {
+            return Err(format!("invalid power mode {} {} {}", pwr, cpu, gpu));
+        }
+    }
+    if let Some(rpm) = actions.fan_rpm.filter(|rpm| *rpm < 0) {
+        return Err(format!("invalid fan rpm {}", rpm));
     }
+    Ok(())
+}
+
+/// Parses a configuration or bundle, converting the standard effect of
+/// older versions, an effect id with raw parameters, to its typed form
+fn from_json_migrated<T: DeserializeOwned>(json: &str) -> serde_json::Result<T> {
+    let mut json: serde_json::Value = serde_json::from_str(json)?;
+    if let Some(object) = json.as_object_mut() {
+        if let Some(id) = object.get("standard_effect").and_then(|id| id.as_u64()) {
+            let params: Vec<u8> = object
+                .remove("standard_effect_params")
+                .and_then(|p| serde_json::from_value(p).ok())
+                .unwrap_or_default();
+            let effect = legacy_standard_effect(id as u8, &params);
+            object.insert("standard_effect".into(), serde_json::to_value(effect)?);
+        }
+    }
+    serde_json::from_value(json)
+}
+
+/// Standard effect from the id and parameters sent to the firmware by older
+/// versions. Anything the firmware would not have shown is turned off
+fn legacy_standard_effect(id: u8, params: &[u8]) -> comms::StandardEffect {
+    let param = |i: usize| params.get(i).copied().unwrap_or(0);
+    let colour = |i: usize| [param(i), param(i + 1), param(i + 2)];
+    let colours = |kind: u8, first: usize| match kind {
+        0x01 => Some(comms::EffectColours::Single(colour(first))),
+        0x02 => Some(comms::EffectColours::Dual(colour(first), colour(first + 3))),
+        0x03 => Some(comms::EffectColours::Random),
+        _ => None,
+    };
+    let effect = match id {
+        RazerLaptop::WAVE => Some(comms::StandardEffect::Wave {
+            direction: match param(0) {
+                0x02 => comms::WaveDirection::RightToLeft,
+                _ => comms::WaveDirection::LeftToRight,
+            },
+            speed: params.get(1).copied().unwrap_or(comms::StandardEffect::DEFAULT_WAVE_SPEED),
+        }),
+        RazerLaptop::REACTIVE => Some(comms::StandardEffect::Reactive { speed: param(0), colour: colour(1) }),
+        RazerLaptop::BREATHING => colours(param(0), 1).map(|colours| comms::StandardEffect::Breathing { colours }),
+        RazerLaptop::SPECTRUM => Some(comms::StandardEffect::Spectrum),
+        RazerLaptop::STATIC => Some(comms::StandardEffect::Static { colour: colour(0) }),
+        RazerLaptop::STARLIGHT => {
+            colours(param(0), 2).map(|colours| comms::StandardEffect::Starlight { speed: param(1), colours })
+        }
+        _ => None,
+    };
+    effect.filter(|e| e.validate().is_ok()).unwrap_or(comms::StandardEffect::Off)
 }
 
 fn get_home_directory() -> String {
     env::var("HOME").expect("The \"HOME\" environment variable must be set to a valid directory")
 }
+
+pub fn get_config_dir() -> String {
+    get_home_directory() + CONFIG_DIR
+}
+
+pub fn get_settings_file() -> String {
+    get_config_dir() + "/" + SETTINGS_FILE_NAME
+}
+
+pub fn get_effects_file() -> String {
+    get_config_dir() + "/" + EFFECTS_FILE_NAME
+}
+
+/// Returns the contents of a file if they differ from what the daemon last
+/// wrote or loaded
+pub fn read_if_changed(path: &str) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let known = KNOWN_CONTENTS.lock().unwrap();
+    if known.get(Path::new(path)) == Some(&contents) {
+        return None;
+    }
+    Some(contents)
+}
+
+/// Marks the contents of a file as applied by the daemon
+pub fn remember_contents(path: &str, contents: String) {
+    KNOWN_CONTENTS.lock().unwrap().insert(PathBuf::from(path), contents);
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("json.bak")
+}
+
+/// Writes a file so that a crash at any point leaves either the old or the new
+/// contents on disk, never a truncated file. The previous contents are kept
+/// as a backup if they are still valid JSON
+fn write_file_atomic(path: &str, contents: &[u8]) -> io::Result<()> {
+    let path = Path::new(path);
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    fs::create_dir_all(dir)?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    if let Err(e) = tmp.write_all(contents).and_then(|_| tmp.sync_all()) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    let current_is_good = fs::read_to_string(path)
+        .map(|s| serde_json::from_str::<serde_json::Value>(&s).is_ok())
+        .unwrap_or(false);
+    if current_is_good {
+        // Linked rather than moved, so the file exists at all times. The
+        // rename below leaves the backup with the previous contents
+        let backup = backup_path(path);
+        let _ = fs::remove_file(&backup);
+        if fs::hard_link(path, &backup).is_err() {
+            fs::copy(path, &backup)?;
+        }
+    }
+    // Remember the contents before they become visible to the config watcher
+    let contents = String::from_utf8_lossy(contents).into_owned();
+    KNOWN_CONTENTS.lock().unwrap().insert(path.to_path_buf(), contents);
+    fs::rename(&tmp_path, path)?;
+    // Make sure the rename itself reaches the disk
+    File::open(dir)?.sync_all()
+}
+
+/// Reads a file with `parse`, falling back to its backup if the file is
+/// missing or `parse` rejects it
+fn read_file_with_backup<T>(path: &str, parse: impl Fn(&str) -> Result<T, String>) -> io::Result<T> {
+    let path = Path::new(path);
+    let res = fs::read_to_string(path)
+        .and_then(|str| parse(&str).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    match res {
+        Ok(res) => Ok(res),
+        Err(e) => {
+            let backup = backup_path(path);
+            if !backup.exists() {
+                return Err(e);
+            }
+            eprintln!("Error reading {:?} ({}), using backup", path, e);
+            let str = fs::read_to_string(backup)?;
+            parse(&str).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_writes_keep_a_backup() {
+        let dir = std::env::temp_dir().join(format!("razer-config-test-{}", std::process::id()));
+        let path = dir.join("daemon.json");
+        let path_str = path.to_str().unwrap();
+        write_file_atomic(path_str, b"{\"version\": 1}").unwrap();
+        assert!(!backup_path(&path).exists());
+        write_file_atomic(path_str, b"{\"version\": 2}").unwrap();
+        write_file_atomic(path_str, b"{\"version\": 3}").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"version\": 3}");
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "{\"version\": 2}");
+        assert!(!path.with_extension("json.tmp").exists());
+
+        // Broken files are not backed up
+        fs::write(&path, "{").unwrap();
+        write_file_atomic(path_str, b"{\"version\": 4}").unwrap();
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "{\"version\": 2}");
+        let json = |str: &str| serde_json::from_str::<serde_json::Value>(str).map_err(|e| e.to_string());
+        let value = read_file_with_backup(path_str, json).unwrap();
+        assert_eq!(value["version"], 4);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_configs_fall_back_to_the_backup() {
+        let dir = std::env::temp_dir().join(format!("razer-config-backup-test-{}", std::process::id()));
+        let path = dir.join("daemon.json");
+        let path_str = path.to_str().unwrap();
+        let mut config = Configuration::new();
+        config.power[1].power_mode = 2;
+        write_file_atomic(path_str, serde_json::to_string(&config).unwrap().as_bytes()).unwrap();
+        // Valid JSON, but not a valid configuration
+        config.power[1].power_mode = 9;
+        write_file_atomic(path_str, serde_json::to_string(&config).unwrap().as_bytes()).unwrap();
+        let read = read_file_with_backup(path_str, Configuration::parse).unwrap();
+        assert_eq!(read.power[1].power_mode, 2);
+
+        fs::remove_file(backup_path(&path)).unwrap();
+        assert!(read_file_with_backup(path_str, Configuration::parse).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
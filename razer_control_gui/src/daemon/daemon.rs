@@ -10,6 +10,7 @@ use signal_hook::iterator::Signals;
 use signal_hook::consts::{SIGINT, SIGTERM};
 use dbus::blocking::Connection;
 use dbus::{Message, arg};
+use inotify::{Inotify, WatchMask};
 
 #[path = "../comms.rs"]
 mod comms;
@@ -21,11 +22,15 @@ mod dbus_mutter_displayconfig;
 mod dbus_mutter_idlemonitor;
 mod screensaver;
 mod login1;
+mod openrgb;
+mod schedule;
 
 use crate::kbd::Effect;
 
 lazy_static! {
     static ref EFFECT_MANAGER: Mutex<kbd::EffectManager> = Mutex::new(kbd::EffectManager::new());
+    /// Layer OpenRGB clients draw on, added when the first client connects
+    static ref OPENRGB_LAYER: Mutex<Option<u32>> = Mutex::new(None);
     // static ref CONFIG: Mutex<config::Configuration> = {
         // match config::Configuration::read_from_config() {
             // Ok(c) => Mutex::new(c),
@@ -68,10 +73,12 @@ fn main() {
             info!("AC0 online: {:?}", online);
             d.set_ac_state(online);
             d.restore_standard_effect();
-            if let Ok(json) = config::Configuration::read_effects_file() {
-                EFFECT_MANAGER.lock().unwrap().load_from_save(json);
-            } else {
-                println!("No effects save, creating a new one");
+            let loaded = match config::Configuration::read_effects_file() {
+                Ok(json) => EFFECT_MANAGER.lock().unwrap().load_from_save(json),
+                Err(_) => false,
+            };
+            if !loaded {
+                println!("No valid effects save, creating a new one");
                 // No effects found, start with a green static layer, just like synapse
                 EFFECT_MANAGER.lock().unwrap().push_effect(
                     kbd::effects::Static::new(vec![0, 255, 0]), 
@@ -85,8 +92,15 @@ fn main() {
     }
 
     start_keyboard_animator_task();
+    start_brightness_fade_task();
     start_screensaver_monitor_task();
     start_battery_monitor_task();
+    start_config_watcher_task();
+    start_key_event_task();
+    start_lock_indicator_task();
+    start_notification_task();
+    start_openrgb_task();
+    start_schedule_task(Box::new(schedule::SystemClock));
     let clean_thread = start_shutdown_task();
 
     if let Some(listener) = comms::create() {
@@ -129,14 +143,370 @@ pub fn start_keyboard_animator_task() -> JoinHandle<()> {
     // Start the keyboard animator thread,
     thread::spawn(|| {
         loop {
-            if let Some(laptop) = DEV_MANAGER.lock().unwrap().get_device() {
-                EFFECT_MANAGER.lock().unwrap().update(laptop);
+            let frame_start = std::time::Instant::now();
+            let mut animating = false;
+            let mut fps = 1;
+            if let Ok(mut d) = DEV_MANAGER.lock() {
+                fps = d.get_current_animation_fps().max(1);
+                let transition_ms = d.get_transition_ms();
+                if let Some(laptop) = d.get_device() {
+                    let mut k = EFFECT_MANAGER.lock().unwrap();
+                    k.set_transition_ms(transition_ms);
+                    animating = k.update(laptop);
+                }
+            }
+            if animating {
+                // Time spent rendering counts towards the frame
+                let frame = std::time::Duration::from_millis(1000 / fps as u64);
+                thread::sleep(frame.saturating_sub(frame_start.elapsed()));
+            } else {
+                // Nothing changes on screen, sleep until the layers do
+                kbd::wait_for_stack_change(std::time::Duration::from_secs(1));
             }
-            thread::sleep(std::time::Duration::from_millis(kbd::ANIMATION_SLEEP_MS));
         }
     })
 }
 
+/// Steps brightness fades, like the keyboard going dark when idle
+fn start_brightness_fade_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        loop {
+            let fading = DEV_MANAGER.lock().is_ok_and(|mut d| d.step_brightness_fade());
+            thread::sleep(time::Duration::from_millis(if fading { 20 } else { 100 }));
+        }
+    })
+}
+
+/// Applies the schedule rule active at the time given by `clock`, checking
+/// every second so rule edits and AC changes are followed
+fn start_schedule_task(clock: Box<dyn schedule::Clock>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut applied: Option<comms::ScheduleRule> = None;
+        let mut last_error = None;
+        loop {
+            let (rules, location) = DEV_MANAGER.lock().unwrap().get_schedule();
+            let active = match schedule::Schedule::new(&rules, location) {
+                Ok(schedule) => {
+                    last_error = None;
+                    schedule.active_rule(clock.now()).map(|idx| rules[idx].clone())
+                }
+                Err(error) => {
+                    if last_error.as_ref() != Some(&error) {
+                        warn!("Ignoring invalid schedule: {}", error);
+                        last_error = Some(error);
+                    }
+                    None
+                }
+            };
+            if active != applied {
+                info!("Schedule rule: {}", active.as_ref().map_or("none", |r| r.name.as_str()));
+                apply_schedule_rule(active.as_ref());
+                applied = active;
+            }
+            thread::sleep(time::Duration::from_secs(1));
+        }
+    })
+}
+
+/// Switches to the schedule rule `rule`. Power settings of a rule are used
+/// instead of the ones of the AC state, and its effects are shown without
+/// replacing the saved ones, which come back when it ends
+fn apply_schedule_rule(rule: Option<&comms::ScheduleRule>) {
+    let mut d = DEV_MANAGER.lock().unwrap();
+    let mut power_override = device::PowerOverride::default();
+    let mut effects = None;
+    for action in rule.map_or(&[][..], |r| &r.actions) {
+        match action {
+            comms::ScheduleAction::Profile { name } => match d.get_profile(name) {
+                Some(profile) => {
+                    power_override.brightness = Some(profile.power.brightness);
+                    power_override.logo_state = Some(profile.power.logo_state);
+                    power_override.logo_brightness = Some(profile.power.logo_brightness);
+                    power_override.power = Some((profile.power.power_mode, profile.power.cpu_boost, profile.power.gpu_boost));
+                    power_override.fan_rpm = Some(profile.power.fan_rpm);
+                    effects = profile.effects.or(effects);
+                }
+                None => warn!("Schedule rule uses missing profile {}", name),
+            },
+            comms::ScheduleAction::Brightness { percent } => {
+                power_override.brightness = Some((*percent as u16 * 255 / 100) as u8);
+            }
+            comms::ScheduleAction::Logo { state } => power_override.logo_state = Some(*state),
+            comms::ScheduleAction::LogoBrightness { percent } => {
+                power_override.logo_brightness = Some((*percent as u16 * 255 / 100) as u8);
+            }
+            comms::ScheduleAction::Effect { name, params } => match kbd::registry::create_effect(name, params.clone()) {
+                Ok(effect) => effects = Some(kbd::single_effect_save(effect)),
+                Err(error) => warn!("Schedule rule effect {}: {}", name, error),
+            },
+            comms::ScheduleAction::Power { pwr, cpu, gpu } => power_override.power = Some((*pwr, *cpu, *gpu)),
+            comms::ScheduleAction::Fan { rpm } => power_override.fan_rpm = Some(*rpm),
+        }
+    }
+    d.set_active_rule(rule.map(|r| r.name.clone()), power_override);
+
+    let mut k = EFFECT_MANAGER.lock().unwrap();
+    match effects {
+        Some(effects) => {
+            if !k.show_scheduled(effects) {
+                warn!("Could not show the effects of the schedule");
+            }
+        }
+        // Back to the saved effects, which may be a standard one
+        None => {
+            if k.end_scheduled() && k.is_standard_shown() {
+                d.restore_standard_effect();
+            }
+        }
+    }
+}
+
+/// Lights the lock keys that are on over the custom effects. The locks are
+/// read from the LEDs in sysfs, or from the keyboard event device without them
+fn start_lock_indicator_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        let mut device: Option<Option<evdev::Device>> = None; // Searched once
+        loop {
+            let (indicators, keymap) = match DEV_MANAGER.lock() {
+                Ok(mut d) => (d.get_lock_indicators(), d.get_keymap()),
+                Err(_) => (comms::LockIndicators::default(), None),
+            };
+            let keymap = match keymap {
+                Some(keymap) if indicators.caps || indicators.num || indicators.scroll => keymap,
+                _ => {
+                    EFFECT_MANAGER.lock().unwrap().set_indicators(vec![]);
+                    thread::sleep(time::Duration::from_secs(1));
+                    continue;
+                }
+            };
+            let state = kbd::keys::read_lock_leds().or_else(|| {
+                let device = device.get_or_insert_with(kbd::keys::find_keyboard);
+                device.as_ref().and_then(kbd::keys::read_device_locks)
+            });
+            let keys = kbd::keys::lock_indicator_keys(&indicators, state.unwrap_or_default(), &keymap);
+            EFFECT_MANAGER.lock().unwrap().set_indicators(keys);
+            thread::sleep(time::Duration::from_millis(100));
+        }
+    })
+}
+
+/// Flashes the keyboard for desktop notifications while they are enabled
+fn start_notification_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        loop {
+            if DEV_MANAGER.lock().is_ok_and(|mut d| d.get_notifications().0) {
+                if let Err(error) = monitor_notifications() {
+                    warn!("Can't monitor notifications: {}", error);
+                    thread::sleep(time::Duration::from_secs(10));
+                }
+            }
+            thread::sleep(time::Duration::from_secs(1));
+        }
+    })
+}
+
+/// Reads `Notify` calls on the session bus until notifications are disabled.
+/// They are method calls to the notification server, not signals, so they
+/// are only seen by a monitor. A monitor connection can't be used for
+/// anything else, so it is separate from the one of the screensaver monitor
+fn monitor_notifications() -> Result<(), dbus::Error> {
+    let dbus_session = Connection::new_session()?;
+    let proxy = dbus_session.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", time::Duration::from_millis(5000));
+    let rules = vec!["type='method_call',interface='org.freedesktop.Notifications',member='Notify'"];
+    proxy.method_call::<(), _, _, _>("org.freedesktop.DBus.Monitoring", "BecomeMonitor", (rules, 0u32))?;
+    info!("Monitoring desktop notifications");
+    while DEV_MANAGER.lock().is_ok_and(|mut d| d.get_notifications().0) {
+        let msg = match dbus_session.channel().blocking_pop_message(time::Duration::from_millis(1000))? {
+            Some(msg) if msg.member().as_deref() == Some("Notify") => msg,
+            _ => continue,
+        };
+        // app_name, replaces_id, app_icon, summary, body, actions, hints, expire_timeout
+        let (app, _, _, _, _, _, hints, _): (String, u32, String, String, String, Vec<String>, arg::PropMap, i32) =
+            match msg.read_all() {
+                Ok(args) => args,
+                Err(_) => continue,
+            };
+        let urgency = arg::prop_cast::<u8>(&hints, "urgency").copied().unwrap_or(1);
+        let rule = match DEV_MANAGER.lock().ok().and_then(|mut d| d.match_notification(&app, urgency)) {
+            Some(rule) => rule,
+            None => continue,
+        };
+        debug!("Notification from {} (urgency {})", app, urgency);
+        let keys = kbd::timeline::parse_selector(&rule.keys).unwrap_or_default();
+        EFFECT_MANAGER.lock().unwrap().flash(rule.colour, keys, rule.style);
+    }
+    Ok(())
+}
+
+/// Feeds key presses to the custom effects while key events are enabled
+fn start_key_event_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        loop {
+            let keymap = match DEV_MANAGER.lock() {
+                Ok(mut d) => {
+                    if d.get_key_events() { d.get_keymap() } else { None }
+                }
+                Err(_) => None,
+            };
+            let keymap = match keymap {
+                Some(keymap) => keymap,
+                None => {
+                    thread::sleep(time::Duration::from_secs(1));
+                    continue;
+                }
+            };
+            let mut device = match kbd::keys::find_keyboard() {
+                Some(device) => device,
+                None => {
+                    warn!("No keyboard event device found, retrying");
+                    thread::sleep(time::Duration::from_secs(10));
+                    continue;
+                }
+            };
+            info!("Reading key events from {}", device.name().unwrap_or("keyboard"));
+            loop {
+                let events = match kbd::keys::read_events(&mut device, &keymap) {
+                    Ok(events) => events,
+                    Err(error) => {
+                        warn!("Error reading key events: {}", error);
+                        break;
+                    }
+                };
+                // Checked after each batch, as reading blocks until a key is pressed
+                if !DEV_MANAGER.lock().is_ok_and(|mut d| d.get_key_events()) {
+                    break;
+                }
+                if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                    for event in events {
+                        k.key_event(event);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Runs the OpenRGB SDK server while it is enabled, following port changes
+fn start_openrgb_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        loop {
+            let port = match DEV_MANAGER.lock().ok().and_then(|mut d| d.get_openrgb_port()) {
+                Some(port) => port,
+                None => {
+                    thread::sleep(time::Duration::from_secs(1));
+                    continue;
+                }
+            };
+            let listener = match std::net::TcpListener::bind(("127.0.0.1", port)) {
+                Ok(listener) => listener,
+                Err(error) => {
+                    error!("Could not start the OpenRGB server on port {}: {}", port, error);
+                    thread::sleep(time::Duration::from_secs(10));
+                    continue;
+                }
+            };
+            info!("OpenRGB server listening on port {}", port);
+            // Non blocking, so the server can be stopped or moved
+            if let Err(error) = listener.set_nonblocking(true) {
+                error!("Could not set up the OpenRGB server: {}", error);
+                return;
+            }
+            while DEV_MANAGER.lock().ok().and_then(|mut d| d.get_openrgb_port()) == Some(port) {
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        info!("OpenRGB client connected from {}", address);
+                        thread::spawn(move || serve_openrgb_client(stream));
+                    }
+                    Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(time::Duration::from_millis(200));
+                    }
+                    Err(error) => warn!("Error accepting OpenRGB client: {}", error),
+                }
+            }
+            info!("OpenRGB server on port {} stopped", port);
+        }
+    })
+}
+
+fn serve_openrgb_client(stream: std::net::TcpStream) {
+    if let Err(error) = stream.set_nonblocking(false) {
+        warn!("Error setting up OpenRGB client: {}", error);
+        return;
+    }
+    let name = match DEV_MANAGER.lock().unwrap().get_device() {
+        Some(laptop) => laptop.get_name(),
+        None => "Razer Laptop".into(),
+    };
+    if let Ok(mut d) = DEV_MANAGER.lock() {
+        if let Some(laptop) = d.get_device() {
+            openrgb_layer(&mut EFFECT_MANAGER.lock().unwrap(), laptop);
+        }
+    }
+    match openrgb::serve_client(stream, &name, &apply_openrgb) {
+        Err(error) if error.kind() != std::io::ErrorKind::UnexpectedEof => {
+            warn!("OpenRGB client disconnected with error: {}", error)
+        }
+        _ => info!("OpenRGB client disconnected"),
+    }
+    // Keep what the client left on the keyboard
+    if let Ok(mut d) = DEV_MANAGER.lock() {
+        save_openrgb_state(&mut d, &mut EFFECT_MANAGER.lock().unwrap());
+    }
+}
+
+/// Saves the layer and logo state OpenRGB clients left
+fn save_openrgb_state(d: &mut device::DeviceManager, k: &mut kbd::EffectManager) {
+    let ac = d.get_device().map(|laptop| laptop.get_ac_state());
+    if let (Some(ac), Some(logo_state)) = (ac, d.get_openrgb_logo()) {
+        d.set_logo_led_state(ac, logo_state);
+    }
+    save_effects(d, k);
+    d.take_write_error(); // Already logged, there is no client to report it to
+}
+
+/// The layer OpenRGB clients draw on. It is added hidden, on top, unless
+/// the one of an earlier session is still there
+fn openrgb_layer(k: &mut kbd::EffectManager, laptop: &mut device::RazerLaptop) -> Option<u32> {
+    let mut layer = OPENRGB_LAYER.lock().unwrap();
+    if let Some(id) = layer.filter(|id| k.list_layers().iter().any(|l| l.id == *id)) {
+        return Some(id);
+    }
+    let effect = kbd::effects::PerKey::new(vec![0; openrgb::KEYBOARD_LEDS * 3]);
+    *layer = k.insert_effect(effect, None, vec![true; openrgb::KEYBOARD_LEDS]);
+    if let Some(id) = *layer {
+        k.set_layer_visible(id, false, laptop); // Until the client sends colours
+    }
+    *layer
+}
+
+/// Shows the state of the OpenRGB controller on its layer and the logo
+fn apply_openrgb(controller: &openrgb::Controller, change: openrgb::Change) {
+    let mut d = DEV_MANAGER.lock().unwrap();
+    let mut k = EFFECT_MANAGER.lock().unwrap();
+    if change == openrgb::Change::Save {
+        save_openrgb_state(&mut d, &mut k);
+        return;
+    }
+
+    let logo_state = if controller.shown_colour(openrgb::LOGO_LED) == [0, 0, 0] { 0 } else { 1 };
+    d.set_openrgb_logo(Some(logo_state));
+
+    let laptop = match d.get_device() {
+        Some(laptop) => laptop,
+        None => return,
+    };
+    let mut colours = vec![];
+    for led in 0..openrgb::KEYBOARD_LEDS {
+        colours.extend(controller.shown_colour(led));
+    }
+    let effect = kbd::effects::PerKey::new(colours);
+    // Added again if it was removed during the session
+    if let Some(id) = openrgb_layer(&mut k, laptop) {
+        k.replace_effect(id, effect);
+        k.set_layer_visible(id, controller.mode != openrgb::Mode::Off, laptop);
+    }
+}
+
 fn start_screensaver_monitor_task() -> JoinHandle<()> {
     thread::spawn(move || {
         let dbus_session = Connection::new_session()
@@ -147,7 +517,7 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
             if let Some(online) = online {
                 if *online == 3 {
                     if let Ok(mut d) = DEV_MANAGER.lock() {
-                        d.light_off();
+                        d.light_off(false);
                     }
                 }
                 else if *online == 0 {
@@ -164,7 +534,7 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
             if let Ok(mut d) = DEV_MANAGER.lock() {
                 if d.idle_id == h.id {
                     println!("idle trigger {:?}", h.id);
-                    d.light_off();
+                    d.light_off(false);
                 } else if d.active_id == h.id {
                     println!("active trigger {:?}", h.id);
                     d.restore_light();
@@ -177,7 +547,7 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
             println!("ActiveChanged {:?}", h.arg0);
             if let Ok(mut d) = DEV_MANAGER.lock() {
                 if h.arg0 {
-                    d.light_off();
+                    d.light_off(false);
                 } else {
                     d.restore_light();
                 }
@@ -225,12 +595,28 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             time::Duration::from_millis(5000)
         );
 
+        use battery::OrgFreedesktopUPowerDevice;
+        if let Ok(perc) = proxy_battery.percentage() {
+            kbd::metrics::set_battery_percentage(perc as f32);
+            update_low_battery(&mut DEV_MANAGER.lock().unwrap(), Some(perc));
+        }
+
         let _id = proxy_ac.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
             let online: Option<&bool> = arg::prop_cast(&h.changed_properties, "Online");
             if let Some(online) = online {
                 info!("AC0 online: {:?}", online);
                 if let Ok(mut d) = DEV_MANAGER.lock() {
+                    let profile = d.apply_default_profile(*online as usize);
                     d.set_ac_state(*online);
+                    update_low_battery(&mut d, None);
+                    if let Some(effects) = profile.and_then(|p| p.effects) {
+                        let mut k = EFFECT_MANAGER.lock().unwrap();
+                        if k.load_from_save(effects) {
+                            save_effects(&mut d, &mut k);
+                        }
+                        k.invalidate(); // The standard effect was restored over it
+                    }
+                    d.take_write_error(); // Already logged, there is no client to report it to
                 }
             }
             true
@@ -240,6 +626,10 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             let perc: Option<&f64> = arg::prop_cast(&h.changed_properties, "Percentage");
             if let Some(perc) = perc {
                 info!("Battery percentage: {:.1}", perc);
+                kbd::metrics::set_battery_percentage(*perc as f32);
+                if let Ok(mut d) = DEV_MANAGER.lock() {
+                    update_low_battery(&mut d, Some(*perc));
+                }
             }
             true
         });
@@ -248,10 +638,15 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             info!("PrepareForSleep {:?}", h.start);
             if let Ok(mut d) = DEV_MANAGER.lock() {
                 d.set_ac_state_get();
+                update_low_battery(&mut d, None);
                 if h.start {
-                    d.light_off();
+                    d.light_off(true);
                 } else {
                     d.restore_light();
+                    // The keyboard may have lost its lighting while suspended,
+                    // so the unchanged custom frame is sent again too
+                    d.restore_standard_effect();
+                    EFFECT_MANAGER.lock().unwrap().invalidate();
                 }
             }
             true
@@ -261,6 +656,98 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
     })
 }
 
+/// Starts or ends low battery mode if the battery crossed the threshold, and
+/// pauses the animations while it is on
+fn update_low_battery(d: &mut device::DeviceManager, percentage: Option<f64>) {
+    if d.update_low_battery(percentage) {
+        info!("Low battery mode {}", if d.is_low_battery() { "on" } else { "off" });
+    }
+    EFFECT_MANAGER.lock().unwrap().set_paused(d.is_low_battery());
+}
+
+/// Watches the config directory and applies files edited from the outside
+fn start_config_watcher_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        let dir = config::get_config_dir();
+        if let Err(error) = std::fs::create_dir_all(&dir) {
+            error!("Could not create config directory {}: {}", dir, error);
+            return;
+        }
+        let mut inotify = match Inotify::init() {
+            Ok(inotify) => inotify,
+            Err(error) => {
+                error!("Could not initialize inotify, config reload disabled: {}", error);
+                return;
+            }
+        };
+        // Watch the directory rather than the files, as saving replaces them
+        if let Err(error) = inotify.add_watch(&dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO) {
+            error!("Could not watch {}, config reload disabled: {}", dir, error);
+            return;
+        }
+        let mut buffer = [0u8; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(error) => {
+                    error!("Error reading inotify events: {}", error);
+                    return;
+                }
+            };
+            for event in events {
+                match event.name.and_then(|n| n.to_str()) {
+                    Some(config::SETTINGS_FILE_NAME) => reload_settings_file(),
+                    Some(config::EFFECTS_FILE_NAME) => reload_effects_file(),
+                    _ => {}
+                }
+            }
+        }
+    })
+}
+
+fn reload_settings_file() {
+    let path = config::get_settings_file();
+    if let Some(contents) = config::read_if_changed(&path) {
+        match config::Configuration::parse(&contents) {
+            Ok(new_config) => {
+                info!("Settings file changed, reloading");
+                let mut d = DEV_MANAGER.lock().unwrap();
+                d.reload_config(new_config);
+                update_low_battery(&mut d, None); // The threshold may have changed
+                // The standard effect may have been written over the custom one
+                EFFECT_MANAGER.lock().unwrap().invalidate();
+                config::remember_contents(&path, contents);
+            }
+            Err(error) => warn!("Ignoring invalid settings file: {}", error),
+        }
+    }
+}
+
+fn reload_effects_file() {
+    let path = config::get_effects_file();
+    if let Some(contents) = config::read_if_changed(&path) {
+        match serde_json::from_str(&contents) {
+            Ok(json) => {
+                if EFFECT_MANAGER.lock().unwrap().load_from_save(json) {
+                    info!("Effects file changed, reloading");
+                    config::remember_contents(&path, contents);
+                } else {
+                    warn!("Ignoring invalid effects file");
+                }
+            }
+            Err(error) => warn!("Ignoring invalid effects file: {}", error),
+        }
+    }
+}
+
+/// Persists the effect stack so it is restored on the next start
+fn save_effects(d: &mut device::DeviceManager, k: &mut kbd::EffectManager) {
+    if let Err(error) = config::Configuration::write_effects_save(k.save()) {
+        error!("Error writing effects {}", error);
+        d.record_write_error(error.to_string());
+    }
+}
+
 /// Monitors signals and stops the daemon when receiving one
 pub fn start_shutdown_task() -> JoinHandle<()> {
     thread::spawn(|| {
@@ -268,11 +755,9 @@ pub fn start_shutdown_task() -> JoinHandle<()> {
         let _ = signals.forever().next();
         
         // If we reach this point, we have a signal and it is time to exit
+        // Effects are saved as soon as they change, so there is nothing to
+        // write here. Doing it would clobber edits made while we were running
         println!("Received signal, cleaning up");
-        let json = EFFECT_MANAGER.lock().unwrap().save();
-        if let Err(error) = config::Configuration::write_effects_save(json) {
-            error!("Error writing config {}", error);
-        }
         if std::fs::metadata(comms::SOCKET_PATH).is_ok() {
             std::fs::remove_file(comms::SOCKET_PATH).unwrap();
         }
@@ -281,12 +766,11 @@ pub fn start_shutdown_task() -> JoinHandle<()> {
 }
 
 fn handle_data(mut stream: UnixStream) {
-    let mut buffer = [0u8; 4096];
-    if stream.read(&mut buffer).is_err() {
-        return;
-    }
-
-    if let Some(cmd) = comms::read_from_socket_req(&buffer) {
+    if let Some(cmd) = comms::read_from_socket_req(&mut stream) {
+        if let comms::DaemonCommand::StartStream { layer } = cmd {
+            start_stream_session(stream, layer);
+            return;
+        }
         if let Some(s) = process_client_request(cmd) {
             if let Ok(x) = bincode::serialize(&s) {
                 let result = stream.write_all(&x);
@@ -299,9 +783,36 @@ fn handle_data(mut stream: UnixStream) {
     }
 }
 
+/// Reads the frames of a streaming client until it disconnects, then goes
+/// back to the saved effects
+fn start_stream_session(mut stream: UnixStream, layer: Option<u32>) {
+    let session = EFFECT_MANAGER.lock().unwrap().start_stream(layer);
+    let response = comms::DaemonResponse::StartStream { result: session.is_some() };
+    if let Ok(x) = bincode::serialize(&response) {
+        if let Err(error) = stream.write_all(&x) {
+            println!("Client disconnected with error: {error}");
+        }
+    }
+    let session = match session {
+        Some(session) => session,
+        None => return,
+    };
+    info!("Streaming session {} started", session);
+    thread::spawn(move || {
+        let mut frame = [0u8; comms::STREAM_FRAME_SIZE];
+        while stream.read_exact(&mut frame).is_ok() {
+            if !EFFECT_MANAGER.lock().unwrap().stream_frame(session, &frame) {
+                break;
+            }
+        }
+        EFFECT_MANAGER.lock().unwrap().end_stream(session);
+        info!("Streaming session {} ended", session);
+    });
+}
+
 pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
     if let Ok(mut d) = DEV_MANAGER.lock() {
-        return match cmd {
+        let response = match cmd {
             comms::DaemonCommand::SetPowerMode { ac, pwr, cpu, gpu } => {
                 Some(comms::DaemonResponse::SetPowerMode { result: d.set_power_mode(ac, pwr, cpu, gpu) })
             },
@@ -324,12 +835,63 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
                 Some(comms::DaemonResponse::GetBrightness { result: d.get_brightness(ac)})
             },
             comms::DaemonCommand::GetLogoLedState{ac} => Some(comms::DaemonResponse::GetLogoLedState {logo_state: d.get_logo_led_state(ac) }),
+            comms::DaemonCommand::SetLogoBrightness { ac, val } => {
+                Some(comms::DaemonResponse::SetLogoBrightness { result: d.set_logo_brightness(ac, val) })
+            }
+            comms::DaemonCommand::GetLogoBrightness { ac } => {
+                Some(comms::DaemonResponse::GetLogoBrightness { brightness: d.get_logo_brightness(ac) })
+            }
+            comms::DaemonCommand::SetLogoOnIdle { ac, on } => {
+                Some(comms::DaemonResponse::SetLogoOnIdle { result: d.set_logo_on_idle(ac, on) })
+            }
+            comms::DaemonCommand::GetLogoOnIdle { ac } => Some(comms::DaemonResponse::GetLogoOnIdle { on: d.get_logo_on_idle(ac) }),
+            comms::DaemonCommand::SetLockIndicators { indicators } => {
+                Some(comms::DaemonResponse::SetLockIndicators { result: d.set_lock_indicators(indicators) })
+            }
+            comms::DaemonCommand::SetNotifications { enabled } => {
+                Some(comms::DaemonResponse::SetNotifications { result: d.set_notifications(enabled) })
+            }
+            comms::DaemonCommand::SetNotificationRules { rules } => {
+                match d.set_notification_rules(rules) {
+                    Ok(()) => Some(comms::DaemonResponse::SetNotificationRules { result: true }),
+                    Err(message) => Some(comms::DaemonResponse::Error { message }),
+                }
+            }
+            comms::DaemonCommand::SetDoNotDisturb { enabled } => {
+                Some(comms::DaemonResponse::SetDoNotDisturb { result: d.set_do_not_disturb(enabled) })
+            }
+            comms::DaemonCommand::GetNotifications() => {
+                let (enabled, do_not_disturb, rules) = d.get_notifications();
+                Some(comms::DaemonResponse::GetNotifications { enabled, do_not_disturb, rules })
+            }
+            comms::DaemonCommand::SetLowBattery { threshold, actions } => {
+                match d.set_low_battery(threshold, actions) {
+                    Ok(()) => {
+                        update_low_battery(&mut d, None);
+                        Some(comms::DaemonResponse::SetLowBattery { result: true })
+                    }
+                    Err(message) => Some(comms::DaemonResponse::Error { message }),
+                }
+            }
+            comms::DaemonCommand::GetLowBattery() => {
+                let (threshold, actions, active) = d.get_low_battery();
+                Some(comms::DaemonResponse::GetLowBattery { threshold, actions, active })
+            }
+            comms::DaemonCommand::GetStandardEffect() => {
+                let active = !EFFECT_MANAGER.lock().unwrap().is_drawing();
+                Some(comms::DaemonResponse::GetStandardEffect { effect: d.get_standard_effect(), active })
+            }
+            comms::DaemonCommand::GetLockIndicators() => {
+                Some(comms::DaemonResponse::GetLockIndicators { indicators: d.get_lock_indicators() })
+            }
             comms::DaemonCommand::GetKeyboardRGB { layer } => {
-                let map = EFFECT_MANAGER.lock().unwrap().get_map(layer);
-                Some(comms::DaemonResponse::GetKeyboardRGB {
-                    layer,
-                    rgbdata: map,
-                })
+                match EFFECT_MANAGER.lock().unwrap().get_map(layer) {
+                    Some(map) => Some(comms::DaemonResponse::GetKeyboardRGB {
+                        layer,
+                        rgbdata: map,
+                    }),
+                    None => Some(comms::DaemonResponse::Error { message: format!("No layer with ID {}", layer) }),
+                }
             }
             comms::DaemonCommand::GetSync() => Some(comms::DaemonResponse::GetSync { sync: d.get_sync() }),
             comms::DaemonCommand::GetFanSpeed{ac} => Some(comms::DaemonResponse::GetFanSpeed { rpm: d.get_fan_rpm(ac)}),
@@ -337,77 +899,282 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
             comms::DaemonCommand::GetCPUBoost{ac} => Some(comms::DaemonResponse::GetCPUBoost { cpu: d.get_cpu_boost(ac) }),
             comms::DaemonCommand::GetGPUBoost{ac} => Some(comms::DaemonResponse::GetGPUBoost { gpu: d.get_gpu_boost(ac) }),
             comms::DaemonCommand::SetEffect{ name, params } => {
+                match kbd::registry::create_effect(&name, params) {
+                    Ok(effect) => {
+                        let mut res = false;
+                        if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                            if let Some(laptop) = d.get_device() {
+                                k.crossfade();
+                                k.pop_effect(laptop); // Remove old layer
+                                k.push_effect(
+                                    effect,
+                                    [true; 90]
+                                    );
+                                res = true;
+                                save_effects(&mut d, &mut k);
+                            }
+                        }
+                        Some(comms::DaemonResponse::SetEffect{result: res})
+                    }
+                    Err(message) => Some(comms::DaemonResponse::Error{ message }),
+                }
+            }
+
+            comms::DaemonCommand::ListEffects() => {
+                Some(comms::DaemonResponse::ListEffects{ effects: kbd::registry::list_effects() })
+            }
+
+            comms::DaemonCommand::ListLayers() => {
+                EFFECT_MANAGER.lock().ok().map(|mut k| comms::DaemonResponse::ListLayers { layers: k.list_layers() })
+            }
+            comms::DaemonCommand::AddLayer { position, name, params, key_mask } => {
+                match kbd::registry::create_effect(&name, params) {
+                    Ok(effect) => {
+                        let mut id = None;
+                        if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                            let key_mask = key_mask.unwrap_or_else(|| vec![true; 90]);
+                            id = k.insert_effect(effect, position, key_mask);
+                            if id.is_some() {
+                                save_effects(&mut d, &mut k);
+                            }
+                        }
+                        Some(comms::DaemonResponse::AddLayer { id })
+                    }
+                    Err(message) => Some(comms::DaemonResponse::Error { message }),
+                }
+            }
+            comms::DaemonCommand::ReplaceLayer { id, name, params } => {
+                match kbd::registry::create_effect(&name, params) {
+                    Ok(effect) => {
+                        let mut res = false;
+                        if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                            if k.list_layers().last().is_some_and(|top| top.id == id) {
+                                k.crossfade();
+                            }
+                            res = k.replace_effect(id, effect);
+                            if res {
+                                save_effects(&mut d, &mut k);
+                            }
+                        }
+                        Some(comms::DaemonResponse::ReplaceLayer { result: res })
+                    }
+                    Err(message) => Some(comms::DaemonResponse::Error { message }),
+                }
+            }
+            comms::DaemonCommand::MoveLayer { id, position } => {
                 let mut res = false;
                 if let Ok(mut k) = EFFECT_MANAGER.lock() {
-                    res = true;
-                    let effect = match name.as_str() {
-                        "static" => Some(kbd::effects::Static::new(params)),
-                        "static_gradient" => Some(kbd::effects::StaticGradient::new(params)),
-                        "wave_gradient" => Some(kbd::effects::WaveGradient::new(params)),
-                        "breathing_single" => Some(kbd::effects::BreathSingle::new(params)),
-                        _ => None
-                    };
-
-                    if let Some(laptop) = d.get_device() {
-                        if let Some(e) = effect {
-                            k.pop_effect(laptop); // Remove old layer
-                            k.push_effect(
-                                e,
-                                [true; 90]
-                                );
-                        } else {
-                            res = false
+                    res = k.move_layer(id, position);
+                    if res {
+                        save_effects(&mut d, &mut k);
+                    }
+                }
+                Some(comms::DaemonResponse::MoveLayer { result: res })
+            }
+            comms::DaemonCommand::RemoveLayer { id } => {
+                let mut res = false;
+                if let Some(laptop) = d.get_device() {
+                    if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                        res = k.remove_layer(id, laptop);
+                        if res {
+                            save_effects(&mut d, &mut k);
                         }
-                    } else {
-                        res = false;
                     }
                 }
-                Some(comms::DaemonResponse::SetEffect{result: res})
+                Some(comms::DaemonResponse::RemoveLayer { result: res })
             }
-
-            comms::DaemonCommand::SetStandardEffect{ name, params } => {
-                // TODO save standart effect may be struct ?
+            comms::DaemonCommand::SetLayerVisible { id, visible } => {
                 let mut res = false;
                 if let Some(laptop) = d.get_device() {
                     if let Ok(mut k) = EFFECT_MANAGER.lock() {
-                        k.pop_effect(laptop); // Remove old layer
-                        let _res = match name.as_str() {
-                            "off" => d.set_standard_effect(device::RazerLaptop::OFF, params),
-                            "wave" => d.set_standard_effect(device::RazerLaptop::WAVE, params),
-                            "reactive" => d.set_standard_effect(device::RazerLaptop::REACTIVE, params),
-                            "breathing" => d.set_standard_effect(device::RazerLaptop::BREATHING, params),
-                            "spectrum" => d.set_standard_effect(device::RazerLaptop::SPECTRUM, params),
-                            "static" => d.set_standard_effect(device::RazerLaptop::STATIC, params),
-                            "starlight" => d.set_standard_effect(device::RazerLaptop::STARLIGHT, params), 
-                            _ => false,
-                        };
-                        res = _res;
+                        res = k.set_layer_visible(id, visible, laptop);
+                        if res {
+                            save_effects(&mut d, &mut k);
+                        }
+                    }
+                }
+                Some(comms::DaemonResponse::SetLayerVisible { result: res })
+            }
+            comms::DaemonCommand::SetAnimationFps { ac, fps } => {
+                Some(comms::DaemonResponse::SetAnimationFps { result: d.set_animation_fps(ac, fps) })
+            }
+            comms::DaemonCommand::GetAnimationFps { ac } => {
+                Some(comms::DaemonResponse::GetAnimationFps { fps: d.get_animation_fps(ac) })
+            }
+            comms::DaemonCommand::SetKeyEvents { enabled } => {
+                Some(comms::DaemonResponse::SetKeyEvents { result: d.set_key_events(enabled) })
+            }
+            comms::DaemonCommand::GetKeyEvents() => {
+                Some(comms::DaemonResponse::GetKeyEvents { enabled: d.get_key_events() })
+            }
+            comms::DaemonCommand::StartStream { .. } => {
+                // Handled before getting here, as it keeps the connection
+                Some(comms::DaemonResponse::Error { message: "streams need their own connection".into() })
+            }
+            comms::DaemonCommand::SetOpenRgb { port } => {
+                Some(comms::DaemonResponse::SetOpenRgb { result: d.set_openrgb_port(port) })
+            }
+            comms::DaemonCommand::GetOpenRgb() => {
+                Some(comms::DaemonResponse::GetOpenRgb { port: d.get_openrgb_port() })
+            }
+            comms::DaemonCommand::Preview { effects, frames, frame_ms } => {
+                let save = match effects {
+                    Some(json) => serde_json::from_str(&json).map_err(|e| format!("Invalid effects: {}", e)),
+                    None => Ok(EFFECT_MANAGER.lock().unwrap().save()),
+                };
+                match save.and_then(|save| kbd::render_preview(save, frames, frame_ms)) {
+                    Ok(rendered) => Some(comms::DaemonResponse::Preview {
+                        frames: rendered.into_iter().map(|mut kbd| kbd.get_curr_state()).collect(),
+                    }),
+                    Err(message) => Some(comms::DaemonResponse::Error { message }),
+                }
+            }
+            comms::DaemonCommand::SetColourCorrection { correction } => {
+                let result = d.set_colour_correction(correction);
+                if result {
+                    EFFECT_MANAGER.lock().unwrap().invalidate();
+                }
+                Some(comms::DaemonResponse::SetColourCorrection { result })
+            }
+            comms::DaemonCommand::GetColourCorrection() => {
+                Some(comms::DaemonResponse::GetColourCorrection { correction: d.get_colour_correction() })
+            }
+            comms::DaemonCommand::SetTransitions { ms } => {
+                Some(comms::DaemonResponse::SetTransitions { result: d.set_transition_ms(ms) })
+            }
+            comms::DaemonCommand::GetTransitions() => {
+                Some(comms::DaemonResponse::GetTransitions { ms: d.get_transition_ms() })
+            }
+            comms::DaemonCommand::SetSchedule { rules, location } => {
+                match d.set_schedule(rules, location) {
+                    Ok(()) => Some(comms::DaemonResponse::SetSchedule { result: true }),
+                    Err(message) => Some(comms::DaemonResponse::Error { message }),
+                }
+            }
+            comms::DaemonCommand::GetSchedule() => {
+                let (rules, location) = d.get_schedule();
+                Some(comms::DaemonResponse::GetSchedule { rules, location, active: d.get_active_rule() })
+            }
+            comms::DaemonCommand::ValidateAnimation { animation } => {
+                let error = kbd::timeline::Timeline::parse(&animation).err();
+                Some(comms::DaemonResponse::ValidateAnimation { error })
+            }
+            comms::DaemonCommand::SetLayerBlend { id, blend, opacity } => {
+                let mut res = false;
+                if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                    res = k.set_layer_blend(id, blend, opacity);
+                    if res {
+                        save_effects(&mut d, &mut k);
+                    }
+                }
+                Some(comms::DaemonResponse::SetLayerBlend { result: res })
+            }
+            comms::DaemonCommand::SetLayerMask { id, key_mask } => {
+                let mut res = false;
+                if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                    res = k.set_layer_mask(id, key_mask);
+                    if res {
+                        save_effects(&mut d, &mut k);
                     }
+                }
+                Some(comms::DaemonResponse::SetLayerMask { result: res })
+            }
+
+            comms::DaemonCommand::SetStandardEffect { effect } => {
+                if let Err(message) = effect.validate() {
+                    Some(comms::DaemonResponse::Error { message })
                 } else {
-                    res = false;
+                    let mut res = false;
+                    if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                        k.show_standard_effect(); // Custom effects would draw over it
+                        res = d.set_standard_effect(effect);
+                        save_effects(&mut d, &mut k);
+                    }
+                    Some(comms::DaemonResponse::SetStandardEffect { result: res })
                 }
-                Some(comms::DaemonResponse::SetStandardEffect{result: res})
             }
             comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold } => { 
-                return Some(comms::DaemonResponse::SetBatteryHealthOptimizer { result: d.set_bho_handler(is_on, threshold)});
+                Some(comms::DaemonResponse::SetBatteryHealthOptimizer { result: d.set_bho_handler(is_on, threshold)})
             }
             comms::DaemonCommand::GetBatteryHealthOptimizer() => {
-                return d.get_bho_handler().map(|result| 
+                d.get_bho_handler().map(|result| 
                     comms::DaemonResponse::GetBatteryHealthOptimizer {
                         is_on: (result.0), 
                         threshold: (result.1) 
                     }
-                );
+                )
             }
             comms::DaemonCommand::GetDeviceName => {
                 let name = match &d.device {
                     Some(device) => device.get_name(),
                     None => "Unknown Device".into()
                 };
-                return Some(comms::DaemonResponse::GetDeviceName { name });
+                Some(comms::DaemonResponse::GetDeviceName { name })
+            }
+            comms::DaemonCommand::CreateProfile { ac, name, with_effects } => {
+                let effects = match with_effects {
+                    true => Some(EFFECT_MANAGER.lock().unwrap().save()),
+                    false => None,
+                };
+                Some(comms::DaemonResponse::CreateProfile { result: d.create_profile(ac, name, effects) })
+            }
+            comms::DaemonCommand::ListProfiles() => {
+                let (profiles, default_profiles) = d.get_profiles();
+                Some(comms::DaemonResponse::ListProfiles { profiles, default_profiles })
+            }
+            comms::DaemonCommand::RenameProfile { name, new_name } => {
+                Some(comms::DaemonResponse::RenameProfile { result: d.rename_profile(&name, new_name) })
+            }
+            comms::DaemonCommand::DeleteProfile { name } => {
+                Some(comms::DaemonResponse::DeleteProfile { result: d.delete_profile(&name) })
+            }
+            comms::DaemonCommand::ActivateProfile { ac, name } => {
+                let profile = d.activate_profile(ac, &name);
+                let mut result = profile.is_some();
+                if let Some(effects) = profile.and_then(|p| p.effects) {
+                    let mut k = EFFECT_MANAGER.lock().unwrap();
+                    result = k.load_from_save(effects);
+                    if result {
+                        save_effects(&mut d, &mut k);
+                    }
+                }
+                Some(comms::DaemonResponse::ActivateProfile { result })
+            }
+            comms::DaemonCommand::SetDefaultProfile { ac, name } => {
+                Some(comms::DaemonResponse::SetDefaultProfile { result: d.set_default_profile(ac, name) })
+            }
+            comms::DaemonCommand::ExportBundle() => {
+                let effects = EFFECT_MANAGER.lock().unwrap().save();
+                let bundle = d.export_bundle(effects)
+                    .and_then(|b| serde_json::to_string_pretty(&b).ok());
+                Some(comms::DaemonResponse::ExportBundle { bundle })
+            }
+            comms::DaemonCommand::ImportBundle { bundle } => {
+                match config::Bundle::parse(&bundle) {
+                    Ok(mut bundle) => {
+                        let mut notes = d.import_bundle(&mut bundle);
+                        let (effects, effect_notes) = kbd::fit_save(&bundle.effects);
+                        notes.extend(effect_notes);
+                        let mut k = EFFECT_MANAGER.lock().unwrap();
+                        let loaded = k.load_from_save(effects);
+                        if loaded {
+                            save_effects(&mut d, &mut k);
+                        } else {
+                            notes.push("The effects can't be loaded, the current ones were kept".into());
+                        }
+                        k.invalidate(); // The standard effect was restored over it
+                        Some(comms::DaemonResponse::ImportBundle { result: loaded, notes })
+                    }
+                    Err(error) => Some(comms::DaemonResponse::ImportBundle { result: false, notes: vec![error] }),
+                }
             }
 
         };
+        // The change was applied, but the client must know it will not survive a restart
+        if let Some(error) = d.take_write_error() {
+            return Some(comms::DaemonResponse::Error { message: format!("Failed to save settings: {}", error) });
+        }
+        return response;
     } else {
         return None;
     }
@@ -1,11 +1,16 @@
 // mod kbd;
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
+use std::collections::HashMap;
 use std::{thread, time, io, fs};
 use hidapi::HidApi;
 use crate::dbus_mutter_idlemonitor;
 use crate::config;
 use crate::battery;
+use crate::kbd;
+use crate::comms;
+use crate::schedule;
+use log::*;
 use dbus::blocking::Connection;
 
 const RAZER_VENDOR_ID: u16 = 0x1532;
@@ -17,6 +22,8 @@ pub struct SupportedDevice {
     pub pid: String,
     pub features: Vec<String>,
     pub fan: Vec<u16>,
+    #[serde(default)]
+    pub keymap: HashMap<u16, u8>, // Linux key code -> key index, where it differs from the default layout
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -73,6 +80,43 @@ impl RazerPacket {
 }
 
 const DEVICE_FILE: &str = "/usr/share/razercontrol/laptops.json";
+/// How far above the threshold the battery has to charge to end low battery
+/// mode, so it doesn't flap around the threshold
+const LOW_BATTERY_HYSTERESIS: f64 = 5.0;
+/// Settings of the active schedule rule, used instead of the ones of the
+/// current AC state. A manual change of a setting replaces the rule's value
+#[derive(Default, Copy, Clone)]
+pub struct PowerOverride {
+    pub brightness: Option<u8>,
+    pub logo_state: Option<u8>,
+    pub logo_brightness: Option<u8>,
+    pub power: Option<(u8, u8, u8)>,
+    pub fan_rpm: Option<i32>,
+}
+
+/// Brightness fade in progress
+struct BrightnessFade {
+    from: u8,
+    to: u8,
+    start: time::Instant,
+    duration: time::Duration,
+    logo_state: Option<u8>, // Set once the fade is done
+}
+
+impl BrightnessFade {
+    fn progress(&self) -> f32 {
+        if self.duration.is_zero() {
+            return 1.0;
+        }
+        (self.start.elapsed().as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+    }
+
+    fn level(&self) -> u8 {
+        let (from, to) = (self.from as f32, self.to as f32);
+        (from + (to - from) * self.progress()).round() as u8
+    }
+}
+
 pub struct DeviceManager {
     pub device: Option <RazerLaptop>,
     supported_devices: Vec<SupportedDevice>,
@@ -81,6 +125,13 @@ pub struct DeviceManager {
     pub active_id: u32,
     add_active: bool,
     pub change_idle: bool,
+    write_error: Option<String>, // last failed config write, reported to the client
+    fade: Option<BrightnessFade>,
+    power_override: PowerOverride,
+    active_rule: Option<String>, // name of the active schedule rule
+    battery_percentage: Option<f64>, // None until UPower reports it
+    low_battery: bool,
+    openrgb_logo: Option<u8>, // logo state of an OpenRGB session, not saved
 }
 
 impl DeviceManager {
@@ -93,6 +144,13 @@ impl DeviceManager {
             active_id: 0,
             add_active: false,
             change_idle: false,
+            write_error: None,
+            fade: None,
+            power_override: PowerOverride::default(),
+            active_rule: None,
+            battery_percentage: None,
+            low_battery: false,
+            openrgb_logo: None,
         };
     }
 
@@ -129,18 +187,56 @@ impl DeviceManager {
             ac = laptop.ac_state as usize;
         }
         let other = (ac + 1) & 0x01;
-        if let Some(config) = self.get_config() {
+        if let Some(config) = self.config.as_mut() {
             config.sync = sync;
             config.power[other].brightness = config.power[ac].brightness;
             config.power[other].logo_state = config.power[ac].logo_state;
+            config.power[other].logo_brightness = config.power[ac].logo_brightness;
+            config.power[other].logo_on_idle = config.power[ac].logo_on_idle;
             config.power[other].screensaver = config.power[ac].screensaver;
             config.power[other].idle = config.power[ac].idle;
+            self.save_config();
+        }
+
+        return true;
+    }
+
+    /// Writes the configuration to disk, keeping a failure to report it to the client
+    fn save_config(&mut self) {
+        if let Some(config) = self.config.as_mut() {
             if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
+                error!("Error writing config {}", e);
+                self.write_error = Some(e.to_string());
             }
         }
+    }
 
-        return true;
+    /// Returns the error of a config write that happened since the last call
+    pub fn take_write_error(&mut self) -> Option<String> {
+        self.write_error.take()
+    }
+
+    /// Records a failed write of another file (like the effects save) so it
+    /// is reported together with config write errors
+    pub fn record_write_error(&mut self, error: String) {
+        self.write_error = Some(error);
+    }
+
+    /// Replaces the configuration with one edited outside of the daemon and
+    /// applies the settings of the current AC state
+    pub fn reload_config(&mut self, config: config::Configuration) {
+        let effect_changed = match self.get_config() {
+            Some(old) => old.standard_effect != config.standard_effect,
+            None => true,
+        };
+        self.config = Some(config);
+        self.change_idle = true;
+        self.fade = None;
+        self.apply_colour_correction();
+        self.apply_current_config();
+        if effect_changed {
+            self.restore_standard_effect();
+        }
     }
 
     pub fn get_sync(&mut self) -> bool {
@@ -151,6 +247,131 @@ impl DeviceManager {
         return false;
     }
 
+    /// Enables forwarding key presses to custom effects
+    pub fn set_key_events(&mut self, enabled: bool) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            config.key_events = enabled;
+            self.save_config();
+        }
+        true
+    }
+
+    pub fn get_key_events(&mut self) -> bool {
+        self.get_config().is_some_and(|config| config.key_events)
+    }
+
+    pub fn set_lock_indicators(&mut self, indicators: comms::LockIndicators) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            config.lock_indicators = indicators;
+            self.save_config();
+        }
+        true
+    }
+
+    pub fn get_lock_indicators(&mut self) -> comms::LockIndicators {
+        self.get_config().map(|config| config.lock_indicators).unwrap_or_default()
+    }
+
+    pub fn set_notifications(&mut self, enabled: bool) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            config.notifications = enabled;
+            self.save_config();
+        }
+        true
+    }
+
+    pub fn set_notification_rules(&mut self, rules: Vec<comms::NotificationRule>) -> Result<(), String> {
+        for (idx, rule) in rules.iter().enumerate() {
+            if rule.urgency.is_some_and(|urgency| urgency > 2) {
+                return Err(format!("rule {}: urgency goes from 0 (low) to 2 (critical)", idx));
+            }
+            kbd::timeline::parse_selector(&rule.keys).map_err(|e| format!("rule {}: {}", idx, e))?;
+        }
+        if let Some(config) = self.config.as_mut() {
+            config.notification_rules = rules;
+            self.save_config();
+        }
+        Ok(())
+    }
+
+    pub fn set_do_not_disturb(&mut self, enabled: bool) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            config.do_not_disturb = enabled;
+            self.save_config();
+        }
+        true
+    }
+
+    /// Whether notifications are enabled, do not disturb and the rules
+    pub fn get_notifications(&mut self) -> (bool, bool, Vec<comms::NotificationRule>) {
+        match self.get_config() {
+            Some(config) => (config.notifications, config.do_not_disturb, config.notification_rules.clone()),
+            None => (false, false, vec![]),
+        }
+    }
+
+    /// The rule for a notification of `app`, None if it shouldn't be shown
+    pub fn match_notification(&mut self, app: &str, urgency: u8) -> Option<comms::NotificationRule> {
+        let config = self.get_config()?;
+        if !config.notifications || config.do_not_disturb {
+            return None;
+        }
+        config.notification_rules.iter().find(|rule| {
+            rule.app.as_ref().is_none_or(|name| name.eq_ignore_ascii_case(app)) && rule.urgency.is_none_or(|u| u == urgency)
+        }).cloned()
+    }
+
+    /// Starts the OpenRGB SDK server on `port`, or stops it if None
+    pub fn set_openrgb_port(&mut self, port: Option<u16>) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            config.openrgb_port = port;
+            self.save_config();
+        }
+        true
+    }
+
+    pub fn get_openrgb_port(&mut self) -> Option<u16> {
+        self.get_config().and_then(|config| config.openrgb_port)
+    }
+
+    /// Stores the colour correction of the current laptop model and applies it
+    /// to custom effects. Returns false if it is out of range or there is no laptop
+    pub fn set_colour_correction(&mut self, correction: comms::ColourCorrection) -> bool {
+        if !correction.is_valid() {
+            return false;
+        }
+        let name = match self.get_device() {
+            Some(laptop) => laptop.get_name(),
+            None => return false,
+        };
+        if let Some(config) = self.config.as_mut() {
+            config.colour_correction.insert(name, correction);
+            self.save_config();
+        }
+        self.apply_colour_correction();
+        true
+    }
+
+    pub fn get_colour_correction(&mut self) -> comms::ColourCorrection {
+        let name = self.get_device().map(|laptop| laptop.get_name()).unwrap_or_default();
+        self.get_config()
+            .and_then(|config| config.colour_correction.get(&name).copied())
+            .unwrap_or_default()
+    }
+
+    /// Passes the stored colour correction of the laptop model to the laptop
+    fn apply_colour_correction(&mut self) {
+        let correction = self.get_colour_correction();
+        if let Some(laptop) = self.get_device() {
+            laptop.set_colour_correction(&correction);
+        }
+    }
+
+    /// Key map of the laptop, None if there is no supported laptop
+    pub fn get_keymap(&mut self) -> Option<HashMap<u16, usize>> {
+        self.get_device().map(|laptop| laptop.get_keymap())
+    }
+
     fn remove_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
         if let Ok(_) = proxy_idle.remove_watch(self.idle_id) {
             println!("remove idle handler");
@@ -179,23 +400,188 @@ impl DeviceManager {
         Ok(res)
     }
 
+    /// Settings of an AC state, with the ones of the active schedule rule
+    /// if it is the current state, and the low battery ones over them
     fn get_ac_config(&mut self, ac: usize) -> Option<config::PowerConfig> {
-        if let Some(c) = self.get_config() {
-            return Some(c.power[ac]);
+        let current = self.get_device().is_some_and(|laptop| laptop.get_ac_state() == ac);
+        let over = self.power_override;
+        let openrgb_logo = self.openrgb_logo;
+        let low_battery = self.low_battery && ac == 0;
+        let config = self.get_config()?;
+        let actions = config.low_battery;
+        let mut power = config.power[ac];
+        if current {
+            power.brightness = over.brightness.unwrap_or(power.brightness);
+            power.logo_state = over.logo_state.unwrap_or(power.logo_state);
+            power.logo_brightness = over.logo_brightness.unwrap_or(power.logo_brightness);
+            if let Some((pwr, cpu, gpu)) = over.power {
+                power.power_mode = pwr;
+                power.cpu_boost = cpu;
+                power.gpu_boost = gpu;
+            }
+            power.fan_rpm = over.fan_rpm.unwrap_or(power.fan_rpm);
+            power.logo_state = openrgb_logo.unwrap_or(power.logo_state);
         }
+        if current && low_battery {
+            apply_low_battery(&mut power, &actions);
+        }
+        Some(power)
+    }
 
-        return None;
+    pub fn set_low_battery(&mut self, threshold: f64, actions: comms::LowBattery) -> Result<(), String> {
+        config::validate_low_battery(threshold, &actions)?;
+        if let Some(config) = self.config.as_mut() {
+            config.no_light = threshold;
+            config.low_battery = actions;
+            self.save_config();
+        }
+        let was_low = self.low_battery;
+        if !self.update_low_battery(None) && was_low {
+            self.apply_current_config(); // Changed while it is low
+        }
+        Ok(())
+    }
+
+    pub fn get_low_battery(&mut self) -> (f64, comms::LowBattery, bool) {
+        let low_battery = self.low_battery;
+        self.get_config().map_or((0.0, comms::LowBattery::default(), false), |config| {
+            (config.no_light, config.low_battery, low_battery)
+        })
+    }
+
+    pub fn is_low_battery(&self) -> bool {
+        self.low_battery
+    }
+
+    /// Checks the battery against the low battery threshold, with the new
+    /// `percentage` if UPower reported one, and applies the settings if low
+    /// battery mode started or ended. Returns true if it did
+    pub fn update_low_battery(&mut self, percentage: Option<f64>) -> bool {
+        self.battery_percentage = percentage.or(self.battery_percentage);
+        let threshold = self.get_config().map_or(0.0, |config| config.no_light);
+        let on_battery = self.get_device().is_some_and(|laptop| laptop.get_ac_state() == 0);
+        let limit = if self.low_battery { threshold + LOW_BATTERY_HYSTERESIS } else { threshold };
+        let low = on_battery && threshold > 0.0 && self.battery_percentage.is_some_and(|p| p < limit);
+        if low == self.low_battery {
+            return false;
+        }
+        self.low_battery = low;
+        if on_battery {
+            self.apply_current_config();
+        }
+        true
+    }
+
+    /// Sends the settings of the current AC state to the laptop, with the
+    /// schedule and low battery ones over them. Whole settings are only ever
+    /// applied here, so neither is lost on profile or AC changes
+    fn apply_current_config(&mut self) {
+        self.fade = None;
+        let ac = match self.get_device() {
+            Some(laptop) => laptop.get_ac_state(),
+            None => return,
+        };
+        if let Some(config) = self.get_ac_config(ac) {
+            if let Some(laptop) = self.get_device() {
+                laptop.set_config(config);
+            }
+        }
+    }
+
+    /// Returns true if `ac` is the current AC state, for settings the active
+    /// schedule rule may override
+    fn is_current_ac(&mut self, ac: usize) -> bool {
+        self.get_device().is_some_and(|laptop| laptop.get_ac_state() == ac)
+    }
+
+    pub fn set_schedule(&mut self, rules: Vec<comms::ScheduleRule>, location: Option<[f64; 2]>) -> Result<(), String> {
+        schedule::validate(&rules, location)?;
+        if !self.has_logo_spectrum() {
+            let spectrum = comms::ScheduleAction::Logo { state: 3 };
+            if let Some(rule) = rules.iter().find(|rule| rule.actions.contains(&spectrum)) {
+                return Err(format!("rule {:?}: the logo of this laptop can't show spectrum", rule.name));
+            }
+        }
+        if let Some(config) = self.config.as_mut() {
+            config.schedule = rules;
+            config.location = location;
+            self.save_config();
+        }
+        Ok(())
+    }
+
+    pub fn get_schedule(&mut self) -> (Vec<comms::ScheduleRule>, Option<[f64; 2]>) {
+        match self.get_config() {
+            Some(config) => (config.schedule.clone(), config.location),
+            None => (vec![], None),
+        }
+    }
+
+    pub fn get_active_rule(&mut self) -> Option<String> {
+        self.active_rule.clone()
     }
 
-    pub fn light_off(&mut self) {
+    /// Applies the settings of the schedule rule that became active, or
+    /// the ones of the AC state again when `rule` is None
+    pub fn set_active_rule(&mut self, rule: Option<String>, power_override: PowerOverride) {
+        self.active_rule = rule;
+        self.power_override = power_override;
+        self.apply_current_config();
+    }
+
+    /// Shows the logo state of an OpenRGB session without saving it, or the
+    /// one of the settings again if None
+    pub fn set_openrgb_logo(&mut self, state: Option<u8>) {
+        if self.openrgb_logo == state {
+            return;
+        }
+        self.openrgb_logo = state;
+        let ac = match self.get_device() {
+            Some(laptop) => laptop.get_ac_state(),
+            None => return,
+        };
+        if let Some(config) = self.get_ac_config(ac) {
+            if let Some(laptop) = self.get_device() {
+                // Shown when the keyboard lights up again
+                if !laptop.screensaver {
+                    laptop.set_logo_led_state(config.logo_state);
+                }
+            }
+        }
+    }
+
+    pub fn get_openrgb_logo(&mut self) -> Option<u8> {
+        self.openrgb_logo
+    }
+
+    /// A copy of a profile, None if there is no profile with that name
+    pub fn get_profile(&mut self, name: &str) -> Option<config::Profile> {
+        self.get_config()?.get_profile(name).cloned()
+    }
+
+    /// Turns the keyboard off on idle, screensaver or `sleep`. The logo goes
+    /// off with it, unless it is set to stay on while idle. Before sleep it
+    /// all goes off at once, the laptop could suspend in the middle of a fade
+    pub fn light_off(&mut self, sleep: bool) {
         if self.idle_id != 0 {
             self.add_active = true;
         }
+        let mut ac: usize = 0;
         if let Some(laptop) = self.get_device() {
             laptop.set_screensaver(true);
-            laptop.set_brightness(0);
-            laptop.set_logo_led_state(0);
+            ac = laptop.get_ac_state();
         }
+        if sleep {
+            self.fade = None;
+            if let Some(laptop) = self.get_device() {
+                laptop.set_brightness(0);
+                laptop.set_logo_led_state(0);
+            }
+            return;
+        }
+        let keep_logo = self.get_ac_config(ac).is_some_and(|config| config.logo_on_idle);
+        // The logo can't fade, it goes off with the keyboard
+        self.fade_brightness(0, if keep_logo { None } else { Some(0) });
     }
 
     pub fn restore_light(&mut self) {
@@ -212,35 +598,294 @@ impl DeviceManager {
         }
         if let Some(laptop) = self.get_device() {
             laptop.set_screensaver(false);
-            laptop.set_brightness(brightness);
             laptop.set_logo_led_state(logo_state);
         }
+        self.fade_brightness(brightness, None);
+    }
+
+    pub fn set_transition_ms(&mut self, ms: u32) -> bool {
+        if ms > config::MAX_TRANSITION_MS {
+            return false;
+        }
+        if let Some(config) = self.config.as_mut() {
+            config.transition_ms = ms;
+            self.save_config();
+        }
+        true
+    }
+
+    pub fn get_transition_ms(&mut self) -> u32 {
+        self.get_config().map_or(0, |config| config.transition_ms)
+    }
+
+    /// Fades the keyboard brightness to `target`, then sets the logo to
+    /// `logo_state`. Starts from the current level, so a fade that is
+    /// interrupted reverses smoothly
+    fn fade_brightness(&mut self, target: u8, logo_state: Option<u8>) {
+        let transition_ms = self.get_transition_ms();
+        let from = match self.fade.as_ref() {
+            Some(fade) => fade.level(),
+            None => match self.get_device() {
+                Some(laptop) => laptop.get_brightness(),
+                None => return,
+            },
+        };
+        // A full fade between off and `target` takes the whole transition,
+        // the rest of an interrupted one takes what is left of it
+        let distance = (target as i32 - from as i32).unsigned_abs();
+        let range = (target.max(from) as u32).max(1);
+        self.fade = Some(BrightnessFade {
+            from,
+            to: target,
+            start: time::Instant::now(),
+            duration: time::Duration::from_millis((transition_ms * distance / range) as u64),
+            logo_state,
+        });
+        self.step_brightness_fade();
+    }
+
+    /// Sets the brightness of the fade in progress. Returns true while fading
+    pub fn step_brightness_fade(&mut self) -> bool {
+        let fade = match self.fade.as_ref() {
+            Some(fade) => fade,
+            None => return false,
+        };
+        let (level, done, logo_state) = (fade.level(), fade.progress() >= 1.0, fade.logo_state);
+        if let Some(laptop) = self.get_device() {
+            laptop.set_brightness(level);
+            if done {
+                if let Some(logo_state) = logo_state {
+                    laptop.set_logo_led_state(logo_state);
+                }
+            }
+        }
+        if done {
+            self.fade = None;
+        }
+        !done
     }
 
     pub fn restore_standard_effect(&mut self) {
-        let mut effect = 0;
-        let mut params: Vec<u8> = vec![];
+        let effect = self.get_standard_effect();
+        if let Some(laptop) = self.get_device() {
+            laptop.set_standard_effect(&effect);
+        }
+    }
+
+    /// Saves the current settings of the given AC state as a named profile,
+    /// replacing any profile with the same name
+    pub fn create_profile(&mut self, ac: usize, name: String, effects: Option<serde_json::Value>) -> bool {
+        if name.is_empty() {
+            return false;
+        }
+        if let Some(config) = self.config.as_mut() {
+            let profile = config::Profile { name, power: config.power[ac], effects };
+            match config.profiles.iter_mut().find(|p| p.name == profile.name) {
+                Some(p) => *p = profile,
+                None => config.profiles.push(profile),
+            }
+            self.save_config();
+            return true;
+        }
+
+        false
+    }
+
+    pub fn get_profiles(&mut self) -> (Vec<String>, [Option<String>; 2]) {
         if let Some(config) = self.get_config() {
-            effect = config.standard_effect;
-            params = config.standard_effect_params.clone();
+            let names = config.profiles.iter().map(|p| p.name.clone()).collect();
+            return (names, config.default_profiles.clone());
         }
-        if let Some(laptop) = self.get_device() {
-            laptop.set_standard_effect(effect, params);
+
+        (vec![], [None, None])
+    }
+
+    pub fn rename_profile(&mut self, name: &str, new_name: String) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            if new_name.is_empty() || config.get_profile(&new_name).is_some() {
+                return false;
+            }
+            if let Some(profile) = config.profiles.iter_mut().find(|p| p.name == name) {
+                profile.name = new_name.clone();
+                for default in config.default_profiles.iter_mut() {
+                    if default.as_deref() == Some(name) {
+                        *default = Some(new_name.clone());
+                    }
+                }
+                self.save_config();
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn delete_profile(&mut self, name: &str) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            let count = config.profiles.len();
+            config.profiles.retain(|p| p.name != name);
+            if config.profiles.len() == count {
+                return false;
+            }
+            for default in config.default_profiles.iter_mut() {
+                if default.as_deref() == Some(name) {
+                    *default = None;
+                }
+            }
+            self.save_config();
+            return true;
+        }
+
+        false
+    }
+
+    /// Copies the profile's power settings into the given AC state, applying
+    /// them to the laptop if that state is the current one. The profile owns
+    /// the state from then on: settings changed by hand afterwards are kept
+    /// until a profile is activated again, which replaces them. The applied
+    /// profile is returned so the caller can load its effect stack
+    pub fn activate_profile(&mut self, ac: usize, name: &str) -> Option<config::Profile> {
+        let mut profile: Option<config::Profile> = None;
+        if let Some(config) = self.config.as_mut() {
+            profile = config.get_profile(name).cloned();
+            if let Some(p) = &profile {
+                let before = config.power;
+                config.power[ac] = p.power;
+                if config.sync {
+                    let other = (ac + 1) & 0x01;
+                    config.power[other].brightness = p.power.brightness;
+                    config.power[other].logo_state = p.power.logo_state;
+                    config.power[other].logo_brightness = p.power.logo_brightness;
+                    config.power[other].logo_on_idle = p.power.logo_on_idle;
+                    config.power[other].screensaver = p.power.screensaver;
+                    config.power[other].idle = p.power.idle;
+                }
+                if config.power != before {
+                    self.save_config();
+                }
+            }
+        }
+        let profile = profile?;
+        self.change_idle = true;
+        if self.is_current_ac(ac) {
+            self.apply_current_config();
         }
+
+        Some(profile)
+    }
+
+    pub fn set_default_profile(&mut self, ac: usize, name: Option<String>) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            if let Some(n) = &name {
+                if config.get_profile(n).is_none() {
+                    return false;
+                }
+            }
+            config.default_profiles[ac] = name;
+            self.save_config();
+            return true;
+        }
+
+        false
+    }
+
+    /// Activates the profile mapped to the given AC state, if there is one.
+    /// Called on every switch to that state, so the profile replaces any
+    /// settings of the state changed by hand since the last switch
+    pub fn apply_default_profile(&mut self, ac: usize) -> Option<config::Profile> {
+        let name = self.get_config()?.default_profiles[ac].clone()?;
+        println!("applying default profile {:?}", name);
+        self.activate_profile(ac, &name)
+    }
+
+    pub fn export_bundle(&mut self, effects: serde_json::Value) -> Option<config::Bundle> {
+        let device = self.get_device()?.get_name();
+        let bho = self.get_bho_handler()
+            .map(|(is_on, threshold)| config::BhoConfig { is_on, threshold });
+        let config = self.get_config()?;
+        Some(config::Bundle {
+            format: config::BUNDLE_FORMAT.into(),
+            version: config::BUNDLE_VERSION,
+            device,
+            power: config.power,
+            sync: config.sync,
+            profiles: config.profiles.clone(),
+            bho,
+            standard_effect: config.standard_effect,
+            effects,
+        })
+    }
+
+    /// Applies the settings of a bundle, adjusting anything this laptop does
+    /// not support. The effects are left to the caller. Returns a description
+    /// of every adjustment made
+    pub fn import_bundle(&mut self, bundle: &mut config::Bundle) -> Vec<String> {
+        let mut notes: Vec<String> = vec![];
+        let laptop = match self.get_device() {
+            Some(laptop) => laptop,
+            None => return vec!["No laptop found".into()],
+        };
+        let name = laptop.get_name();
+        let can_boost = laptop.have_feature("boost".to_string());
+        let has_logo = laptop.have_feature("logo".to_string());
+        let has_logo_spectrum = laptop.have_feature("logo_spectrum".to_string());
+        let has_bho = laptop.have_feature("bho".to_string());
+        let fan_range = laptop.get_fan_range();
+
+        if bundle.device != name {
+            notes.push(format!("Bundle was exported from {}, importing into {}", bundle.device, name));
+        }
+        for (ac, label) in [(0, "battery"), (1, "AC")] {
+            fit_power_config(&mut bundle.power[ac], label, can_boost, has_logo, has_logo_spectrum, fan_range, &mut notes);
+        }
+        for profile in bundle.profiles.iter_mut() {
+            let label = format!("profile {}", profile.name);
+            fit_power_config(&mut profile.power, &label, can_boost, has_logo, has_logo_spectrum, fan_range, &mut notes);
+        }
+
+        if let Some(bho) = bundle.bho {
+            if !has_bho {
+                notes.push("Battery health optimizer is not supported, skipped".into());
+            } else if bho.threshold < 50 || bho.threshold > 80 || bho.threshold % 5 != 0 {
+                notes.push(format!("Invalid battery health optimizer threshold {}, skipped", bho.threshold));
+            } else {
+                self.set_bho_handler(bho.is_on, bho.threshold);
+            }
+        }
+        if let Err(e) = bundle.standard_effect.validate() {
+            notes.push(format!("Invalid standard effect, {}, turned off", e));
+            bundle.standard_effect = comms::StandardEffect::Off;
+        }
+
+        if let Some(config) = self.config.as_mut() {
+            config.power = bundle.power;
+            config.sync = bundle.sync;
+            for profile in bundle.profiles.iter() {
+                match config.profiles.iter_mut().find(|p| p.name == profile.name) {
+                    Some(p) => *p = profile.clone(),
+                    None => config.profiles.push(profile.clone()),
+                }
+            }
+            config.standard_effect = bundle.standard_effect;
+            self.save_config();
+        }
+        self.change_idle = true;
+        self.apply_current_config();
+        self.restore_standard_effect();
+
+        notes
     }
 
     pub fn change_idle(&mut self, ac: usize, timeout: u32) -> bool {
         // let mut arm: bool = false;
-        if let Some(config) = self.get_config() {
+        if let Some(config) = self.config.as_mut() {
             if config.power[ac].idle != timeout {
                 config.power[ac].idle = timeout;
                 if config.sync {
                     let other = (ac + 1) & 0x01;
                     config.power[other].idle = timeout;
                 }
-                if let Err(e) = config.write_to_file() {
-                    eprintln!("Error write config {:?}", e);
-                }
+                self.save_config();
                 // arm = true;
                 self.change_idle = true;
             }
@@ -249,16 +894,50 @@ impl DeviceManager {
         return true;
     }
 
+    /// Sets the frame rate custom effects are rendered at in the given AC
+    /// state. Not synced, so battery can use a lower one
+    pub fn set_animation_fps(&mut self, ac: usize, fps: u8) -> bool {
+        if fps == 0 || fps > config::MAX_ANIMATION_FPS {
+            return false;
+        }
+        if let Some(config) = self.config.as_mut() {
+            config.power[ac].animation_fps = fps;
+            self.save_config();
+            return true;
+        }
+
+        false
+    }
+
+    pub fn get_animation_fps(&mut self, ac: usize) -> u8 {
+        match self.get_ac_config(ac) {
+            Some(config) => config.animation_fps,
+            None => config::PowerConfig::new().animation_fps,
+        }
+    }
+
+    /// Frame rate for the current AC state
+    pub fn get_current_animation_fps(&mut self) -> u8 {
+        let ac = match self.get_device() {
+            Some(laptop) => laptop.get_ac_state(),
+            None => 1,
+        };
+        self.get_animation_fps(ac)
+    }
+
     pub fn set_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> bool {
         let mut res: bool = false;
-        if let Some(config) = self.get_config() {
+        if let Some(config) = self.config.as_mut() {
             config.power[ac].power_mode = pwr;
             config.power[ac].cpu_boost = cpu;
             config.power[ac].gpu_boost = gpu;
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            self.save_config();
         }
+        if self.is_current_ac(ac) {
+            self.power_override.power = None;
+        }
+        // Low battery may keep another power mode
+        let (pwr, cpu, gpu) = self.get_ac_config(ac).map_or((pwr, cpu, gpu), |c| (c.power_mode, c.cpu_boost, c.gpu_boost));
         if let Some(laptop) = self.get_device() {
             let state = laptop.get_ac_state();
             if state != ac {
@@ -271,30 +950,33 @@ impl DeviceManager {
         return res;
     }
 
-    pub fn set_standard_effect(&mut self, effect_id: u8, params: Vec<u8>) -> bool {
-        if let Some(config) = self.get_config() {
-            config.standard_effect = effect_id;
-            config.standard_effect_params = params.clone();
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+    pub fn set_standard_effect(&mut self, effect: comms::StandardEffect) -> bool {
+        if effect.validate().is_err() {
+            return false;
         }
-        if let Some(laptop) = self.get_device() {
-            laptop.set_standard_effect(effect_id, params);
+        if let Some(config) = self.config.as_mut() {
+            config.standard_effect = effect;
+            self.save_config();
         }
+        self.get_device().is_some_and(|laptop| laptop.set_standard_effect(&effect))
+    }
 
-        return true;
+    pub fn get_standard_effect(&mut self) -> comms::StandardEffect {
+        self.get_config().map_or(comms::StandardEffect::Off, |config| config.standard_effect)
     }
 
     pub fn set_fan_rpm(&mut self, ac:usize, rpm: i32) -> bool {
         let mut res: bool = false;
-        if let Some(config) = self.get_config() {
+        if let Some(config) = self.config.as_mut() {
             config.power[ac].fan_rpm = rpm;
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            self.save_config();
         }
              
+        if self.is_current_ac(ac) {
+            self.power_override.fan_rpm = None;
+        }
+        // Low battery may keep another fan speed
+        let rpm = self.get_ac_config(ac).map_or(rpm, |c| c.fan_rpm);
         if let Some(laptop) = self.get_device() {
             let state = laptop.get_ac_state();
             if state != ac {
@@ -309,17 +991,24 @@ impl DeviceManager {
 
     pub fn set_logo_led_state(&mut self, ac:usize, logo_state: u8) -> bool {
         let mut res: bool = false;
-        if let Some(config) = self.get_config() {
+        if logo_state > 3 || (logo_state == 3 && !self.has_logo_spectrum()) {
+            return false;
+        }
+        if let Some(config) = self.config.as_mut() {
             config.power[ac].logo_state = logo_state;
             if config.sync {
                 let other = (ac + 1) & 0x01;
                 config.power[other].logo_state = logo_state;
             }
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            self.save_config();
         }
              
+        if self.is_current_ac(ac) {
+            self.power_override.logo_state = None;
+            self.openrgb_logo = None;
+        }
+        // Stays off while the battery is low, if it turns the lights off
+        let logo_state = self.get_ac_config(ac).map_or(logo_state, |config| config.logo_state);
         if let Some(laptop) = self.get_device() {
             let state = laptop.get_ac_state();
            
@@ -340,33 +1029,89 @@ impl DeviceManager {
             // }
         // }
     
-        if let Some(config) = self.get_ac_config(ac) {
-            return config.logo_state;
+        if let Some(config) = self.get_config() {
+            return config.power[ac].logo_state;
         }
 
         return 0;
     }
 
+    /// Returns true if the logo of the laptop can cycle through colours
+    pub fn has_logo_spectrum(&mut self) -> bool {
+        self.get_device().is_some_and(|laptop| laptop.have_feature("logo_spectrum".to_string()))
+    }
+
+    /// Returns true if the laptop has a logo that can be dimmed
+    pub fn has_logo(&mut self) -> bool {
+        self.get_device().is_some_and(|laptop| laptop.have_feature("logo".to_string()))
+    }
+
+    /// Sets the brightness of the logo in %, separate from the keyboard's
+    pub fn set_logo_brightness(&mut self, ac: usize, brightness: u8) -> bool {
+        if brightness > 100 || !self.has_logo() {
+            return false;
+        }
+        let val = (brightness as u16 * 255 / 100) as u8;
+        if let Some(config) = self.config.as_mut() {
+            config.power[ac].logo_brightness = val;
+            if config.sync {
+                config.power[(ac + 1) & 0x01].logo_brightness = val;
+            }
+            self.save_config();
+        }
+        if !self.is_current_ac(ac) {
+            return true;
+        }
+        self.power_override.logo_brightness = None;
+        let val = self.get_ac_config(ac).map_or(val, |config| config.logo_brightness); // Capped while the battery is low
+        self.get_device().is_some_and(|laptop| laptop.set_logo_brightness(val))
+    }
+
+    pub fn get_logo_brightness(&mut self, ac: usize) -> u8 {
+        self.get_config().map_or(0, |config| ((config.power[ac].logo_brightness as u32 * 100 + 127) / 255) as u8)
+    }
+
+    /// Sets whether the logo stays on when the keyboard turns off on idle or
+    /// screensaver. It always turns off on sleep
+    pub fn set_logo_on_idle(&mut self, ac: usize, on: bool) -> bool {
+        if let Some(config) = self.config.as_mut() {
+            config.power[ac].logo_on_idle = on;
+            if config.sync {
+                config.power[(ac + 1) & 0x01].logo_on_idle = on;
+            }
+            self.save_config();
+        }
+        true
+    }
+
+    pub fn get_logo_on_idle(&mut self, ac: usize) -> bool {
+        self.get_config().is_some_and(|config| config.power[ac].logo_on_idle)
+    }
+
     pub fn set_brightness(&mut self, ac:usize, brightness: u8) -> bool {
         let mut res: bool = false;
         let _val = brightness as u16  * 255 / 100;
-        if let Some(config) = self.get_config() {
+        if let Some(config) = self.config.as_mut() {
             config.power[ac].brightness = _val as u8;
             if config.sync {
                 let other = (ac + 1) & 0x01;
                 config.power[other].brightness = _val as u8;
             }
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            self.save_config();
         }
  
+        if self.is_current_ac(ac) {
+            self.fade = None; // Replaced by the new brightness
+            self.power_override.brightness = None;
+        }
+        // Capped while the battery is low
+        let level = self.get_ac_config(ac).map_or(_val as u8, |config| config.brightness);
         if let Some(laptop) = self.get_device() {
             let state = laptop.get_ac_state();
             if state != ac {
                 res = true;
             } else {
-                res = laptop.set_brightness(_val as u8);
+                res = laptop.set_brightness(level);
             }
         }
 
@@ -456,12 +1201,7 @@ impl DeviceManager {
             laptop.set_ac_state(ac);
         }
         self.change_idle = true;
-        let config: Option<config::PowerConfig> = self.get_ac_config(ac as usize);
-        if let Some(config) = config {
-            if let Some(laptop) = self.get_device() {
-                laptop.set_config(config);
-            }
-        }
+        self.apply_current_config(); // The brightness of the new state is set right away
     }
 
     pub fn set_ac_state_get(&mut self) {
@@ -474,12 +1214,7 @@ impl DeviceManager {
                 laptop.set_ac_state(online);
             }
             self.change_idle = true;
-            let config: Option<config::PowerConfig> = self.get_ac_config(online as usize);
-            if let Some(config) = config {
-                if let Some(laptop) = self.get_device() {
-                    laptop.set_config(config);
-                }
-            }
+            self.apply_current_config();
         }
 
     }
@@ -540,8 +1275,10 @@ impl DeviceManager {
                                     supported_device.name.clone(),
                                     supported_device.features.clone(),
                                     supported_device.fan.clone(),
+                                    kbd::keys::build_keymap(&supported_device.keymap),
                                     dev
                                 ));
+                                self.apply_colour_correction();
                                 break;
                             },
                             Err(e) => {
@@ -562,11 +1299,13 @@ pub struct RazerLaptop {
     name: String,
     features: Vec<String>,
     fan: Vec<u16>,
+    keymap: HashMap<u16, usize>,
     device: hidapi::HidDevice,
     power: u8, // need for fan
     fan_rpm: u8, // need for power
     ac_state: u8, // index config array
     screensaver: bool,
+    colour_table: kbd::board::ColourTable, // correction of custom effect colours
 }
 //
 impl RazerLaptop {
@@ -580,24 +1319,24 @@ impl RazerLaptop {
     pub const OFF:u8 = 0x00;
     pub const WAVE:u8 = 0x01;
     pub const REACTIVE:u8 = 0x02; // Afterglo
-    #[allow(dead_code)]
     pub const BREATHING:u8 = 0x03;
     pub const SPECTRUM:u8 = 0x04;
     pub const CUSTOMFRAME:u8 = 0x05;
     pub const STATIC:u8 = 0x06;
-    #[allow(dead_code)]
     pub const STARLIGHT:u8 = 0x19;
 
-    pub fn new(name: String, features: Vec<String>, fan: Vec<u16>, device: hidapi::HidDevice) -> RazerLaptop {
+    pub fn new(name: String, features: Vec<String>, fan: Vec<u16>, keymap: HashMap<u16, usize>, device: hidapi::HidDevice) -> RazerLaptop {
         return RazerLaptop{
             name,
             features,
             fan,
+            keymap,
             device,
             power: 0,
             fan_rpm: 0,
             ac_state: 0,
-            screensaver: false
+            screensaver: false,
+            colour_table: kbd::board::ColourTable::new(&comms::ColourCorrection::default()),
         };
     }
 
@@ -613,7 +1352,10 @@ impl RazerLaptop {
             ret |= self.set_logo_led_state(config.logo_state);
         } else {
             ret |= self.set_brightness(0);
-            ret |= self.set_logo_led_state(0);
+            ret |= self.set_logo_led_state(if config.logo_on_idle { config.logo_state } else { 0 });
+        }
+        if self.have_feature("logo".to_string()) {
+            ret |= self.set_logo_brightness(config.logo_brightness);
         }
         ret |= self.set_power_mode(config.power_mode, config.cpu_boost, config.gpu_boost);
         ret |= self.set_fan_rpm(config.fan_rpm as u16);
@@ -639,6 +1381,23 @@ impl RazerLaptop {
         return self.name.clone();
     }
 
+    pub fn set_colour_correction(&mut self, correction: &comms::ColourCorrection) {
+        self.colour_table = kbd::board::ColourTable::new(correction);
+    }
+
+    pub fn get_colour_table(&self) -> &kbd::board::ColourTable {
+        &self.colour_table
+    }
+
+    /// Linux key code -> key index in the keyboard matrix
+    pub fn get_keymap(&self) -> HashMap<u16, usize> {
+        self.keymap.clone()
+    }
+
+    pub fn get_fan_range(&self) -> (u16, u16) {
+        (self.fan[0], self.fan[1])
+    }
+
     pub fn have_feature(&mut self, fch: String) -> bool {
         return self.features.contains(&fch);
     }
@@ -665,19 +1424,41 @@ impl RazerLaptop {
         return value;
     }
 
-    pub fn set_standard_effect(&mut self, effect_id: u8, params: Vec<u8>) -> bool {
+    pub fn set_standard_effect(&mut self, effect: &comms::StandardEffect) -> bool {
+        let (effect_id, params) = RazerLaptop::standard_effect_args(effect);
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x0a, 80);
-        report.args[0] = effect_id; // effect id
-        if !params.is_empty() {
-            for idx in 0..params.len() {
-                report.args[idx+1] = params[idx];
+        report.args[0] = effect_id;
+        report.args[1..=params.len()].copy_from_slice(&params);
+        self.send_report(report).is_some()
+    }
+
+    /// Effect id and parameters the firmware expects for a standard effect
+    fn standard_effect_args(effect: &comms::StandardEffect) -> (u8, Vec<u8>) {
+        let colours = |colours: &comms::EffectColours| match colours {
+            comms::EffectColours::Single(c) => [&[0x01][..], c].concat(),
+            comms::EffectColours::Dual(c1, c2) => [&[0x02][..], c1, c2].concat(),
+            comms::EffectColours::Random => vec![0x03],
+        };
+        match effect {
+            comms::StandardEffect::Off => (RazerLaptop::OFF, vec![]),
+            comms::StandardEffect::Wave { direction, speed } => {
+                let direction = match direction {
+                    comms::WaveDirection::LeftToRight => 0x01,
+                    comms::WaveDirection::RightToLeft => 0x02,
+                };
+                (RazerLaptop::WAVE, vec![direction, *speed])
+            }
+            comms::StandardEffect::Reactive { speed, colour } => (RazerLaptop::REACTIVE, [&[*speed][..], colour].concat()),
+            comms::StandardEffect::Breathing { colours: c } => (RazerLaptop::BREATHING, colours(c)),
+            comms::StandardEffect::Spectrum => (RazerLaptop::SPECTRUM, vec![]),
+            comms::StandardEffect::Static { colour } => (RazerLaptop::STATIC, colour.to_vec()),
+            comms::StandardEffect::Starlight { speed, colours: c } => {
+                // The speed goes between the kind and the colours
+                let mut args = colours(c);
+                args.insert(1, *speed);
+                (RazerLaptop::STARLIGHT, args)
             }
         }
-        if let Some(_) = self.send_report(report) {
-            return true;
-        }
-
-        return false;
     }
 
     pub fn set_custom_frame_data(&mut self, row: u8, data: Vec<u8>) {
@@ -851,6 +1632,8 @@ impl RazerLaptop {
                 report.args[2] = 0x00;
             } else if mode == 2 {
                 report.args[2] = 0x02;
+            } else if mode == 3 {
+                report.args[2] = 0x04; // spectrum
             }
             self.send_report(report);
         }
@@ -877,6 +1660,14 @@ impl RazerLaptop {
         return 0;
     }
 
+    pub fn set_logo_brightness(&mut self, brightness: u8) -> bool {
+        let mut report: RazerPacket = RazerPacket::new(0x03, 0x03, 0x03);
+        report.args[0] = RazerLaptop::VARSTORE;
+        report.args[1] = RazerLaptop::LOGO_LED;
+        report.args[2] = brightness;
+        self.send_report(report).is_some()
+    }
+
     pub fn set_brightness(&mut self, brightness: u8) -> bool {
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x03, 0x03);
         report.args[0] = RazerLaptop::VARSTORE;
@@ -982,6 +1773,66 @@ impl RazerLaptop {
 
 }
 
+/// Caps the brightness and replaces the power mode and fan speed of a power
+/// config with the ones used while the battery is low
+fn apply_low_battery(power: &mut config::PowerConfig, actions: &comms::LowBattery) {
+    let level = (actions.brightness as u16 * 255 / 100) as u8;
+    power.brightness = power.brightness.min(level);
+    power.logo_brightness = power.logo_brightness.min(level);
+    if level == 0 {
+        power.logo_state = 0;
+    }
+    if let Some((pwr, cpu, gpu)) = actions.power {
+        power.power_mode = pwr;
+        power.cpu_boost = cpu;
+        power.gpu_boost = gpu;
+    }
+    power.fan_rpm = actions.fan_rpm.unwrap_or(power.fan_rpm);
+}
+
+/// Brings a power config within what the laptop supports, describing every
+/// change in `notes`
+fn fit_power_config(power: &mut config::PowerConfig, label: &str, can_boost: bool, has_logo: bool,
+                    has_logo_spectrum: bool, fan_range: (u16, u16), notes: &mut Vec<String>) {
+    if power.power_mode > 4 {
+        notes.push(format!("{}: invalid power mode {}, using balanced", label, power.power_mode));
+        power.power_mode = 0;
+    }
+    if power.cpu_boost == 3 && !can_boost {
+        notes.push(format!("{}: CPU boost is not supported, using high", label));
+        power.cpu_boost = 2;
+    } else if power.cpu_boost > 3 {
+        notes.push(format!("{}: invalid CPU boost {}, using high", label, power.cpu_boost));
+        power.cpu_boost = 2;
+    }
+    if power.gpu_boost > 2 {
+        notes.push(format!("{}: invalid GPU boost {}, using high", label, power.gpu_boost));
+        power.gpu_boost = 2;
+    }
+    if power.logo_state != 0 && !has_logo {
+        notes.push(format!("{}: this laptop has no logo LED, turned off", label));
+        power.logo_state = 0;
+    } else if power.logo_state == 3 && !has_logo_spectrum {
+        notes.push(format!("{}: the logo can't show spectrum, turned on", label));
+        power.logo_state = 1;
+    } else if power.logo_state > 3 {
+        notes.push(format!("{}: invalid logo state {}, turned on", label, power.logo_state));
+        power.logo_state = 1;
+    }
+    if power.animation_fps == 0 || power.animation_fps > config::MAX_ANIMATION_FPS {
+        let fps = power.animation_fps.clamp(1, config::MAX_ANIMATION_FPS);
+        notes.push(format!("{}: animation frame rate {} is out of range, using {}", label, power.animation_fps, fps));
+        power.animation_fps = fps;
+    }
+    if power.fan_rpm != 0 {
+        let rpm = power.fan_rpm.clamp(fan_range.0 as i32, fan_range.1 as i32);
+        if rpm != power.fan_rpm {
+            notes.push(format!("{}: fan speed {} RPM is out of range, using {} RPM", label, power.fan_rpm, rpm));
+            power.fan_rpm = rpm;
+        }
+    }
+}
+
 // top bit flags whether battery health optimization is on or off
 // bottom bits are the actual threshold that it is set to
 fn byte_to_bho(u: u8) -> (bool, u8) {
@@ -994,3 +1845,80 @@ fn bho_to_byte(is_on: bool, threshold: u8) -> u8 {
     }
     return threshold;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(app: Option<&str>, urgency: Option<u8>, colour: u8) -> comms::NotificationRule {
+        comms::NotificationRule {
+            app: app.map(String::from),
+            urgency,
+            colour: [colour, 0, 0],
+            style: comms::FlashStyle::Flash,
+            keys: "all".into(),
+        }
+    }
+
+    /// A manager without a laptop, with notifications on
+    fn manager(rules: Vec<comms::NotificationRule>) -> DeviceManager {
+        let mut manager = DeviceManager::new();
+        let mut config = config::Configuration::new();
+        config.notifications = true;
+        config.notification_rules = rules;
+        manager.config = Some(config);
+        manager
+    }
+
+    fn matched(manager: &mut DeviceManager, app: &str, urgency: u8) -> Option<u8> {
+        manager.match_notification(app, urgency).map(|rule| rule.colour[0])
+    }
+
+    #[test]
+    fn first_matching_rule() {
+        let mut d = manager(vec![
+            rule(None, Some(2), 1),
+            rule(Some("Signal"), None, 2),
+            rule(Some("Thunderbird"), Some(1), 3),
+        ]);
+        assert_eq!(matched(&mut d, "Thunderbird", 2), Some(1));
+        assert_eq!(matched(&mut d, "signal", 0), Some(2));
+        assert_eq!(matched(&mut d, "Thunderbird", 1), Some(3));
+        assert_eq!(matched(&mut d, "Thunderbird", 0), None);
+        assert_eq!(matched(&mut d, "Firefox", 1), None);
+
+        let mut catch_all = manager(vec![rule(Some("Signal"), None, 2), rule(None, None, 4)]);
+        assert_eq!(matched(&mut catch_all, "Firefox", 0), Some(4));
+        assert_eq!(matched(&mut catch_all, "Signal", 0), Some(2));
+    }
+
+    #[test]
+    fn disabled_and_do_not_disturb() {
+        let mut d = manager(vec![rule(None, None, 1)]);
+        assert_eq!(matched(&mut d, "Signal", 1), Some(1));
+        d.config.as_mut().unwrap().do_not_disturb = true;
+        assert_eq!(matched(&mut d, "Signal", 2), None);
+        d.config.as_mut().unwrap().do_not_disturb = false;
+        d.config.as_mut().unwrap().notifications = false;
+        assert_eq!(matched(&mut d, "Signal", 1), None);
+        assert_eq!(matched(&mut DeviceManager::new(), "Signal", 1), None);
+    }
+
+    #[test]
+    fn low_battery_power_and_fan() {
+        let mut power = config::PowerConfig::new();
+        (power.power_mode, power.cpu_boost, power.gpu_boost, power.fan_rpm) = (2, 3, 2, 5000);
+        (power.brightness, power.logo_brightness, power.logo_state) = (255, 100, 1);
+
+        let mut low = power;
+        apply_low_battery(&mut low, &comms::LowBattery { brightness: 20, power: Some((0, 0, 0)), fan_rpm: Some(0) });
+        assert_eq!((low.power_mode, low.cpu_boost, low.gpu_boost, low.fan_rpm), (0, 0, 0, 0));
+        assert_eq!((low.brightness, low.logo_brightness, low.logo_state), (51, 51, 1));
+
+        // Without power or fan actions only the lights change
+        let mut low = power;
+        apply_low_battery(&mut low, &comms::LowBattery { brightness: 0, power: None, fan_rpm: None });
+        assert_eq!((low.power_mode, low.cpu_boost, low.gpu_boost, low.fan_rpm), (2, 3, 2, 5000));
+        assert_eq!((low.brightness, low.logo_brightness, low.logo_state), (0, 0, 0));
+    }
+}
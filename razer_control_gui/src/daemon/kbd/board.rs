@@ -1,6 +1,7 @@
 /*
 use crate::driver_sysfs;
 */
+use crate::comms;
 use crate::device;
 use std::cmp::Ordering;
 use std::ops;
@@ -10,7 +11,7 @@ use std::ops;
 pub const KEYS_PER_ROW: usize = 15;
 pub const ROWS: usize = 6;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// Represents the colour channels for a key
 pub struct KeyColour {
     /// Red channel
@@ -63,6 +64,41 @@ impl AnimatorKeyColour {
         }
     }
 
+    /// Colour from a hue in degrees, and a saturation and value from 0.0 to 1.0
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> AnimatorKeyColour {
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+        let (red, green, blue) = match sector as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+        AnimatorKeyColour::new_f((red + m) * 255.0, (green + m) * 255.0, (blue + m) * 255.0)
+    }
+
+    /// Multiplies each channel, treating 255 as 1.0
+    pub fn multiply(&self, other: AnimatorKeyColour) -> AnimatorKeyColour {
+        AnimatorKeyColour {
+            red: self.red * other.red / 255.0,
+            green: self.green * other.green / 255.0,
+            blue: self.blue * other.blue / 255.0,
+        }
+    }
+
+    /// Brightest value of each channel
+    pub fn max(&self, other: AnimatorKeyColour) -> AnimatorKeyColour {
+        AnimatorKeyColour {
+            red: self.red.max(other.red),
+            green: self.green.max(other.green),
+            blue: self.blue.max(other.blue),
+        }
+    }
+
     pub fn get_clamped_colour(&self) -> KeyColour {
         KeyColour {
             red: AnimatorKeyColour::clamp_colour(self.red),
@@ -94,6 +130,17 @@ impl ops::Sub for AnimatorKeyColour {
     }
 }
 
+impl ops::Mul<f32> for AnimatorKeyColour {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self {
+        Self {
+            red: self.red * rhs,
+            green: self.green * rhs,
+            blue: self.blue * rhs,
+        }
+    }
+}
+
 impl ops::AddAssign for AnimatorKeyColour {
     fn add_assign(&mut self, rhs: AnimatorKeyColour) {
         self.red += rhs.red;
@@ -129,7 +176,37 @@ impl PartialOrd for AnimatorKeyColour {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Value sent to the LEDs for every value of every channel, built from a
+/// `comms::ColourCorrection`
+#[derive(Clone)]
+pub struct ColourTable {
+    channels: [[u8; 256]; 3],
+}
+
+impl ColourTable {
+    pub fn new(correction: &comms::ColourCorrection) -> ColourTable {
+        let mut channels = [[0u8; 256]; 3];
+        for (channel, table) in channels.iter_mut().enumerate() {
+            let scale = correction.gain[channel] as f32 / 100.0 * correction.white_point[channel] as f32;
+            for (value, out) in table.iter_mut().enumerate() {
+                let level = (value as f32 / 255.0).powf(correction.gamma);
+                *out = (level * scale).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        ColourTable { channels }
+    }
+
+    /// Corrects RGB data, like a row of `get_row_data`
+    pub fn apply(&self, data: &mut [u8]) {
+        for rgb in data.chunks_exact_mut(3) {
+            for (channel, value) in rgb.iter_mut().enumerate() {
+                *value = self.channels[channel][*value as usize];
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// Represents a horizontal row of 15 keys on the keyboard
 pub struct RowData {
     keys: [KeyColour; KEYS_PER_ROW],
@@ -215,11 +292,37 @@ impl KeyboardData {
     pub fn update_kbd(&mut self, laptop: &mut device::RazerLaptop) -> bool {
         // driver_sysfs::write_rgb_map(self.get_curr_state())
         for idx in 0..ROWS {
-            laptop.set_custom_frame_data(idx as u8, self.rows[idx].get_row_data());
+            let data = self.corrected_row(idx, laptop);
+            laptop.set_custom_frame_data(idx as u8, data);
         }
         return true;
     }
 
+    /// Colours of a row as they are sent to the laptop, after its colour correction
+    fn corrected_row(&mut self, idx: usize, laptop: &device::RazerLaptop) -> Vec<u8> {
+        let mut data = self.rows[idx].get_row_data();
+        laptop.get_colour_table().apply(&mut data);
+        data
+    }
+
+    /// Uploads only the rows that differ from `previous`, or every row if
+    /// there is no previous frame. Returns true if anything was uploaded
+    pub fn update_changed_rows(&mut self, laptop: &mut device::RazerLaptop, previous: Option<&KeyboardData>) -> bool {
+        let mut changed = false;
+        for idx in 0..ROWS {
+            let row_changed = match previous {
+                Some(previous) => previous.rows[idx] != self.rows[idx],
+                None => true,
+            };
+            if row_changed {
+                let data = self.corrected_row(idx, laptop);
+                laptop.set_custom_frame_data(idx as u8, data);
+                changed = true;
+            }
+        }
+        changed
+    }
+
     pub fn update_custom_mode(&mut self, laptop: &mut device::RazerLaptop) -> bool {
         // driver_sysfs::write_custom_mode_frame(1)
         // driver_sysfs::write_custom_mode_frame(1)
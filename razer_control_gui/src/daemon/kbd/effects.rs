@@ -25,7 +25,7 @@ impl Effect for Static {
         return Box::new(s);
     }
 
-    fn update(&mut self) -> board::KeyboardData {
+    fn update(&mut self, _delta_ms: f32) -> board::KeyboardData {
         return self.kbd;
     }
 
@@ -36,6 +36,24 @@ impl Effect for Static {
         return "Static";
     }
 
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "static"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![registry::colour_param("Colour")]
+    }
+
+    fn is_animated(&self) -> bool {
+        false
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         &self.args
     }
@@ -88,7 +106,7 @@ impl Effect for StaticGradient {
         Box::new(StaticGradient { kbd, args })
     }
 
-    fn update(&mut self) -> board::KeyboardData {
+    fn update(&mut self, _delta_ms: f32) -> board::KeyboardData {
         self.kbd // Nothing to update
     }
 
@@ -99,6 +117,24 @@ impl Effect for StaticGradient {
         "Static Gradient"
     }
 
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "static_gradient"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![registry::colour_param("Colour 1"), registry::colour_param("Colour 2")]
+    }
+
+    fn is_animated(&self) -> bool {
+        false
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         return &self.args;
     }
@@ -124,10 +160,14 @@ impl Effect for StaticGradient {
 /// 2 colours forming a gradient, animated across the keyboard
 ///
 
+/// Columns the wave moves by per second
+const WAVE_SPEED: f32 = 10.0;
+
 pub struct WaveGradient {
     kbd: board::KeyboardData,
     args: [u8; 6],
     colour_band: Vec<board::AnimatorKeyColour>,
+    offset: f32, // How far the band has moved, in columns
 }
 
 impl Effect for WaveGradient {
@@ -142,6 +182,7 @@ impl Effect for WaveGradient {
             kbd: board::KeyboardData::new(),
             args,
             colour_band: vec![],
+            offset: 0.0,
         };
         let mut c1 = board::AnimatorKeyColour::new_u(args[0], args[1], args[2]);
         let mut c2 = board::AnimatorKeyColour::new_u(args[3], args[4], args[5]);
@@ -157,12 +198,17 @@ impl Effect for WaveGradient {
         Box::new(wave)
     }
 
-    fn update(&mut self) -> board::KeyboardData {
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        let band_len = self.colour_band.len();
+        self.offset = (self.offset + WAVE_SPEED * delta_ms / 1000.0) % band_len as f32;
         for i in 0..15 {
-            let c = self.colour_band[i].get_clamped_colour();
+            // The column sits between two colours of the band
+            let pos = (i as f32 - self.offset).rem_euclid(band_len as f32);
+            let c1 = self.colour_band[pos as usize % band_len];
+            let c2 = self.colour_band[(pos as usize + 1) % band_len];
+            let c = (c1 + (c2 - c1) * pos.fract()).get_clamped_colour();
             self.kbd.set_col_colour(i, c.red, c.green, c.blue);
         }
-        self.colour_band.rotate_right(1);
         self.kbd
     }
 
@@ -173,6 +219,20 @@ impl Effect for WaveGradient {
         "Wave Gradient"
     }
 
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "wave_gradient"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![registry::colour_param("Colour 1"), registry::colour_param("Colour 2")]
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         return &self.args;
     }
@@ -199,6 +259,7 @@ impl Clone for WaveGradient {
             kbd: self.kbd,
             args: self.args,
             colour_band: self.colour_band.to_vec(),
+            offset: self.offset,
         }
     }
 }
@@ -211,12 +272,12 @@ impl Clone for WaveGradient {
 pub struct BreathSingle {
     args: [u8; 4],
     kbd: board::KeyboardData,
-    step_duration_ms: u128,
-    static_start_ms: u128,
+    step_duration_ms: f32,
+    step_elapsed_ms: f32, // Time spent in the current off or on step
     curr_step: u8, // Step 0 = Off, 1 = increasing, 2 = On, 3 = decreasing
     target_colour: board::AnimatorKeyColour,
     current_colour: board::AnimatorKeyColour,
-    animator_step_colour: board::AnimatorKeyColour,
+    animator_step_colour: board::AnimatorKeyColour, // Change per ms while fading
 }
 
 impl Effect for BreathSingle {
@@ -227,48 +288,50 @@ impl Effect for BreathSingle {
         Box::new(BreathSingle {
             args: [args[0], args[1], args[2], args[3]],
             kbd: k,
-            step_duration_ms: cycle_duration_ms as u128,
-            static_start_ms: get_millis(),
+            step_duration_ms: cycle_duration_ms,
+            step_elapsed_ms: 0.0,
             curr_step: 0,
             target_colour: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
             current_colour: board::AnimatorKeyColour::new_u(0, 0, 0),
             animator_step_colour: board::AnimatorKeyColour::new_f(
-                args[0] as f32 / (cycle_duration_ms as f32 / ANIMATION_SLEEP_MS as f32) as f32,
-                args[1] as f32 / (cycle_duration_ms as f32 / ANIMATION_SLEEP_MS as f32) as f32,
-                args[2] as f32 / (cycle_duration_ms as f32 / ANIMATION_SLEEP_MS as f32) as f32,
+                args[0] as f32 / cycle_duration_ms,
+                args[1] as f32 / cycle_duration_ms,
+                args[2] as f32 / cycle_duration_ms,
             ),
         })
     }
 
-    fn update(&mut self) -> board::KeyboardData {
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
         match self.curr_step {
             0 => {
                 self.current_colour = board::AnimatorKeyColour::new_u(0, 0, 0);
-                if get_millis() - self.static_start_ms >= self.step_duration_ms {
+                self.step_elapsed_ms += delta_ms;
+                if self.step_elapsed_ms >= self.step_duration_ms {
                     self.curr_step += 1;
                 }
             }
             1 => {
                 // Increasing
-                self.current_colour += self.animator_step_colour;
+                self.current_colour += self.animator_step_colour * delta_ms;
                 if self.current_colour >= self.target_colour {
                     self.curr_step += 1;
-                    self.static_start_ms = get_millis();
+                    self.step_elapsed_ms = 0.0;
                 }
             }
             2 => {
                 self.current_colour = self.target_colour;
-                if get_millis() - self.static_start_ms >= self.step_duration_ms {
+                self.step_elapsed_ms += delta_ms;
+                if self.step_elapsed_ms >= self.step_duration_ms {
                     self.curr_step += 1;
                 }
             }
             3 => {
                 // Decreasing
-                self.current_colour -= self.animator_step_colour;
+                self.current_colour -= self.animator_step_colour * delta_ms;
                 let target = board::AnimatorKeyColour::new_u(0, 0, 0);
                 if self.current_colour <= target {
                     self.curr_step = 0;
-                    self.static_start_ms = get_millis();
+                    self.step_elapsed_ms = 0.0;
                 }
             }
             _ => {} // Unknown state? Ignore
@@ -285,6 +348,23 @@ impl Effect for BreathSingle {
         "Breathing Single"
     }
 
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "breathing_single"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::colour_param("Colour"),
+            registry::range_param("Duration (1/10 s)", 1, 255, 10),
+        ]
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         return &self.args;
     }
@@ -304,3 +384,1218 @@ impl Effect for BreathSingle {
         self.kbd.get_curr_state()
     }
 }
+
+///
+/// SPECTRUM KEYBOARD EFFECT
+/// The whole keyboard cycles through every hue
+///
+#[derive(Copy, Clone)]
+pub struct Spectrum {
+    kbd: board::KeyboardData,
+    args: [u8; 1],
+    hue: f32, // Degrees
+}
+
+/// Time of a full hue cycle for the speed of rainbow effects
+fn hue_cycle_ms(speed: u8) -> f32 {
+    100_000.0 / speed as f32 // Speed 10 = 10 seconds per cycle
+}
+
+impl Effect for Spectrum {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(Spectrum {
+            kbd: board::KeyboardData::new(),
+            args: [args[0]],
+            hue: 0.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        self.hue = (self.hue + 360.0 * delta_ms / hue_cycle_ms(self.args[0])) % 360.0;
+        let c = board::AnimatorKeyColour::from_hsv(self.hue, 1.0, 1.0).get_clamped_colour();
+        self.kbd.set_kbd_colour(c.red, c.green, c.blue);
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Spectrum"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "spectrum"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![registry::range_param("Speed", 1, 100, 10)]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Spectrum"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// RAINBOW WAVE KEYBOARD EFFECT
+/// Every hue spread across the keyboard, moving in one direction
+///
+#[derive(Copy, Clone)]
+pub struct RainbowWave {
+    kbd: board::KeyboardData,
+    args: [u8; 3], // Direction, speed, centre key
+    phase: f32, // Degrees
+}
+
+pub const WAVE_DIRECTIONS: [&str; 4] = ["Horizontal", "Vertical", "Diagonal", "Radial"];
+
+impl RainbowWave {
+    /// Where a key is along the wave, 0.0 at the start to 1.0 at the end
+    fn key_position(&self, row: usize, col: usize) -> f32 {
+        let rows = board::ROWS as f32;
+        let cols = board::KEYS_PER_ROW as f32;
+        match self.args[0] {
+            0 => col as f32 / cols,
+            1 => row as f32 / rows,
+            2 => (row + col) as f32 / (rows + cols),
+            _ => {
+                let centre = self.args[2] as usize;
+                let d_row = row as f32 - (centre / board::KEYS_PER_ROW) as f32;
+                let d_col = col as f32 - (centre % board::KEYS_PER_ROW) as f32;
+                (d_row * d_row + d_col * d_col).sqrt() / cols
+            }
+        }
+    }
+}
+
+impl Effect for RainbowWave {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(RainbowWave {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2]],
+            phase: 0.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        self.phase = (self.phase + 360.0 * delta_ms / hue_cycle_ms(self.args[1])) % 360.0;
+        for row in 0..board::ROWS {
+            for col in 0..board::KEYS_PER_ROW {
+                // Subtracting the phase moves the colours away from the start
+                let hue = self.key_position(row, col) * 360.0 - self.phase;
+                let c = board::AnimatorKeyColour::from_hsv(hue, 1.0, 1.0).get_clamped_colour();
+                self.kbd.set_key_colour(row, col, c.red, c.green, c.blue);
+            }
+        }
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Rainbow Wave"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "rainbow_wave"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::choice_param("Direction", &WAVE_DIRECTIONS, 0),
+            registry::range_param("Speed", 1, 100, 10),
+            registry::range_param("Centre key (radial)", 0, (board::ROWS * board::KEYS_PER_ROW - 1) as u8, 37),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Rainbow Wave"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// RIPPLE KEYBOARD EFFECT
+/// Rings of colour spread out from pressed keys
+///
+#[derive(Clone)]
+pub struct Ripple {
+    kbd: board::KeyboardData,
+    args: [u8; 4], // Colour, speed in keys per second
+    ripples: Vec<(usize, f32)>, // Key the ripple started at, radius in keys
+}
+
+/// Radius at which a ripple has faded out completely
+const RIPPLE_MAX_RADIUS: f32 = 15.0;
+/// Ripples kept at once, older ones are dropped when typing fast
+const MAX_RIPPLES: usize = 16;
+
+impl Effect for Ripple {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(Ripple {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2], args[3]],
+            ripples: Vec::new(),
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        let growth = self.args[3] as f32 * delta_ms / 1000.0;
+        for ripple in self.ripples.iter_mut() {
+            ripple.1 += growth;
+        }
+        self.ripples.retain(|r| r.1 < RIPPLE_MAX_RADIUS);
+
+        let colour = board::AnimatorKeyColour::new_u(self.args[0], self.args[1], self.args[2]);
+        for row in 0..board::ROWS {
+            for col in 0..board::KEYS_PER_ROW {
+                let mut intensity: f32 = 0.0;
+                for (key, radius) in self.ripples.iter() {
+                    let d_row = row as f32 - (key / board::KEYS_PER_ROW) as f32;
+                    let d_col = col as f32 - (key % board::KEYS_PER_ROW) as f32;
+                    let distance = (d_row * d_row + d_col * d_col).sqrt();
+                    // One key wide ring, fading as it grows
+                    let ring = (1.0 - (distance - radius).abs()).max(0.0);
+                    intensity = intensity.max(ring * (1.0 - radius / RIPPLE_MAX_RADIUS));
+                }
+                let c = (colour * intensity).get_clamped_colour();
+                self.kbd.set_key_colour(row, col, c.red, c.green, c.blue);
+            }
+        }
+        self.kbd
+    }
+
+    fn key_event(&mut self, event: keys::KeyEvent) {
+        if event.pressed {
+            if self.ripples.len() == MAX_RIPPLES {
+                self.ripples.remove(0);
+            }
+            self.ripples.push((event.key, 0.0));
+        }
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Ripple"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "ripple"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::colour_param("Colour"),
+            registry::range_param("Speed (keys/s)", 1, 50, 15),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Ripple"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// AFTERGLOW KEYBOARD EFFECT
+/// Pressed keys light up and slowly fade out
+///
+#[derive(Copy, Clone)]
+pub struct Afterglow {
+    kbd: board::KeyboardData,
+    args: [u8; 4], // Colour, duration in tenths of a second
+    glow: [f32; board::ROWS * board::KEYS_PER_ROW], // 1.0 just pressed, 0.0 faded
+}
+
+impl Effect for Afterglow {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(Afterglow {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2], args[3]],
+            glow: [0.0; board::ROWS * board::KEYS_PER_ROW],
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        let fade = delta_ms / (self.args[3] as f32 * 100.0);
+        let colour = board::AnimatorKeyColour::new_u(self.args[0], self.args[1], self.args[2]);
+        for (index, glow) in self.glow.iter_mut().enumerate() {
+            *glow = (*glow - fade).max(0.0);
+            let c = (colour * *glow).get_clamped_colour();
+            self.kbd.set_key_colour(index / board::KEYS_PER_ROW, index % board::KEYS_PER_ROW, c.red, c.green, c.blue);
+        }
+        self.kbd
+    }
+
+    fn key_event(&mut self, event: keys::KeyEvent) {
+        if event.pressed {
+            self.glow[event.key] = 1.0;
+        }
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Afterglow"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "afterglow"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::colour_param("Colour"),
+            registry::range_param("Duration (1/10 s)", 1, 255, 10),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Afterglow"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// HEATMAP KEYBOARD EFFECT
+/// Keys go from cold to hot the more they are pressed
+///
+#[derive(Copy, Clone)]
+pub struct Heatmap {
+    kbd: board::KeyboardData,
+    args: [u8; 7], // Cold colour, hot colour, cool-down in seconds
+    heat: [f32; board::ROWS * board::KEYS_PER_ROW], // Presses, decaying over time
+}
+
+impl Effect for Heatmap {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(Heatmap {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2], args[3], args[4], args[5], args[6]],
+            heat: [0.0; board::ROWS * board::KEYS_PER_ROW],
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        // Heat drops to about a third every cool-down period
+        let decay = (-delta_ms / (self.args[6] as f32 * 1000.0)).exp();
+        for heat in self.heat.iter_mut() {
+            *heat *= decay;
+        }
+        let hottest = self.heat.iter().cloned().fold(0.0, f32::max);
+
+        let cold = board::AnimatorKeyColour::new_u(self.args[0], self.args[1], self.args[2]);
+        let hot = board::AnimatorKeyColour::new_u(self.args[3], self.args[4], self.args[5]);
+        for (index, heat) in self.heat.iter().enumerate() {
+            let t = if hottest > 0.0 { heat / hottest } else { 0.0 };
+            let c = (cold * (1.0 - t) + hot * t).get_clamped_colour();
+            self.kbd.set_key_colour(index / board::KEYS_PER_ROW, index % board::KEYS_PER_ROW, c.red, c.green, c.blue);
+        }
+        self.kbd
+    }
+
+    fn key_event(&mut self, event: keys::KeyEvent) {
+        if event.pressed {
+            self.heat[event.key] += 1.0;
+        }
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Heatmap"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "heatmap"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::colour_param("Cold colour"),
+            registry::colour_param("Hot colour"),
+            registry::range_param("Cool-down (s)", 1, 255, 60),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Heatmap"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+//
+// SYSTEM METRIC EFFECTS
+// The keyboard as a status display, sampled every few tenths of a second
+//
+
+/// Counts time between samples of a system metric
+#[derive(Copy, Clone)]
+struct MetricSampler {
+    interval_ms: f32,
+    since_sample_ms: f32,
+}
+
+impl MetricSampler {
+    fn new(interval: u8) -> MetricSampler {
+        MetricSampler {
+            interval_ms: interval as f32 * 100.0,
+            since_sample_ms: f32::INFINITY, // Sample on the first frame
+        }
+    }
+
+    /// Whether it is time for a new sample
+    fn due(&mut self, delta_ms: f32) -> bool {
+        self.since_sample_ms += delta_ms;
+        if self.since_sample_ms < self.interval_ms {
+            return false;
+        }
+        self.since_sample_ms = 0.0;
+        true
+    }
+}
+
+fn sample_interval_param(default: u8) -> comms::EffectParam {
+    registry::range_param("Sample interval (1/10 s)", 1, 100, default)
+}
+
+/// Lights `fraction` of the keys from `first_col` to `last_col`, the last lit
+/// key dimmed by how much of it is filled. Every other key is turned off
+fn draw_bar(kbd: &mut board::KeyboardData, row: usize, first_col: usize, last_col: usize, fraction: f32, colour: board::AnimatorKeyColour) {
+    kbd.set_kbd_colour(0, 0, 0);
+    let filled = fraction.clamp(0.0, 1.0) * (last_col - first_col + 1) as f32;
+    for (idx, col) in (first_col..=last_col).enumerate() {
+        let c = (colour * (filled - idx as f32).clamp(0.0, 1.0)).get_clamped_colour();
+        kbd.set_key_colour(row, col, c.red, c.green, c.blue);
+    }
+}
+
+/// CPU load as a bar across the number keys, 10 % per key
+#[derive(Copy, Clone)]
+pub struct CpuLoad {
+    kbd: board::KeyboardData,
+    args: [u8; 8], // Bar colour, high load colour, high load %, sample interval
+    sampler: MetricSampler,
+    load: f32,
+}
+
+impl Effect for CpuLoad {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(CpuLoad {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2], args[3], args[4], args[5], args[6], args[7]],
+            sampler: MetricSampler::new(args[7]),
+            load: 0.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        if self.sampler.due(delta_ms) {
+            if let Some(load) = metrics::sample_cpu_load() {
+                self.load = load;
+            }
+        }
+        let colour = if self.load * 100.0 >= self.args[6] as f32 {
+            board::AnimatorKeyColour::new_u(self.args[3], self.args[4], self.args[5])
+        } else {
+            board::AnimatorKeyColour::new_u(self.args[0], self.args[1], self.args[2])
+        };
+        draw_bar(&mut self.kbd, 1, 1, 10, self.load, colour); // 1 to 0
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "CPU Load"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "cpu_load"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::colour_param("Bar colour"),
+            registry::colour_param("High load colour"),
+            registry::range_param("High load (%)", 0, 100, 80),
+            sample_interval_param(10),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("CPU Load"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+/// Battery percentage as a gauge on the F-keys
+#[derive(Copy, Clone)]
+pub struct BatteryGauge {
+    kbd: board::KeyboardData,
+    args: [u8; 8], // Charged colour, low battery colour, low battery %, sample interval
+    sampler: MetricSampler,
+    percentage: f32,
+}
+
+impl Effect for BatteryGauge {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(BatteryGauge {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2], args[3], args[4], args[5], args[6], args[7]],
+            sampler: MetricSampler::new(args[7]),
+            percentage: 100.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        if self.sampler.due(delta_ms) {
+            if let Some(percentage) = metrics::sample_battery_percentage() {
+                self.percentage = percentage;
+            }
+        }
+        let colour = if self.percentage <= self.args[6] as f32 {
+            board::AnimatorKeyColour::new_u(self.args[3], self.args[4], self.args[5])
+        } else {
+            board::AnimatorKeyColour::new_u(self.args[0], self.args[1], self.args[2])
+        };
+        draw_bar(&mut self.kbd, 0, 1, 12, self.percentage / 100.0, colour); // F1 to F12
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Battery Gauge"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "battery_gauge"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::colour_param("Charged colour"),
+            registry::colour_param("Low battery colour"),
+            registry::range_param("Low battery (%)", 0, 100, 20),
+            sample_interval_param(50),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Battery Gauge"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+/// CPU temperature as a colour between cool and hot on the whole keyboard
+#[derive(Copy, Clone)]
+pub struct CpuTemperature {
+    kbd: board::KeyboardData,
+    args: [u8; 9], // Cool colour, hot colour, cool and hot temperatures, sample interval
+    sampler: MetricSampler,
+    temperature: f32,
+}
+
+impl Effect for CpuTemperature {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(CpuTemperature {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2], args[3], args[4], args[5], args[6], args[7], args[8]],
+            sampler: MetricSampler::new(args[8]),
+            temperature: 0.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        if self.sampler.due(delta_ms) {
+            if let Some(temperature) = metrics::sample_cpu_temperature() {
+                self.temperature = temperature;
+            }
+        }
+        let (cool, hot) = (self.args[6] as f32, self.args[7] as f32);
+        let t = if hot > cool {
+            ((self.temperature - cool) / (hot - cool)).clamp(0.0, 1.0)
+        } else if self.temperature >= hot {
+            1.0
+        } else {
+            0.0
+        };
+        let cool_colour = board::AnimatorKeyColour::new_u(self.args[0], self.args[1], self.args[2]);
+        let hot_colour = board::AnimatorKeyColour::new_u(self.args[3], self.args[4], self.args[5]);
+        let c = (cool_colour * (1.0 - t) + hot_colour * t).get_clamped_colour();
+        self.kbd.set_kbd_colour(c.red, c.green, c.blue);
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "CPU Temperature"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "cpu_temperature"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::colour_param("Cool colour"),
+            registry::colour_param("Hot colour"),
+            registry::range_param("Cool temperature (°C)", 0, 120, 40),
+            registry::range_param("Hot temperature (°C)", 0, 120, 90),
+            sample_interval_param(20),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("CPU Temperature"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// IMAGE KEYBOARD EFFECT
+/// A PNG drawn on the keyboard, or an animated GIF/APNG played with its delays
+///
+#[derive(Clone)]
+pub struct Image {
+    args: Vec<u8>, // Scaling, path
+    frames: Vec<images::ImageFrame>,
+    frame: usize,
+    frame_elapsed_ms: f32,
+}
+
+impl Effect for Image {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let path = registry::read_path(&args, 1).map(|(path, _)| path).unwrap_or_default();
+        let frames = match images::load_frames(&path, args[0] == 1) {
+            Ok(frames) => frames,
+            Err(e) => {
+                eprintln!("Error loading image {}: {}", path, e);
+                vec![(board::KeyboardData::new(), 0.0)]
+            }
+        };
+        Box::new(Image {
+            args,
+            frames,
+            frame: 0,
+            frame_elapsed_ms: 0.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        if self.frames.len() > 1 {
+            self.frame_elapsed_ms += delta_ms;
+            while self.frame_elapsed_ms >= self.frames[self.frame].1 {
+                self.frame_elapsed_ms -= self.frames[self.frame].1;
+                self.frame = (self.frame + 1) % self.frames.len();
+            }
+        }
+        self.frames[self.frame].0
+    }
+
+    fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Image"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "image"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![
+            registry::choice_param("Scaling", &images::SCALING_MODES, 1),
+            registry::file_param("Image"),
+        ]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Image"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.frames[self.frame].0.get_curr_state()
+    }
+}
+
+///
+/// KEYFRAME ANIMATION KEYBOARD EFFECT
+/// Plays an animation file, see `timeline` for the format
+///
+#[derive(Clone)]
+pub struct KeyframeAnimation {
+    args: Vec<u8>, // Path
+    timeline: std::sync::Arc<timeline::Timeline>,
+    elapsed_ms: f32,
+}
+
+impl KeyframeAnimation {
+    fn load(args: &[u8]) -> Result<timeline::Timeline, String> {
+        let (path, _) = registry::read_path(args, 0).ok_or("invalid path")?;
+        let json = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        timeline::Timeline::parse(&json).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Draws nothing, used in place of an animation that can't be loaded so
+    /// the layer is kept until it is fixed
+    fn empty_timeline() -> timeline::Timeline {
+        timeline::Timeline::parse(r#"{ "loop": false, "keyframes": [{ "time_ms": 0, "keys": {} }] }"#).unwrap()
+    }
+}
+
+impl Effect for KeyframeAnimation {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let timeline = KeyframeAnimation::load(&args).unwrap_or_else(|e| {
+            eprintln!("Error loading animation {}", e);
+            KeyframeAnimation::empty_timeline()
+        });
+        Box::new(KeyframeAnimation {
+            args,
+            timeline: std::sync::Arc::new(timeline),
+            elapsed_ms: 0.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        self.elapsed_ms += delta_ms;
+        if self.timeline.is_looping() {
+            self.elapsed_ms %= self.timeline.duration_ms();
+        }
+        self.timeline.frame_at(self.elapsed_ms)
+    }
+
+    fn is_animated(&self) -> bool {
+        self.timeline.is_looping() || self.elapsed_ms <= self.timeline.duration_ms()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Keyframe Animation"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "keyframes"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![registry::file_param("Animation")]
+    }
+
+    fn check_args(args: &[u8]) -> Result<(), String>
+    where
+        Self: Sized,
+    {
+        KeyframeAnimation::load(args).map(|_| ())
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Keyframe Animation"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.timeline.frame_at(self.elapsed_ms).get_curr_state()
+    }
+}
+
+///
+/// EMBEDDED KEYFRAME ANIMATION KEYBOARD EFFECT
+/// Plays an animation kept in the effect's arguments instead of a file, so
+/// saved layers don't depend on the file staying where it was
+///
+#[derive(Clone)]
+pub struct EmbeddedKeyframeAnimation {
+    args: Vec<u8>, // Animation JSON, encoded like a path
+    timeline: std::sync::Arc<timeline::Timeline>,
+    elapsed_ms: f32,
+}
+
+impl EmbeddedKeyframeAnimation {
+    fn load(args: &[u8]) -> Result<timeline::Timeline, String> {
+        let (json, _) = registry::read_path(args, 0).ok_or("invalid animation")?;
+        timeline::Timeline::parse(&json)
+    }
+}
+
+impl Effect for EmbeddedKeyframeAnimation {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let timeline = EmbeddedKeyframeAnimation::load(&args).unwrap_or_else(|e| {
+            eprintln!("Error loading embedded animation {}", e);
+            KeyframeAnimation::empty_timeline()
+        });
+        Box::new(EmbeddedKeyframeAnimation {
+            args,
+            timeline: std::sync::Arc::new(timeline),
+            elapsed_ms: 0.0,
+        })
+    }
+
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        self.elapsed_ms += delta_ms;
+        if self.timeline.is_looping() {
+            self.elapsed_ms %= self.timeline.duration_ms();
+        }
+        self.timeline.frame_at(self.elapsed_ms)
+    }
+
+    fn is_animated(&self) -> bool {
+        self.timeline.is_looping() || self.elapsed_ms <= self.timeline.duration_ms()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Embedded Keyframe Animation"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "keyframes_embedded"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![registry::file_contents_param("Animation")]
+    }
+
+    fn check_args(args: &[u8]) -> Result<(), String>
+    where
+        Self: Sized,
+    {
+        EmbeddedKeyframeAnimation::load(args).map(|_| ())
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Embedded Keyframe Animation"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.timeline.frame_at(self.elapsed_ms).get_curr_state()
+    }
+}
+
+///
+/// PER-KEY KEYBOARD EFFECT
+/// A colour for every key, mostly set by other programs like OpenRGB
+///
+#[derive(Clone)]
+pub struct PerKey {
+    kbd: board::KeyboardData,
+    args: Vec<u8>, // RGB of every key, row by row
+}
+
+impl Effect for PerKey {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let mut kbd = board::KeyboardData::new();
+        for (pos, rgb) in args.chunks_exact(3).enumerate() {
+            kbd.set_key_at(pos, board::KeyColour { red: rgb[0], green: rgb[1], blue: rgb[2] });
+        }
+        Box::new(PerKey { kbd, args })
+    }
+
+    fn update(&mut self, _delta_ms: f32) -> board::KeyboardData {
+        self.kbd
+    }
+
+    fn is_animated(&self) -> bool {
+        false
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Per-key Colours"
+    }
+
+    fn get_id() -> &'static str
+    where
+        Self: Sized,
+    {
+        "per_key"
+    }
+
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized,
+    {
+        vec![registry::key_colours_param("Colours")]
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Per-key Colours"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(key: usize) -> keys::KeyEvent {
+        keys::KeyEvent { key, pressed: true }
+    }
+
+    fn release(key: usize) -> keys::KeyEvent {
+        keys::KeyEvent { key, pressed: false }
+    }
+
+    /// Feeds key events to an effect, then renders a frame `delta_ms` later
+    fn play(effect: &mut Box<dyn Effect>, events: &[keys::KeyEvent], delta_ms: f32) -> board::KeyboardData {
+        for event in events {
+            effect.key_event(*event);
+        }
+        effect.update(delta_ms)
+    }
+
+    fn red(frame: board::KeyboardData, key: usize) -> u8 {
+        frame.get_key_at(key).red
+    }
+
+    #[test]
+    fn breathing_holds_by_frame_time() {
+        // Red, 1 second per step
+        let mut breath = registry::create_effect("breathing_single", vec![200, 0, 0, 10]).unwrap();
+        let shown: Vec<u8> = [0.0, 1000.0, 500.0, 500.0, 600.0, 300.0, 100.0, 500.0]
+            .iter()
+            .map(|delta_ms| red(breath.update(*delta_ms), 0))
+            .collect();
+        assert_eq!(shown, [0, 0, 100, 200, 200, 200, 200, 100]);
+    }
+
+    #[test]
+    fn ripple_spreads_from_pressed_keys() {
+        // Red, 10 keys per second
+        let mut ripple = registry::create_effect("ripple", vec![255, 0, 0, 10]).unwrap();
+        assert_eq!(red(play(&mut ripple, &[release(37)], 0.0), 37), 0);
+        let frame = play(&mut ripple, &[press(37), release(37)], 0.0);
+        assert_eq!(red(frame, 37), 255);
+        assert_eq!(red(frame, 39), 0);
+        // 2 keys out after 200 ms, fainter as it grows
+        let frame = play(&mut ripple, &[], 200.0);
+        assert_eq!(red(frame, 37), 0);
+        assert!((200..255).contains(&red(frame, 39)), "{}", red(frame, 39));
+        assert_eq!(red(frame, 7), red(frame, 67));
+        // Gone once it reaches the edge
+        assert!((0..90).all(|key| red(play(&mut ripple, &[], 1500.0), key) == 0));
+    }
+
+    #[test]
+    fn ripples_are_capped() {
+        let mut ripple = registry::create_effect("ripple", vec![255, 0, 0, 10]).unwrap();
+        let presses: Vec<keys::KeyEvent> = (0..MAX_RIPPLES + 4).map(press).collect();
+        let frame = play(&mut ripple, &presses, 0.0);
+        // The oldest ripples were dropped
+        assert_eq!(red(frame, 0), 0);
+        assert_eq!(red(frame, MAX_RIPPLES + 3), 255);
+    }
+
+    #[test]
+    fn afterglow_fades() {
+        // Red, 1 second
+        let mut afterglow = registry::create_effect("afterglow", vec![255, 0, 0, 10]).unwrap();
+        let frame = play(&mut afterglow, &[press(5), release(5), press(6)], 0.0);
+        assert_eq!((red(frame, 5), red(frame, 6), red(frame, 7)), (255, 255, 0));
+        let frame = play(&mut afterglow, &[release(6)], 500.0);
+        assert!((126..=128).contains(&red(frame, 5)), "{}", red(frame, 5));
+        assert_eq!(red(frame, 5), red(frame, 6));
+        // Pressing again lights the key up again
+        let frame = play(&mut afterglow, &[press(6)], 500.0);
+        assert_eq!(red(frame, 5), 0);
+        assert!((126..=128).contains(&red(frame, 6)), "{}", red(frame, 6));
+    }
+
+    #[test]
+    fn heatmap_follows_presses() {
+        // Black to red, 60 second cool-down
+        let mut heatmap = registry::create_effect("heatmap", vec![0, 0, 0, 255, 0, 0, 60]).unwrap();
+        assert_eq!(red(play(&mut heatmap, &[], 0.0), 1), 0);
+        let events = [press(1), release(1), press(1), release(1), press(2), release(2)];
+        let frame = play(&mut heatmap, &events, 0.0);
+        assert_eq!(red(frame, 1), 255);
+        assert!((126..=128).contains(&red(frame, 2)), "{}", red(frame, 2));
+        assert_eq!(red(frame, 3), 0);
+        // Cooling down keeps the hottest key hot
+        let frame = play(&mut heatmap, &[], 30000.0);
+        assert_eq!(red(frame, 1), 255);
+    }
+}
@@ -0,0 +1,166 @@
+use super::*;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, Delay, DynamicImage, ImageFormat, RgbaImage};
+use std::fs::File;
+use std::io::BufReader;
+
+/// Delay used for frames that don't set one, like browsers do
+const DEFAULT_FRAME_DELAY_MS: f32 = 100.0;
+/// Shortest delay between frames, faster frames could not be shown anyway
+const MIN_FRAME_DELAY_MS: f32 = 20.0;
+
+pub const SCALING_MODES: [&str; 2] = ["Nearest", "Area"];
+
+/// One frame of an image scaled to the keyboard, and how long it is shown
+pub type ImageFrame = (board::KeyboardData, f32);
+
+/// Loads a PNG, APNG or GIF and scales every frame to the keyboard matrix
+pub fn load_frames(path: &str, area: bool) -> Result<Vec<ImageFrame>, String> {
+    let format = ImageFormat::from_path(path).map_err(|e| e.to_string())?;
+    let reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let frames = match format {
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(reader).map_err(|e| e.to_string())?;
+            decoder.into_frames().collect_frames().map_err(|e| e.to_string())?
+        }
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader).map_err(|e| e.to_string())?;
+            if decoder.is_apng() {
+                decoder.apng().into_frames().collect_frames().map_err(|e| e.to_string())?
+            } else {
+                let image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+                return Ok(vec![(scale_to_keyboard(&image.to_rgba8(), area), 0.0)]);
+            }
+        }
+        _ => return Err(format!("unsupported image format {:?}", format)),
+    };
+    if frames.is_empty() {
+        return Err("the image has no frames".into());
+    }
+    Ok(frames
+        .iter()
+        .map(|frame| (scale_to_keyboard(frame.buffer(), area), frame_delay(frame.delay())))
+        .collect())
+}
+
+/// Delay of a frame in ms, frames without one get the default
+fn frame_delay(delay: Delay) -> f32 {
+    let (numer, denom) = delay.numer_denom_ms();
+    match numer as f32 / denom.max(1) as f32 {
+        d if d <= 0.0 => DEFAULT_FRAME_DELAY_MS,
+        d => d.max(MIN_FRAME_DELAY_MS),
+    }
+}
+
+/// Scales an image to one pixel per key, either picking the pixel at the
+/// centre of each key or averaging every pixel the key covers. Transparent
+/// pixels are drawn black
+fn scale_to_keyboard(image: &RgbaImage, area: bool) -> board::KeyboardData {
+    let mut kbd = board::KeyboardData::new();
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return kbd;
+    }
+    for row in 0..board::ROWS {
+        let y0 = row as u32 * height / board::ROWS as u32;
+        let y1 = ((row as u32 + 1) * height / board::ROWS as u32).max(y0 + 1);
+        for col in 0..board::KEYS_PER_ROW {
+            let x0 = col as u32 * width / board::KEYS_PER_ROW as u32;
+            let x1 = ((col as u32 + 1) * width / board::KEYS_PER_ROW as u32).max(x0 + 1);
+            let colour = if area {
+                let mut sum = board::AnimatorKeyColour::new_f(0.0, 0.0, 0.0);
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        sum += pixel_colour(image, x, y);
+                    }
+                }
+                sum.divide(((x1 - x0) * (y1 - y0)) as f32)
+            } else {
+                pixel_colour(image, (x0 + x1) / 2, (y0 + y1) / 2)
+            };
+            let c = colour.get_clamped_colour();
+            kbd.set_key_colour(row, col, c.red, c.green, c.blue);
+        }
+    }
+    kbd
+}
+
+fn pixel_colour(image: &RgbaImage, x: u32, y: u32) -> board::AnimatorKeyColour {
+    let [r, g, b, a] = image.get_pixel(x, y).0;
+    board::AnimatorKeyColour::new_u(r, g, b) * (a as f32 / 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Frame, Rgba};
+
+    fn key(frame: board::KeyboardData, key: usize) -> (u8, u8, u8) {
+        let c = frame.get_key_at(key);
+        (c.red, c.green, c.blue)
+    }
+
+    fn fixture(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("razer-images-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn delays_are_clamped() {
+        assert_eq!(frame_delay(Delay::from_numer_denom_ms(0, 1)), DEFAULT_FRAME_DELAY_MS);
+        assert_eq!(frame_delay(Delay::from_numer_denom_ms(5, 1)), MIN_FRAME_DELAY_MS);
+        assert_eq!(frame_delay(Delay::from_numer_denom_ms(150, 1)), 150.0);
+        assert_eq!(frame_delay(Delay::from_numer_denom_ms(100, 3)), 100.0 / 3.0);
+    }
+
+    #[test]
+    fn animated_gif() {
+        let path = fixture("two_frames.gif");
+        let frames = [([255, 0, 0, 255], 0), ([0, 0, 255, 255], 10), ([0, 255, 0, 255], 250)].map(|(colour, delay)| {
+            Frame::from_parts(RgbaImage::from_pixel(30, 12, Rgba(colour)), 0, 0, Delay::from_numer_denom_ms(delay, 1))
+        });
+        GifEncoder::new(File::create(&path).unwrap()).encode_frames(frames).unwrap();
+
+        let frames = load_frames(&path, false).unwrap();
+        let delays: Vec<f32> = frames.iter().map(|(_, delay)| *delay).collect();
+        assert_eq!(delays, [DEFAULT_FRAME_DELAY_MS, MIN_FRAME_DELAY_MS, 250.0]);
+        assert_eq!(key(frames[0].0, 0), (255, 0, 0));
+        assert_eq!(key(frames[2].0, 89), (0, 255, 0));
+    }
+
+    #[test]
+    fn nearest_and_area_scaling() {
+        // Keys 0 to 7 white, 2 by 2 pixels per key
+        let image = RgbaImage::from_fn(30, 12, |x, y| match (x, y) {
+            (14, 0) => Rgba([0, 0, 0, 255]),
+            (20..=21, 2..=3) => Rgba([255, 255, 255, 128]),
+            (x, _) if x < 16 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 255]),
+        });
+        let path = fixture("halves.png");
+        image.save(&path).unwrap();
+
+        let nearest = load_frames(&path, false).unwrap();
+        assert_eq!(nearest.len(), 1);
+        assert_eq!(key(nearest[0].0, 0), (255, 255, 255));
+        assert_eq!(key(nearest[0].0, 7), (255, 255, 255));
+        assert_eq!(key(nearest[0].0, 8), (0, 0, 0));
+        // Half transparent pixels are drawn half as bright
+        assert_eq!(key(nearest[0].0, 25), (128, 128, 128));
+
+        let area = load_frames(&path, true).unwrap();
+        // One of the 4 pixels is black
+        assert_eq!(key(area[0].0, 7), (191, 191, 191));
+        assert_eq!(key(area[0].0, 25), (128, 128, 128));
+        assert_eq!(key(area[0].0, 89), (0, 0, 0));
+    }
+
+    #[test]
+    fn unsupported_files() {
+        assert!(load_frames("keyboard.bmp", false).is_err());
+        assert!(load_frames(&fixture("missing.png"), false).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use super::*;
+use evdev::{Device, InputEventKind, Key, LedType};
+use std::collections::HashMap;
+
+const LEDS_DIR: &str = "/sys/class/leds";
+
+/// Position of each key in the matrix of the Blade keyboards, by Linux key
+/// code. Models with a different layout override keys in `laptops.json`
+const DEFAULT_LAYOUT: &[(Key, usize, usize)] = &[
+    // Row 0
+    (Key::KEY_ESC, 0, 0),
+    (Key::KEY_F1, 0, 1),
+    (Key::KEY_F2, 0, 2),
+    (Key::KEY_F3, 0, 3),
+    (Key::KEY_F4, 0, 4),
+    (Key::KEY_F5, 0, 5),
+    (Key::KEY_F6, 0, 6),
+    (Key::KEY_F7, 0, 7),
+    (Key::KEY_F8, 0, 8),
+    (Key::KEY_F9, 0, 9),
+    (Key::KEY_F10, 0, 10),
+    (Key::KEY_F11, 0, 11),
+    (Key::KEY_F12, 0, 12),
+    (Key::KEY_DELETE, 0, 13),
+    // Row 1
+    (Key::KEY_GRAVE, 1, 0),
+    (Key::KEY_1, 1, 1),
+    (Key::KEY_2, 1, 2),
+    (Key::KEY_3, 1, 3),
+    (Key::KEY_4, 1, 4),
+    (Key::KEY_5, 1, 5),
+    (Key::KEY_6, 1, 6),
+    (Key::KEY_7, 1, 7),
+    (Key::KEY_8, 1, 8),
+    (Key::KEY_9, 1, 9),
+    (Key::KEY_0, 1, 10),
+    (Key::KEY_MINUS, 1, 11),
+    (Key::KEY_EQUAL, 1, 12),
+    (Key::KEY_BACKSPACE, 1, 13),
+    // Row 2
+    (Key::KEY_TAB, 2, 0),
+    (Key::KEY_Q, 2, 1),
+    (Key::KEY_W, 2, 2),
+    (Key::KEY_E, 2, 3),
+    (Key::KEY_R, 2, 4),
+    (Key::KEY_T, 2, 5),
+    (Key::KEY_Y, 2, 6),
+    (Key::KEY_U, 2, 7),
+    (Key::KEY_I, 2, 8),
+    (Key::KEY_O, 2, 9),
+    (Key::KEY_P, 2, 10),
+    (Key::KEY_LEFTBRACE, 2, 11),
+    (Key::KEY_RIGHTBRACE, 2, 12),
+    (Key::KEY_BACKSLASH, 2, 13),
+    // Row 3
+    (Key::KEY_CAPSLOCK, 3, 0),
+    (Key::KEY_A, 3, 1),
+    (Key::KEY_S, 3, 2),
+    (Key::KEY_D, 3, 3),
+    (Key::KEY_F, 3, 4),
+    (Key::KEY_G, 3, 5),
+    (Key::KEY_H, 3, 6),
+    (Key::KEY_J, 3, 7),
+    (Key::KEY_K, 3, 8),
+    (Key::KEY_L, 3, 9),
+    (Key::KEY_SEMICOLON, 3, 10),
+    (Key::KEY_APOSTROPHE, 3, 11),
+    (Key::KEY_ENTER, 3, 13),
+    // Row 4
+    (Key::KEY_LEFTSHIFT, 4, 0),
+    (Key::KEY_Z, 4, 2),
+    (Key::KEY_X, 4, 3),
+    (Key::KEY_C, 4, 4),
+    (Key::KEY_V, 4, 5),
+    (Key::KEY_B, 4, 6),
+    (Key::KEY_N, 4, 7),
+    (Key::KEY_M, 4, 8),
+    (Key::KEY_COMMA, 4, 9),
+    (Key::KEY_DOT, 4, 10),
+    (Key::KEY_SLASH, 4, 11),
+    (Key::KEY_RIGHTSHIFT, 4, 13),
+    // Row 5
+    (Key::KEY_LEFTCTRL, 5, 0),
+    (Key::KEY_LEFTMETA, 5, 2),
+    (Key::KEY_LEFTALT, 5, 3),
+    (Key::KEY_SPACE, 5, 7),
+    (Key::KEY_RIGHTALT, 5, 9),
+    (Key::KEY_RIGHTCTRL, 5, 11),
+    (Key::KEY_LEFT, 5, 12),
+    (Key::KEY_UP, 5, 13),
+    (Key::KEY_DOWN, 5, 13),
+    (Key::KEY_RIGHT, 5, 14),
+];
+
+/// Maps Linux key codes to key indexes in the keyboard matrix, starting from
+/// the default layout and applying the overrides of the model
+pub fn build_keymap(overrides: &HashMap<u16, u8>) -> HashMap<u16, usize> {
+    let mut keymap: HashMap<u16, usize> = DEFAULT_LAYOUT
+        .iter()
+        .map(|(key, row, col)| (key.code(), row * board::KEYS_PER_ROW + col))
+        .collect();
+    for (code, index) in overrides {
+        if (*index as usize) < board::ROWS * board::KEYS_PER_ROW {
+            keymap.insert(*code, *index as usize);
+        }
+    }
+    keymap
+}
+
+/// Finds the event device of the laptop keyboard. Prefers Razer devices, as
+/// external keyboards may also be plugged in
+pub fn find_keyboard() -> Option<Device> {
+    let mut fallback: Option<Device> = None;
+    for (_, device) in evdev::enumerate() {
+        let is_keyboard = device
+            .supported_keys()
+            .is_some_and(|keys| keys.contains(Key::KEY_A) && keys.contains(Key::KEY_SPACE));
+        if !is_keyboard {
+            continue;
+        }
+        if device.input_id().vendor() == 0x1532 {
+            return Some(device);
+        }
+        if fallback.is_none() {
+            fallback = Some(device);
+        }
+    }
+    fallback
+}
+
+/// A key press or release, with the key already mapped to the matrix
+#[derive(Copy, Clone, Debug)]
+pub struct KeyEvent {
+    pub key: usize,
+    pub pressed: bool,
+}
+
+/// Reads the next batch of events, blocking until there is one. Keys that
+/// are not on the keymap and auto repeats are left out
+pub fn read_events(device: &mut Device, keymap: &HashMap<u16, usize>) -> std::io::Result<Vec<KeyEvent>> {
+    let events = device.fetch_events()?;
+    Ok(events
+        .filter_map(|event| match event.kind() {
+            InputEventKind::Key(key) if event.value() != 2 => keymap.get(&key.code()).map(|idx| KeyEvent {
+                key: *idx,
+                pressed: event.value() == 1,
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Which lock keys are on
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LockState {
+    pub caps: bool,
+    pub num: bool,
+    pub scroll: bool,
+}
+
+/// Reads the lock LEDs of the keyboards from sysfs, like `input3::capslock`.
+/// A lock is on if any keyboard shows it. None if there are no lock LEDs
+pub fn read_lock_leds() -> Option<LockState> {
+    let mut state: Option<LockState> = None;
+    for entry in std::fs::read_dir(LEDS_DIR).ok()?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let lock = match name.rsplit_once("::") {
+            Some((_, lock)) if ["capslock", "numlock", "scrolllock"].contains(&lock) => lock.to_string(),
+            _ => continue,
+        };
+        let on = std::fs::read_to_string(entry.path().join("brightness")).is_ok_and(|b| b.trim() != "0");
+        let state = state.get_or_insert_with(LockState::default);
+        match lock.as_str() {
+            "capslock" => state.caps |= on,
+            "numlock" => state.num |= on,
+            _ => state.scroll |= on,
+        }
+    }
+    state
+}
+
+/// Reads the lock LEDs of an event device, for systems without them in sysfs
+pub fn read_device_locks(device: &Device) -> Option<LockState> {
+    let leds = device.get_led_state().ok()?;
+    Some(LockState {
+        caps: leds.contains(LedType::LED_CAPSL),
+        num: leds.contains(LedType::LED_NUML),
+        scroll: leds.contains(LedType::LED_SCROLLL),
+    })
+}
+
+/// Keys to light for the locks that are on and have an indicator. Lock keys
+/// missing from the keymap are skipped
+pub fn lock_indicator_keys(indicators: &comms::LockIndicators, state: LockState, keymap: &HashMap<u16, usize>) -> Vec<(usize, board::KeyColour)> {
+    let [red, green, blue] = indicators.colour;
+    let locks = [
+        (indicators.caps && state.caps, Key::KEY_CAPSLOCK),
+        (indicators.num && state.num, Key::KEY_NUMLOCK),
+        (indicators.scroll && state.scroll, Key::KEY_SCROLLLOCK),
+    ];
+    locks
+        .iter()
+        .filter(|(lit, _)| *lit)
+        .filter_map(|(_, key)| keymap.get(&key.code()))
+        .map(|idx| (*idx, board::KeyColour { red, green, blue }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keymap_overrides() {
+        let keymap = build_keymap(&HashMap::new());
+        assert_eq!(keymap[&Key::KEY_ESC.code()], 0);
+        assert_eq!(keymap[&Key::KEY_A.code()], 46);
+        assert_eq!(keymap[&Key::KEY_RIGHT.code()], 89);
+        assert!(!keymap.contains_key(&Key::KEY_NUMLOCK.code()));
+
+        let overrides = HashMap::from([(Key::KEY_A.code(), 45), (Key::KEY_NUMLOCK.code(), 14), (Key::KEY_B.code(), 90)]);
+        let keymap = build_keymap(&overrides);
+        assert_eq!(keymap[&Key::KEY_A.code()], 45);
+        assert_eq!(keymap[&Key::KEY_NUMLOCK.code()], 14);
+        // Keys past the matrix are ignored
+        assert_eq!(keymap[&Key::KEY_B.code()], 66);
+    }
+}
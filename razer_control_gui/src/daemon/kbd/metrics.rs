@@ -0,0 +1,35 @@
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use systemstat::{CPULoad, DelayedMeasurement, Platform, System};
+
+lazy_static! {
+    /// CPU load measurement started at the previous sample
+    static ref CPU_MEASUREMENT: Mutex<Option<DelayedMeasurement<CPULoad>>> = Mutex::new(None);
+    /// Battery percentage last reported by UPower
+    static ref BATTERY_PERCENTAGE: Mutex<Option<f32>> = Mutex::new(None);
+}
+
+/// Fraction of time the CPUs were busy since the previous sample, None on the
+/// first sample or if the load can't be read
+pub fn sample_cpu_load() -> Option<f32> {
+    let mut measurement = CPU_MEASUREMENT.lock().unwrap();
+    let load = measurement.as_ref().and_then(|m| m.done().ok()).map(|l| 1.0 - l.idle);
+    *measurement = System::new().cpu_load_aggregate().ok();
+    load
+}
+
+/// CPU temperature in degrees Celsius
+pub fn sample_cpu_temperature() -> Option<f32> {
+    System::new().cpu_temp().ok()
+}
+
+pub fn set_battery_percentage(percentage: f32) {
+    *BATTERY_PERCENTAGE.lock().unwrap() = Some(percentage);
+}
+
+/// Battery percentage from UPower, read from the battery directly until
+/// UPower reports a change
+pub fn sample_battery_percentage() -> Option<f32> {
+    let reported = *BATTERY_PERCENTAGE.lock().unwrap();
+    reported.or_else(|| System::new().battery_life().ok().map(|b| b.remaining_capacity * 100.0))
+}
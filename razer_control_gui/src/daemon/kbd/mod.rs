@@ -1,13 +1,46 @@
 pub mod board;
 pub mod effects;
+pub mod images;
+pub mod keys;
+pub mod metrics;
+pub mod registry;
+pub mod timeline;
+use crate::comms;
 use crate::device;
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-const ANIMATION_FPS: u64 = 10; // 33 ms ~= 30fps
+/// Longest time effects are advanced by in a single frame
+const MAX_FRAME_DELTA_MS: u128 = 250;
+/// Most frames a preview can render
+pub const MAX_PREVIEW_FRAMES: u32 = 600;
+/// How long a notification flash lasts
+const FLASH_MS: u128 = 1500;
+/// Length of one blink of `FlashStyle::Flash`, on and off
+const BLINK_MS: u128 = 500;
 
-pub const ANIMATION_SLEEP_MS: u64 = (1000.0 / ANIMATION_FPS as f32) as u64;
+lazy_static! {
+    /// Set when the effect stack changes, so an idle animator renders again
+    static ref STACK_CHANGED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+}
+
+/// Wakes the animator if it is idle
+fn notify_stack_changed() {
+    let (changed, cvar) = &*STACK_CHANGED;
+    *changed.lock().unwrap() = true;
+    cvar.notify_all();
+}
+
+/// Blocks until the effect stack changes, or until `timeout` passes
+pub fn wait_for_stack_change(timeout: Duration) {
+    let (changed, cvar) = &*STACK_CHANGED;
+    let guard = changed.lock().unwrap();
+    let (mut guard, _) = cvar.wait_timeout_while(guard, timeout, |c| !*c).unwrap();
+    *guard = false;
+}
 
 pub fn get_millis() -> u128 {
     SystemTime::now()
@@ -22,24 +55,103 @@ pub struct EffectSave {
     name: String,
 }
 
+/// Makes an effects save from another laptop loadable on this one, fitting
+/// key masks to the keyboard matrix and dropping effects that can't be loaded.
+/// Returns the fitted save and a description of every change
+pub fn fit_save(json: &serde_json::Value) -> (serde_json::Value, Vec<String>) {
+    let keys = board::ROWS * board::KEYS_PER_ROW;
+    let mut notes: Vec<String> = vec![];
+    let mut effects: Vec<serde_json::Value> = vec![];
+    match json["effects"].as_array() {
+        Some(saves) => {
+            for (idx, save) in saves.iter().enumerate() {
+                let mut save = save.clone();
+                if let Some(mask) = save["key_mask"].as_array_mut() {
+                    if mask.len() != keys {
+                        notes.push(format!("Layer {}: key mask has {} keys, fitted to {}", idx, mask.len(), keys));
+                        mask.resize(keys, serde_json::Value::Bool(false));
+                    }
+                }
+                if EffectLayer::from_save(save.clone()).is_some() {
+                    effects.push(save);
+                } else {
+                    notes.push(format!("Layer {}: effect {} can't be loaded, dropped", idx, save["name"]));
+                }
+            }
+        }
+        None => notes.push("No effects found".into()),
+    }
+    (json!({ "effects": effects }), notes)
+}
+
+/// Effects save with `effect` as the only layer, on every key, like the
+/// stack set by `SetEffect`
+pub fn single_effect_save(effect: Box<dyn Effect>) -> serde_json::Value {
+    let mut manager = EffectManager::new();
+    manager.push_effect(effect, [true; board::ROWS * board::KEYS_PER_ROW]);
+    manager.save()
+}
+
+/// Renders an effects save without a keyboard, advancing the effects by
+/// `frame_ms` between frames. The first frame is the initial state
+pub fn render_preview(json: serde_json::Value, frames: u32, frame_ms: u32) -> Result<Vec<board::KeyboardData>, String> {
+    if frames == 0 || frames > MAX_PREVIEW_FRAMES {
+        return Err(format!("between 1 and {} frames can be rendered", MAX_PREVIEW_FRAMES));
+    }
+    let mut manager = EffectManager::new();
+    if !manager.load_from_save(json) {
+        return Err("the effects can't be loaded".into());
+    }
+    Ok((0..frames)
+        .map(|frame| manager.compose(if frame == 0 { 0.0 } else { frame_ms as f32 }))
+        .collect())
+}
+
 /// Base effect trait.
-/// An effect is a lighting function that is updated 30 times per second
-/// in order to create an animation of some description on the laptop's
+/// An effect is a lighting function that is updated at the configured frame
+/// rate in order to create an animation of some description on the laptop's
 /// keyboard
 pub trait Effect: Send + Sync {
     /// Returns a new instance of an Effect
     fn new(args: Vec<u8>) -> Box<dyn Effect>
     where
         Self: Sized;
-    /// Updates the keyboard, returning the current state of the keyboard
-    /// Called 30 times per second by the Effect Manager
-    fn update(&mut self) -> board::KeyboardData;
+    /// Updates the keyboard, returning the current state of the keyboard.
+    /// Called by the Effect Manager on every frame, with the time since the
+    /// previous frame. Animations must advance by time, not by frame, so
+    /// their speed doesn't depend on the frame rate
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData;
     /// Returns the arguments used to spawn the effect
     fn get_varargs(&mut self) -> &[u8];
+    /// Called for every key press and release while key events are enabled.
+    /// The effect is told about every key, not only the ones in its mask
+    fn key_event(&mut self, _event: keys::KeyEvent) {}
+    /// Returns false if `update` always returns the same frame, so it
+    /// doesn't need to be rendered again
+    fn is_animated(&self) -> bool {
+        true
+    }
     /// Returns the name of the effect (Unique identifier)
     fn get_name() -> &'static str
     where
         Self: Sized;
+    /// Returns the ID clients use to request the effect
+    fn get_id() -> &'static str
+    where
+        Self: Sized;
+    /// Describes the arguments `new` expects, in order. `new` is only ever
+    /// called with arguments that match this description
+    fn get_params() -> Vec<comms::EffectParam>
+    where
+        Self: Sized;
+    /// Checks what `get_params` can't describe, like the contents of a file
+    /// parameter. Called with arguments that already match the parameters
+    fn check_args(_args: &[u8]) -> Result<(), String>
+    where
+        Self: Sized,
+    {
+        Ok(())
+    }
     fn clone_box(&self) -> Box<dyn Effect>;
     fn save(&mut self) -> EffectSave;
     fn get_state(&mut self) -> Vec<u8>;
@@ -49,8 +161,16 @@ pub trait Effect: Send + Sync {
 /// The mask layer tells the Effect Manager to apply the given
 /// Effect to. This allows for stacked effects
 struct EffectLayer {
+    /// Assigned by the Effect Manager, unique while the layer exists
+    id: u32,
+    /// Hidden layers are kept, but not rendered
+    visible: bool,
     /// Mask for keys
     key_mask: Vec<bool>,
+    /// How the layer is combined with the layers below
+    blend: comms::BlendMode,
+    /// Opacity in %, used by every blend mode except `Replace`
+    opacity: u8,
     effect: Box<dyn Effect>,
 }
 
@@ -58,24 +178,46 @@ unsafe impl Send for EffectLayer {}
 unsafe impl Sync for EffectLayer {}
 
 impl EffectLayer {
-    fn new(effect: Box<dyn Effect>, mask: [bool; 90]) -> EffectLayer {
-        return EffectLayer {
-            key_mask: mask.to_vec(),
+    fn new(effect: Box<dyn Effect>, mask: Vec<bool>) -> EffectLayer {
+        EffectLayer {
+            id: 0,
+            visible: true,
+            key_mask: mask,
+            blend: comms::BlendMode::Replace,
+            opacity: 100,
             effect,
+        }
+    }
+
+    /// Combines the colour of this layer (`top`) with the colour of the
+    /// layers below it
+    fn blend(&self, below: board::AnimatorKeyColour, top: board::KeyColour) -> board::AnimatorKeyColour {
+        let top = board::AnimatorKeyColour::new_u(top.red, top.green, top.blue);
+        let opacity = self.opacity as f32 / 100.0;
+        let blended = match self.blend {
+            comms::BlendMode::Replace => return top,
+            comms::BlendMode::Alpha => top,
+            comms::BlendMode::Add => below + top,
+            comms::BlendMode::Multiply => below.multiply(top),
+            comms::BlendMode::Max => below.max(top),
         };
+        let mixed = (below + (blended - below) * opacity).get_clamped_colour();
+        board::AnimatorKeyColour::new_u(mixed.red, mixed.green, mixed.blue)
     }
 
-    fn update(&mut self) -> board::KeyboardData {
-        return self.effect.update();
+    fn update(&mut self, delta_ms: f32) -> board::KeyboardData {
+        self.effect.update(delta_ms)
     }
 
     fn get_save(&mut self) -> Option<serde_json::Value> {
         match serde_json::to_value(self.effect.save()) {
             Ok(mut x) => {
                 let keys = serde_json::to_value(&self.key_mask).unwrap();
-                x.as_object_mut()
-                    .unwrap()
-                    .insert(String::from("key_mask"), keys);
+                let obj = x.as_object_mut().unwrap();
+                obj.insert(String::from("key_mask"), keys);
+                obj.insert(String::from("visible"), serde_json::Value::Bool(self.visible));
+                obj.insert(String::from("blend"), serde_json::to_value(self.blend).unwrap());
+                obj.insert(String::from("opacity"), serde_json::Value::from(self.opacity));
                 Some(x)
             }
             Err(_) => None,
@@ -87,7 +229,13 @@ impl EffectLayer {
             eprintln!("Missing data for effect!");
             return None;
         }
-        let key_mask: Vec<bool> = serde_json::from_value(json["key_mask"].clone()).unwrap();
+        let key_mask: Vec<bool> = match serde_json::from_value(json["key_mask"].clone()) {
+            Ok(key_mask) => key_mask,
+            Err(e) => {
+                eprintln!("Invalid key mask for effect: {}", e);
+                return None;
+            }
+        };
         if key_mask.len() != 90 {
             eprintln!(
                 "Invalid key count effect. Expected 90, found {}",
@@ -95,29 +243,51 @@ impl EffectLayer {
             );
             return None;
         }
-        let name: String = serde_json::from_value(json["name"].clone()).unwrap();
-        let args: Vec<u8> = serde_json::from_value(json["args"].clone()).unwrap();
-
-        let effect: Option<Box<dyn Effect>> = match name.as_str() {
-            "Static" => Some(effects::Static::new(args)),
-            "Wave Gradient" => Some(effects::WaveGradient::new(args)),
-            "Breathing Single" => Some(effects::BreathSingle::new(args)),
-            "Static Gradient" => Some(effects::StaticGradient::new(args)),
-            _ => None,
+        let name: String = serde_json::from_value(json["name"].clone()).unwrap_or_default();
+        let args: Vec<u8> = match serde_json::from_value(json["args"].clone()) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("Invalid arguments for effect {}: {}", name, e);
+                return None;
+            }
         };
-        if effect.is_none() {
-            eprintln!("Effect failed to load. Invalid name: {}", name);
-            return None;
+
+        match registry::load_effect(&name, args) {
+            Ok(effect) => Some(EffectLayer {
+                id: 0,
+                visible: json["visible"].as_bool().unwrap_or(true), // Older saves have no visibility
+                key_mask,
+                blend: serde_json::from_value(json["blend"].clone()).unwrap_or(comms::BlendMode::Replace),
+                opacity: json["opacity"].as_u64().unwrap_or(100).min(100) as u8,
+                effect,
+            }),
+            Err(e) => {
+                eprintln!("Effect failed to load: {}", e);
+                None
+            }
         }
-        return Some(EffectLayer {
-            key_mask,
-            effect: effect.unwrap(),
-        });
     }
 
     pub fn get_state(&mut self) -> Vec<u8> {
         self.effect.get_state()
     }
+
+    fn get_info(&mut self) -> comms::LayerInfo {
+        let save = self.effect.save();
+        let effect = match registry::find_effect(&save.name) {
+            Some(definition) => (definition.id)().to_string(),
+            None => save.name,
+        };
+        comms::LayerInfo {
+            id: self.id,
+            effect,
+            params: save.args,
+            visible: self.visible,
+            key_mask: self.key_mask.clone(),
+            blend: self.blend,
+            opacity: self.opacity,
+        }
+    }
     #[allow(dead_code)]
     pub fn get_mask(&mut self) -> Vec<bool> {
         self.key_mask.to_vec()
@@ -125,8 +295,70 @@ impl EffectLayer {
 }
 pub struct EffectManager {
     layers: Vec<EffectLayer>,
+    next_layer_id: u32,
     last_update_ms: u128,
     render_board: board::KeyboardData,
+    /// Last frame sent to the keyboard, `None` if it has to be sent again
+    uploaded_board: Option<board::KeyboardData>,
+    /// Set when the stack changed since the last render
+    dirty: bool,
+    /// Frames pushed by a client, drawn over the stack or instead of a layer
+    stream: Option<Stream>,
+    next_stream_session: u64,
+    /// Set when a stream ended with no layers to fall back to, so the keyboard
+    /// is turned off on the next update
+    blank_pending: bool,
+    /// Crossfade in progress from the frame shown before the stack changed
+    crossfade: Option<Crossfade>,
+    transition_ms: u32,
+    /// Keys lit by the lock indicators, drawn over everything else
+    indicators: Vec<(usize, board::KeyColour)>,
+    /// Notification flash in progress, drawn over the layers
+    flash: Option<Flash>,
+    /// Animations are frozen while the battery is low
+    paused: bool,
+    /// Set while a standard effect is shown instead of the layers. The layers
+    /// are kept, and come back once a custom effect is set
+    standard_shown: bool,
+    /// Save of the layers a schedule rule replaced with its own, restored
+    /// when it ends. Saved instead of the shown layers meanwhile
+    scheduled: Option<serde_json::Value>,
+}
+
+struct Crossfade {
+    from: board::KeyboardData,
+    start_ms: u128,
+}
+
+struct Flash {
+    colour: board::AnimatorKeyColour,
+    keys: Vec<usize>,
+    style: comms::FlashStyle,
+    start_ms: u128,
+}
+
+impl Flash {
+    /// How much of the flash colour covers the keys, from 0 to 1, or None
+    /// once the flash is over
+    fn strength(&self, now: u128) -> Option<f32> {
+        let elapsed = now.saturating_sub(self.start_ms);
+        if elapsed >= FLASH_MS {
+            return None;
+        }
+        let blink = (elapsed % BLINK_MS) as f32 / BLINK_MS as f32;
+        Some(match self.style {
+            comms::FlashStyle::Flash => if blink < 0.5 { 1.0 } else { 0.0 },
+            comms::FlashStyle::Pulse => (blink * std::f32::consts::PI).sin(),
+        })
+    }
+}
+
+/// A streaming session of a client
+struct Stream {
+    session: u64,
+    /// Layer the stream replaces the effect of, `None` for the whole keyboard
+    layer: Option<u32>,
+    frame: board::KeyboardData,
 }
 
 unsafe impl Send for EffectManager {}
@@ -136,46 +368,413 @@ impl EffectManager {
     pub fn new() -> EffectManager {
         EffectManager {
             layers: vec![],
+            next_layer_id: 0,
             last_update_ms: get_millis(),
             render_board: board::KeyboardData::new(),
+            uploaded_board: None,
+            dirty: true,
+            stream: None,
+            next_stream_session: 0,
+            blank_pending: false,
+            crossfade: None,
+            transition_ms: 0,
+            indicators: vec![],
+            flash: None,
+            paused: false,
+            standard_shown: false,
+            scheduled: None,
         }
     }
 
+    /// Sets how long crossfades take, 0 to switch instantly
+    pub fn set_transition_ms(&mut self, ms: u32) {
+        self.transition_ms = ms;
+    }
+
+    /// Fades from the frame shown now to whatever the stack shows next. A
+    /// crossfade in progress continues from where it is
+    pub fn crossfade(&mut self) {
+        if self.transition_ms == 0 || self.uploaded_board.is_none() || self.paused {
+            return;
+        }
+        self.crossfade = Some(Crossfade {
+            from: self.render_board,
+            start_ms: get_millis(),
+        });
+        self.stack_changed();
+    }
+
+    /// Freezes animated effects on their current frame, for low battery.
+    /// Changes to the stack are still shown
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            self.paused = paused;
+            self.crossfade = None;
+            self.flash = None;
+            self.stack_changed();
+        }
+    }
+
+    /// Lights keys over the layers, streams and crossfades, for the lock key
+    /// indicators. They are only shown while custom effects are
+    pub fn set_indicators(&mut self, keys: Vec<(usize, board::KeyColour)>) {
+        if keys != self.indicators {
+            self.indicators = keys;
+            self.stack_changed();
+        }
+    }
+
+    /// Flashes `keys` in `colour` over the layers for a notification,
+    /// replacing any flash in progress
+    pub fn flash(&mut self, colour: [u8; 3], keys: Vec<usize>, style: comms::FlashStyle) {
+        if self.paused {
+            return;
+        }
+        self.flash = Some(Flash {
+            colour: board::AnimatorKeyColour::new_u(colour[0], colour[1], colour[2]),
+            keys,
+            style,
+            start_ms: get_millis(),
+        });
+        self.stack_changed();
+    }
+
     pub fn push_effect(&mut self, effect: Box<dyn Effect>, mask: [bool; 90]) {
-        self.layers.push(EffectLayer::new(effect, mask))
+        let position = self.layers.len();
+        self.add_layer(EffectLayer::new(effect, mask.to_vec()), position);
     }
 
     pub fn pop_effect(&mut self, laptop: &mut device::RazerLaptop) {
         self.layers.pop();
-        // If no more layers, erase keyboard rendering and set it to black
-        if self.layers.is_empty() {
-            self.render_board.set_kbd_colour(0, 0, 0); 
+        self.stack_changed();
+        self.clear_if_hidden(laptop);
+    }
+
+    /// Renders the stack again on the next update
+    fn stack_changed(&mut self) {
+        self.dirty = true;
+        notify_stack_changed();
+    }
+
+    /// Sends the whole frame again on the next update. Needed after something
+    /// else changed the keyboard lighting
+    pub fn invalidate(&mut self) {
+        self.uploaded_board = None;
+        self.stack_changed();
+    }
+
+    /// Whether custom effects are drawn over the standard effect
+    pub fn is_drawing(&self) -> bool {
+        !self.standard_shown && (self.layers.iter().any(|l| l.visible) || self.stream.is_some())
+    }
+
+    /// Stops drawing the layers so a standard effect can be shown, ending any
+    /// stream. The layers are kept
+    pub fn show_standard_effect(&mut self) {
+        self.standard_shown = true;
+        self.stream = None;
+        self.crossfade = None;
+        self.flash = None;
+        self.blank_pending = false;
+        self.stack_changed();
+    }
+
+    /// Draws the layers again after a standard effect was shown
+    fn show_layers(&mut self) {
+        if self.standard_shown {
+            self.standard_shown = false;
+            self.crossfade = None; // From the standard effect, which isn't in the frame
+            self.uploaded_board = None; // The keyboard is in standard effect mode
+        }
+    }
+
+    /// Inserts a layer at `position` (0 is the bottom layer), returning its ID
+    fn add_layer(&mut self, mut layer: EffectLayer, position: usize) -> u32 {
+        self.show_layers();
+        let id = self.next_layer_id;
+        self.next_layer_id = self.next_layer_id.wrapping_add(1);
+        layer.id = id;
+        self.layers.insert(position, layer);
+        self.stack_changed();
+        id
+    }
+
+    fn find_layer(&self, id: u32) -> Option<usize> {
+        self.layers.iter().position(|l| l.id == id)
+    }
+
+    /// If no layer is visible anymore, erase keyboard rendering and set it to black
+    fn clear_if_hidden(&mut self, laptop: &mut device::RazerLaptop) {
+        // A crossfade goes to black on its own, and a standard effect is left alone
+        if !self.standard_shown && !self.layers.iter().any(|l| l.visible) && self.crossfade.is_none() {
+            self.render_board.set_kbd_colour(0, 0, 0);
             self.render_board.update_kbd(laptop);
             self.render_board.update_custom_mode(laptop);
+            // The keyboard mode may change before the next layer is added
+            self.uploaded_board = None;
         }
     }
 
-    pub fn update(&mut self, laptop: &mut device::RazerLaptop) {
-        // Do nothing if we have no effects!
-        if self.layers.is_empty() {
+    /// Starts streaming frames to the whole keyboard, or instead of the effect
+    /// of a layer. Returns the session to push frames to, None if the layer
+    /// doesn't exist or another client is streaming
+    pub fn start_stream(&mut self, layer: Option<u32>) -> Option<u64> {
+        if self.stream.is_some() {
+            return None;
+        }
+        if let Some(id) = layer {
+            self.find_layer(id)?;
+        }
+        self.show_layers();
+        let session = self.next_stream_session;
+        self.next_stream_session += 1;
+        self.stream = Some(Stream {
+            session,
+            layer,
+            frame: board::KeyboardData::new(),
+        });
+        self.stack_changed();
+        Some(session)
+    }
+
+    /// Shows a frame of a stream, RGB for every key, row by row. Returns false
+    /// if the session or its layer is gone
+    pub fn stream_frame(&mut self, session: u64, frame: &[u8]) -> bool {
+        let stream = match self.stream.as_mut() {
+            Some(stream) if stream.session == session => stream,
+            _ => return false,
+        };
+        if let Some(id) = stream.layer {
+            if !self.layers.iter().any(|l| l.id == id) {
+                return false;
+            }
+        }
+        for (pos, rgb) in frame.chunks_exact(3).take(board::ROWS * board::KEYS_PER_ROW).enumerate() {
+            stream.frame.set_key_at(pos, board::KeyColour { red: rgb[0], green: rgb[1], blue: rgb[2] });
+        }
+        self.stack_changed();
+        true
+    }
+
+    /// Ends a stream, going back to the saved stack
+    pub fn end_stream(&mut self, session: u64) {
+        if self.stream.as_ref().map(|s| s.session) != Some(session) {
             return;
         }
-        for layer in self.layers.iter_mut() {
-            let tmp_board = layer.update();
+        self.stream = None;
+        if !self.layers.iter().any(|l| l.visible) {
+            self.blank_pending = true;
+        }
+        self.stack_changed();
+    }
+
+    /// Passes a key event to the effects of every visible layer
+    pub fn key_event(&mut self, event: keys::KeyEvent) {
+        for layer in self.layers.iter_mut().filter(|l| l.visible) {
+            layer.effect.key_event(event);
+        }
+    }
+
+    pub fn list_layers(&mut self) -> Vec<comms::LayerInfo> {
+        self.layers.iter_mut().map(|l| l.get_info()).collect()
+    }
+
+    /// Inserts an effect at `position`, or on top when `None`.
+    /// Returns the ID of the new layer
+    pub fn insert_effect(&mut self, effect: Box<dyn Effect>, position: Option<usize>, mask: Vec<bool>) -> Option<u32> {
+        let position = position.unwrap_or(self.layers.len());
+        if position > self.layers.len() || mask.len() != board::ROWS * board::KEYS_PER_ROW {
+            return None;
+        }
+        Some(self.add_layer(EffectLayer::new(effect, mask), position))
+    }
+
+    /// Swaps the effect of a layer, keeping its position, mask and visibility
+    pub fn replace_effect(&mut self, id: u32, effect: Box<dyn Effect>) -> bool {
+        match self.find_layer(id) {
+            Some(idx) => {
+                self.show_layers();
+                self.layers[idx].effect = effect;
+                self.stack_changed();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn move_layer(&mut self, id: u32, position: usize) -> bool {
+        match self.find_layer(id) {
+            Some(idx) if position < self.layers.len() => {
+                let layer = self.layers.remove(idx);
+                self.layers.insert(position, layer);
+                self.stack_changed();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove_layer(&mut self, id: u32, laptop: &mut device::RazerLaptop) -> bool {
+        match self.find_layer(id) {
+            Some(idx) => {
+                self.layers.remove(idx);
+                self.stack_changed();
+                self.clear_if_hidden(laptop);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_layer_visible(&mut self, id: u32, visible: bool, laptop: &mut device::RazerLaptop) -> bool {
+        match self.find_layer(id) {
+            Some(idx) => {
+                if visible {
+                    self.show_layers();
+                }
+                self.layers[idx].visible = visible;
+                self.stack_changed();
+                self.clear_if_hidden(laptop);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_layer_blend(&mut self, id: u32, blend: comms::BlendMode, opacity: u8) -> bool {
+        if opacity > 100 {
+            return false;
+        }
+        match self.find_layer(id) {
+            Some(idx) => {
+                self.layers[idx].blend = blend;
+                self.layers[idx].opacity = opacity;
+                self.stack_changed();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_layer_mask(&mut self, id: u32, mask: Vec<bool>) -> bool {
+        if mask.len() != board::ROWS * board::KEYS_PER_ROW {
+            return false;
+        }
+        match self.find_layer(id) {
+            Some(idx) => {
+                self.layers[idx].key_mask = mask;
+                self.stack_changed();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Renders the stack and sends the rows that changed to the keyboard.
+    /// Returns false when nothing is animating, so there is no need to call
+    /// it again until the stack changes
+    pub fn update(&mut self, laptop: &mut device::RazerLaptop) -> bool {
+        let now = get_millis();
+        // Time since the last frame. Longer gaps, like the animator waking up
+        // from idle, are not caught up on
+        let delta_ms = now.saturating_sub(self.last_update_ms).min(MAX_FRAME_DELTA_MS) as f32;
+        self.last_update_ms = now;
+        if self.blank_pending && self.stream.is_none() {
+            self.blank_pending = false;
+            self.clear_if_hidden(laptop);
+        }
+        // Do nothing if we have no effects!
+        if self.standard_shown {
+            self.dirty = false;
+            return false;
+        }
+        if !self.layers.iter().any(|l| l.visible) && self.stream.is_none() && self.crossfade.is_none() {
+            self.dirty = false;
+            return false;
+        }
+        let animating = !self.paused
+            && (self.layers.iter().any(|l| l.visible && l.effect.is_animated())
+                || self.crossfade.is_some()
+                || self.flash.is_some());
+        if !animating && !self.dirty && self.uploaded_board.is_some() {
+            return false;
+        }
+        self.dirty = false;
+        self.render_board = self.compose(delta_ms);
+        if let Some(crossfade) = self.crossfade.as_ref() {
+            let progress = now.saturating_sub(crossfade.start_ms) as f32 / self.transition_ms.max(1) as f32;
+            if progress >= 1.0 {
+                self.crossfade = None;
+            } else {
+                for pos in 0..board::ROWS * board::KEYS_PER_ROW {
+                    let from = crossfade.from.get_key_at(pos);
+                    let to = self.render_board.get_key_at(pos);
+                    let mixed = board::AnimatorKeyColour::new_u(from.red, from.green, from.blue) * (1.0 - progress)
+                        + board::AnimatorKeyColour::new_u(to.red, to.green, to.blue) * progress;
+                    self.render_board.set_key_at(pos, mixed.get_clamped_colour());
+                }
+            }
+        }
+        match self.flash.as_ref().map(|flash| (flash, flash.strength(now))) {
+            Some((flash, Some(strength))) => {
+                for pos in flash.keys.iter().copied() {
+                    let c = self.render_board.get_key_at(pos);
+                    let below = board::AnimatorKeyColour::new_u(c.red, c.green, c.blue);
+                    let mixed = below + (flash.colour - below) * strength;
+                    self.render_board.set_key_at(pos, mixed.get_clamped_colour());
+                }
+            }
+            Some((_, None)) => self.flash = None,
+            None => {}
+        }
+        for (pos, colour) in &self.indicators {
+            self.render_board.set_key_at(*pos, *colour);
+        }
+        // Don't forget to actually render the board
+        if self.render_board.update_changed_rows(laptop, self.uploaded_board.as_ref()) {
+            self.render_board.update_custom_mode(laptop);
+        }
+        self.uploaded_board = Some(self.render_board);
+        animating
+    }
+
+    /// Advances the visible layers by `delta_ms` and combines them, with the
+    /// stream on top. Doesn't touch the keyboard
+    fn compose(&mut self, delta_ms: f32) -> board::KeyboardData {
+        // Keys no layer is drawn on stay black
+        let mut composed = [board::AnimatorKeyColour::new_u(0, 0, 0); board::ROWS * board::KEYS_PER_ROW];
+        for layer in self.layers.iter_mut().filter(|l| l.visible) {
+            let mut tmp_board = layer.update(delta_ms);
+            if let Some(stream) = self.stream.as_ref().filter(|s| s.layer == Some(layer.id)) {
+                tmp_board = stream.frame;
+            }
             for (pos, state) in layer.key_mask.iter().enumerate() {
                 if *state {
-                    self.render_board.set_key_at(pos, tmp_board.get_key_at(pos))
+                    composed[pos] = layer.blend(composed[pos], tmp_board.get_key_at(pos));
                 }
             }
         }
-        // Don't forget to actually render the board
-        self.last_update_ms = get_millis();
-        self.render_board.update_kbd(laptop);
-        self.render_board.update_custom_mode(laptop);
+        // Streams to the whole keyboard cover every layer
+        if let Some(stream) = self.stream.as_ref().filter(|s| s.layer.is_none()) {
+            for (pos, colour) in composed.iter_mut().enumerate() {
+                let c = stream.frame.get_key_at(pos);
+                *colour = board::AnimatorKeyColour::new_u(c.red, c.green, c.blue);
+            }
+        }
+        let mut kbd = board::KeyboardData::new();
+        for (pos, colour) in composed.iter().enumerate() {
+            kbd.set_key_at(pos, colour.get_clamped_colour());
+        }
+        kbd
     }
 
+    /// Saves the layers. While a schedule rule shows its own, the ones it
+    /// replaced are saved instead
     pub fn save(&mut self) -> serde_json::value::Value {
-        let mut save_json = json!({"effects" : []});
+        if let Some(save) = &self.scheduled {
+            return save.clone();
+        }
+        let mut save_json = json!({"effects" : [], "standard_shown": self.standard_shown});
 
         let tmp_saves: Vec<Option<serde_json::Value>> =
             self.layers.iter_mut().map(|l| l.get_save()).collect();
@@ -190,26 +789,173 @@ impl EffectManager {
         return save_json;
     }
 
-    pub fn load_from_save(&mut self, mut json: serde_json::Value) {
-        if json["effects"].is_null() {
-            eprintln!("Invalid json. No effects field!");
-            return;
+    /// Replaces the current layers with the ones from an effects save, ending
+    /// the effects of a schedule rule. Nothing is changed unless every layer
+    /// in the save is valid
+    pub fn load_from_save(&mut self, json: serde_json::Value) -> bool {
+        if !self.load_layers(json) {
+            return false;
         }
-        for e in json["effects"].as_array_mut().unwrap() {
-            if let Some(x) = EffectLayer::from_save(e.clone()) {
-                self.layers.push(x);
-            } else {
-                eprintln!("Error adding effect");
+        self.scheduled = None;
+        true
+    }
+
+    /// Fades to the layers of a schedule rule, keeping the current ones to be
+    /// saved and restored when it ends
+    pub fn show_scheduled(&mut self, json: serde_json::Value) -> bool {
+        let previous = self.scheduled.take();
+        let replaced = previous.clone().unwrap_or_else(|| self.save());
+        self.crossfade();
+        if !self.load_layers(json) {
+            self.scheduled = previous;
+            return false;
+        }
+        self.scheduled = Some(replaced);
+        true
+    }
+
+    /// Fades back to the layers a schedule rule replaced. Returns false if no
+    /// rule is showing its layers
+    pub fn end_scheduled(&mut self) -> bool {
+        let save = match self.scheduled.take() {
+            Some(save) => save,
+            None => return false,
+        };
+        self.crossfade();
+        self.load_layers(save)
+    }
+
+    /// Whether a standard effect is shown instead of the layers
+    pub fn is_standard_shown(&self) -> bool {
+        self.standard_shown
+    }
+
+    fn load_layers(&mut self, json: serde_json::Value) -> bool {
+        let effects = match json["effects"].as_array() {
+            Some(effects) => effects,
+            None => {
+                eprintln!("Invalid json. No effects field!");
+                return false;
+            }
+        };
+        let mut layers = Vec::with_capacity(effects.len());
+        for e in effects {
+            match EffectLayer::from_save(e.clone()) {
+                Some(mut x) => {
+                    x.id = self.next_layer_id.wrapping_add(layers.len() as u32);
+                    layers.push(x)
+                }
+                None => {
+                    eprintln!("Error adding effect");
+                    return false;
+                }
             }
         }
+        self.next_layer_id = self.next_layer_id.wrapping_add(layers.len() as u32);
+        self.layers = layers;
+        self.show_layers();
+        self.standard_shown = json["standard_shown"].as_bool().unwrap_or(false);
+        self.stack_changed();
+        true
     }
 
-    pub fn get_map(&mut self, layer_id: i32) -> Vec<u8> {
+    /// Returns the colours of a layer, or of the rendered keyboard if
+    /// `layer_id` is negative. `None` if there is no such layer
+    pub fn get_map(&mut self, layer_id: i32) -> Option<Vec<u8>> {
         if layer_id < 0 {
             // Requesting global layer
-            return self.render_board.get_curr_state();
-        } else {
-            return self.layers[layer_id as usize].get_state();
+            return Some(self.render_board.get_curr_state());
         }
+        let idx = self.find_layer(layer_id as u32)?;
+        Some(self.layers[idx].get_state())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(name: &str, args: Vec<u8>, mask: impl Fn(usize) -> bool, blend: &str, opacity: u8) -> serde_json::Value {
+        let key_mask: Vec<bool> = (0..board::ROWS * board::KEYS_PER_ROW).map(mask).collect();
+        json!({ "name": name, "args": args, "key_mask": key_mask, "blend": blend, "opacity": opacity })
+    }
+
+    fn key(frame: board::KeyboardData, key: usize) -> (u8, u8, u8) {
+        let c = frame.get_key_at(key);
+        (c.red, c.green, c.blue)
+    }
+
+    #[test]
+    fn layers_are_composited() {
+        let save = json!({ "effects": [
+            layer("Static", vec![200, 0, 0], |_| true, "Replace", 100),
+            layer("Static", vec![0, 0, 200], |key| key < board::KEYS_PER_ROW, "Alpha", 50),
+            layer("Static", vec![0, 200, 0], |key| key == 89, "Replace", 100),
+        ] });
+        let frames = render_preview(save, 1, 100).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(key(frames[0], 0), (100, 0, 100));
+        assert_eq!(key(frames[0], 15), (200, 0, 0));
+        assert_eq!(key(frames[0], 89), (0, 200, 0));
+    }
+
+    #[test]
+    fn single_effects_cover_every_key() {
+        let effect = registry::create_effect("static", vec![0, 0, 200]).unwrap();
+        let frames = render_preview(single_effect_save(effect), 1, 100).unwrap();
+        assert_eq!(key(frames[0], 0), (0, 0, 200));
+        assert_eq!(key(frames[0], board::ROWS * board::KEYS_PER_ROW - 1), (0, 0, 200));
+    }
+
+    #[test]
+    fn frames_advance_by_frame_time() {
+        let animation = r##"{ "loop": false, "keyframes": [
+            { "time_ms": 0, "keys": { "all": "#000000" } },
+            { "time_ms": 1000, "keys": { "all": "#c8c8c8" } }
+        ] }"##;
+        let save = json!({ "effects": [
+            layer("Embedded Keyframe Animation", comms::encode_path(animation), |_| true, "Replace", 100),
+        ] });
+        let frames = render_preview(save, 4, 500).unwrap();
+        let shown: Vec<(u8, u8, u8)> = frames.iter().map(|frame| key(*frame, 40)).collect();
+        assert_eq!(shown, [(0, 0, 0), (100, 100, 100), (200, 200, 200), (200, 200, 200)]);
+    }
+
+    #[test]
+    fn preview_errors() {
+        let save = json!({ "effects": [] });
+        assert!(render_preview(save.clone(), 0, 100).is_err());
+        assert!(render_preview(save.clone(), MAX_PREVIEW_FRAMES + 1, 100).is_err());
+        assert!(render_preview(json!({}), 1, 100).is_err());
+        let unknown = json!({ "effects": [layer("Nope", vec![], |_| true, "Replace", 100)] });
+        assert!(render_preview(unknown, 1, 100).is_err());
+        // An empty stack renders black
+        assert_eq!(key(render_preview(save, 1, 100).unwrap()[0], 0), (0, 0, 0));
+    }
+
+    #[test]
+    fn scheduled_layers_are_not_saved() {
+        let stack = |colour: Vec<u8>| json!({ "effects": [layer("Static", colour, |_| true, "Replace", 100)] });
+        let shown = |k: &mut EffectManager| k.list_layers()[0].params.clone();
+        let mut k = EffectManager::new();
+        assert!(k.load_from_save(stack(vec![255, 0, 0])));
+        let saved = k.save();
+
+        assert!(k.show_scheduled(stack(vec![0, 0, 255])));
+        assert_eq!(shown(&mut k), [0, 0, 255]);
+        assert_eq!(k.save(), saved);
+        // The next rule still restores the layers from before the first one
+        assert!(k.show_scheduled(stack(vec![0, 255, 0])));
+        assert!(!k.show_scheduled(json!({})));
+        assert_eq!(k.save(), saved);
+        assert!(k.end_scheduled());
+        assert_eq!(shown(&mut k), [255, 0, 0]);
+        assert!(!k.end_scheduled());
+
+        // Loading a stack replaces the saved layers and ends the rule's
+        assert!(k.show_scheduled(stack(vec![0, 0, 255])));
+        assert!(k.load_from_save(stack(vec![0, 255, 0])));
+        assert!(!k.end_scheduled());
+        assert_eq!(k.save()["effects"][0]["args"], json!([0, 255, 0]));
     }
 }
@@ -0,0 +1,254 @@
+use super::*;
+
+/// Everything the daemon needs to know about a custom effect
+pub struct EffectDefinition {
+    pub id: fn() -> &'static str,
+    pub name: fn() -> &'static str,
+    pub params: fn() -> Vec<comms::EffectParam>,
+    pub check: fn(&[u8]) -> Result<(), String>,
+    pub create: fn(Vec<u8>) -> Box<dyn Effect>,
+}
+
+macro_rules! effect_definition {
+    ($effect:ty) => {
+        EffectDefinition {
+            id: <$effect>::get_id,
+            name: <$effect>::get_name,
+            params: <$effect>::get_params,
+            check: <$effect>::check_args,
+            create: <$effect>::new,
+        }
+    };
+}
+
+/// Every custom effect the daemon can render. This is the only place effects
+/// have to be added to
+pub static EFFECTS: [EffectDefinition; 16] = [
+    effect_definition!(effects::Static),
+    effect_definition!(effects::StaticGradient),
+    effect_definition!(effects::WaveGradient),
+    effect_definition!(effects::BreathSingle),
+    effect_definition!(effects::Spectrum),
+    effect_definition!(effects::RainbowWave),
+    effect_definition!(effects::Ripple),
+    effect_definition!(effects::Afterglow),
+    effect_definition!(effects::Heatmap),
+    effect_definition!(effects::CpuLoad),
+    effect_definition!(effects::BatteryGauge),
+    effect_definition!(effects::CpuTemperature),
+    effect_definition!(effects::Image),
+    effect_definition!(effects::KeyframeAnimation),
+    effect_definition!(effects::EmbeddedKeyframeAnimation),
+    effect_definition!(effects::PerKey),
+];
+
+pub fn colour_param(name: &str) -> comms::EffectParam {
+    comms::EffectParam {
+        name: name.into(),
+        kind: comms::EffectParamKind::Colour,
+    }
+}
+
+pub fn range_param(name: &str, min: u8, max: u8, default: u8) -> comms::EffectParam {
+    comms::EffectParam {
+        name: name.into(),
+        kind: comms::EffectParamKind::Range { min, max, default },
+    }
+}
+
+pub fn choice_param(name: &str, options: &[&str], default: u8) -> comms::EffectParam {
+    comms::EffectParam {
+        name: name.into(),
+        kind: comms::EffectParamKind::Choice {
+            options: options.iter().map(|o| o.to_string()).collect(),
+            default,
+        },
+    }
+}
+
+pub fn key_colours_param(name: &str) -> comms::EffectParam {
+    comms::EffectParam {
+        name: name.into(),
+        kind: comms::EffectParamKind::KeyColours,
+    }
+}
+
+pub fn file_param(name: &str) -> comms::EffectParam {
+    comms::EffectParam {
+        name: name.into(),
+        kind: comms::EffectParamKind::File,
+    }
+}
+
+pub fn file_contents_param(name: &str) -> comms::EffectParam {
+    comms::EffectParam {
+        name: name.into(),
+        kind: comms::EffectParamKind::FileContents,
+    }
+}
+
+/// Reads a path encoded by `comms::encode_path` at `pos`. Returns the path
+/// and the number of bytes it takes
+pub fn read_path(args: &[u8], pos: usize) -> Option<(String, usize)> {
+    let len = u16::from_le_bytes([*args.get(pos)?, *args.get(pos + 1)?]) as usize;
+    let bytes = args.get(pos + 2..pos + 2 + len)?;
+    let path = String::from_utf8(bytes.to_vec()).ok()?;
+    Some((path, len + 2))
+}
+
+/// Finds an effect by its ID or by the name used in effect saves
+pub fn find_effect(name: &str) -> Option<&'static EffectDefinition> {
+    EFFECTS.iter().find(|e| (e.id)() == name || (e.name)() == name)
+}
+
+pub fn list_effects() -> Vec<comms::EffectInfo> {
+    EFFECTS
+        .iter()
+        .map(|e| comms::EffectInfo {
+            id: (e.id)().into(),
+            display_name: (e.name)().into(),
+            params: (e.params)(),
+        })
+        .collect()
+}
+
+/// Checks that `args` holds exactly the parameters described by `params`,
+/// and that file parameters name a file if `check_files` is set
+pub fn validate_args(params: &[comms::EffectParam], args: &[u8], check_files: bool) -> Result<(), String> {
+    let mut pos = 0;
+    for param in params {
+        match &param.kind {
+            comms::EffectParamKind::Colour => {
+                if args.len() < pos + 3 {
+                    return Err(format!("missing colour {}", param.name));
+                }
+                pos += 3;
+            }
+            comms::EffectParamKind::Range { min, max, .. } => {
+                match args.get(pos) {
+                    Some(v) if v < min || v > max => {
+                        return Err(format!("{} must be between {} and {}, got {}", param.name, min, max, v));
+                    }
+                    Some(_) => pos += 1,
+                    None => return Err(format!("missing {}", param.name)),
+                }
+            }
+            comms::EffectParamKind::Choice { options, .. } => {
+                match args.get(pos) {
+                    Some(v) if *v as usize >= options.len() => {
+                        return Err(format!("invalid {} {}", param.name, v));
+                    }
+                    Some(_) => pos += 1,
+                    None => return Err(format!("missing {}", param.name)),
+                }
+            }
+            comms::EffectParamKind::File => {
+                let (path, len) = read_path(args, pos).ok_or(format!("missing or invalid {}", param.name))?;
+                if check_files && !std::path::Path::new(&path).is_file() {
+                    return Err(format!("{} {} is not a file", param.name, path));
+                }
+                pos += len;
+            }
+            comms::EffectParamKind::FileContents => {
+                let (_, len) = read_path(args, pos).ok_or(format!("missing or invalid {}", param.name))?;
+                pos += len;
+            }
+            comms::EffectParamKind::KeyColours => {
+                let len = board::ROWS * board::KEYS_PER_ROW * 3;
+                if args.len() < pos + len {
+                    return Err(format!("missing {}, {} bytes are needed", param.name, len));
+                }
+                pos += len;
+            }
+        }
+    }
+    if args.len() != pos {
+        return Err(format!("expected {} bytes of parameters, got {}", pos, args.len()));
+    }
+    Ok(())
+}
+
+/// Creates an effect from its ID or save name, validating its arguments
+pub fn create_effect(name: &str, args: Vec<u8>) -> Result<Box<dyn Effect>, String> {
+    let definition = find_effect(name).ok_or(format!("unknown effect {}", name))?;
+    validate_args(&(definition.params)(), &args, true)
+        .and_then(|_| (definition.check)(&args))
+        .map_err(|e| format!("{}: {}", (definition.name)(), e))?;
+    Ok((definition.create)(args))
+}
+
+/// Creates an effect from an effects save. Only the layout of its arguments
+/// is checked, so a layer whose file was moved or broken is kept, drawing
+/// nothing until the file is back, instead of failing the whole save
+pub fn load_effect(name: &str, args: Vec<u8>) -> Result<Box<dyn Effect>, String> {
+    let definition = find_effect(name).ok_or(format!("unknown effect {}", name))?;
+    validate_args(&(definition.params)(), &args, false).map_err(|e| format!("{}: {}", (definition.name)(), e))?;
+    Ok((definition.create)(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Vec<comms::EffectParam> {
+        vec![
+            colour_param("Colour"),
+            range_param("Speed", 1, 10, 5),
+            choice_param("Direction", &["Left", "Right"], 0),
+            file_param("Image"),
+        ]
+    }
+
+    fn args(speed: u8, direction: u8, path: &str) -> Vec<u8> {
+        let mut args = vec![255, 0, 0, speed, direction];
+        args.extend(comms::encode_path(path));
+        args
+    }
+
+    #[test]
+    fn valid_args() {
+        assert_eq!(validate_args(&params(), &args(1, 1, "/etc/hostname"), false), Ok(()));
+        assert_eq!(validate_args(&params(), &args(10, 0, "/no/such/file.png"), false), Ok(()));
+        assert_eq!(validate_args(&[key_colours_param("Keys")], &[0; 270], true), Ok(()));
+        assert_eq!(validate_args(&[], &[], true), Ok(()));
+    }
+
+    #[test]
+    fn invalid_args() {
+        let error = |args: &[u8]| validate_args(&params(), args, false).unwrap_err();
+        assert_eq!(error(&[255, 0]), "missing colour Colour");
+        assert_eq!(error(&args(0, 0, "a.png")), "Speed must be between 1 and 10, got 0");
+        assert_eq!(error(&args(11, 0, "a.png")), "Speed must be between 1 and 10, got 11");
+        assert_eq!(error(&[255, 0, 0, 5]), "missing Direction");
+        assert_eq!(error(&args(5, 2, "a.png")), "invalid Direction 2");
+        assert_eq!(error(&[255, 0, 0, 5, 0, 9, 0, b'a']), "missing or invalid Image");
+        let mut long = args(5, 0, "a.png");
+        long.push(0);
+        assert_eq!(error(&long), format!("expected {} bytes of parameters, got {}", long.len() - 1, long.len()));
+        assert!(validate_args(&[key_colours_param("Keys")], &[0; 269], true).is_err());
+    }
+
+    #[test]
+    fn files_are_only_checked_when_asked() {
+        let args = args(5, 0, "/no/such/file.png");
+        assert_eq!(
+            validate_args(&params(), &args, true),
+            Err("Image /no/such/file.png is not a file".into())
+        );
+        let contents = [file_contents_param("Animation")];
+        assert_eq!(validate_args(&contents, &comms::encode_path("{}"), true), Ok(()));
+    }
+
+    #[test]
+    fn effects_by_id_or_name() {
+        assert!(find_effect("ripple").is_some());
+        assert!(find_effect("Ripple").is_some());
+        assert!(create_effect("nope", vec![]).is_err());
+        let error = create_effect("ripple", vec![255, 0, 0, 0]).err().unwrap();
+        assert_eq!(error, "Ripple: Speed (keys/s) must be between 1 and 50, got 0");
+        // Saves keep layers whose files are gone
+        let image = [vec![0], comms::encode_path("/no/such/file.png")].concat();
+        assert!(create_effect("image", image.clone()).is_err());
+        assert!(load_effect("image", image).is_ok());
+    }
+}
@@ -0,0 +1,391 @@
+//! Keyframe animations, authored as JSON files:
+//!
+//! ```json
+//! {
+//!     "duration_ms": 2000,
+//!     "loop": true,
+//!     "keyframes": [
+//!         { "time_ms": 0, "easing": "ease-in-out", "keys": { "all": "#000000", "wasd": [255, 0, 0] } },
+//!         { "time_ms": 1000, "keys": { "row1": "#00ff00", "30-44": "#0000ff", "37": "#ffffff" } }
+//!     ]
+//! }
+//! ```
+//!
+//! Every keyframe starts from the colours of the previous one, so it only
+//! lists the keys that change. Entries in `keys` are applied in order, later
+//! ones over earlier ones. The easing of a keyframe is used on the way to the
+//! next keyframe
+use super::*;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+
+const KEYS: usize = board::ROWS * board::KEYS_PER_ROW;
+
+/// Key groups that can be used by name
+const NAMED_REGIONS: [(&str, &[usize]); 4] = [
+    ("fkeys", &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]),
+    ("numbers", &[16, 17, 18, 19, 20, 21, 22, 23, 24, 25]),
+    ("wasd", &[32, 46, 47, 48]),
+    ("arrows", &[87, 88, 89]),
+];
+
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Step, // Hold the colours until the next keyframe
+}
+
+impl Easing {
+    /// Maps the linear progress between two keyframes to the eased one
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Step => 0.0,
+        }
+    }
+}
+
+/// Keys an entry of a keyframe applies to
+struct KeySelector(Vec<usize>);
+
+impl<'de> Deserialize<'de> for KeySelector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        parse_selector(&name).map(KeySelector).map_err(de::Error::custom)
+    }
+}
+
+fn parse_key(key: &str) -> Result<usize, String> {
+    match key.trim().parse::<usize>() {
+        Ok(key) if key < KEYS => Ok(key),
+        _ => Err(format!("invalid key {:?}, keys go from 0 to {}", key, KEYS - 1)),
+    }
+}
+
+/// Parses `all`, `rowN`, `colN`, a region name, a key (`row * 15 + column`)
+/// or a range of keys like `30-44`
+pub fn parse_selector(name: &str) -> Result<Vec<usize>, String> {
+    if name == "all" {
+        return Ok((0..KEYS).collect());
+    }
+    if let Some((_, keys)) = NAMED_REGIONS.iter().find(|(region, _)| *region == name) {
+        return Ok(keys.to_vec());
+    }
+    if let Some(row) = name.strip_prefix("row") {
+        return match row.parse::<usize>() {
+            Ok(row) if row < board::ROWS => Ok((0..board::KEYS_PER_ROW).map(|col| row * board::KEYS_PER_ROW + col).collect()),
+            _ => Err(format!("invalid row {:?}, rows go from 0 to {}", name, board::ROWS - 1)),
+        };
+    }
+    if let Some(col) = name.strip_prefix("col") {
+        return match col.parse::<usize>() {
+            Ok(col) if col < board::KEYS_PER_ROW => Ok((0..board::ROWS).map(|row| row * board::KEYS_PER_ROW + col).collect()),
+            _ => Err(format!("invalid column {:?}, columns go from 0 to {}", name, board::KEYS_PER_ROW - 1)),
+        };
+    }
+    if let Some((first, last)) = name.split_once('-') {
+        let (first, last) = (parse_key(first)?, parse_key(last)?);
+        if first > last {
+            return Err(format!("invalid range {:?}", name));
+        }
+        return Ok((first..=last).collect());
+    }
+    parse_key(name).map(|key| vec![key]).map_err(|_| {
+        let regions: Vec<&str> = NAMED_REGIONS.iter().map(|(region, _)| *region).collect();
+        format!(
+            "unknown keys {:?}, expected all, rowN, colN, {}, a key or a range of keys",
+            name,
+            regions.join(", ")
+        )
+    })
+}
+
+/// Either `"#rrggbb"` or `[r, g, b]`
+struct Colour(board::AnimatorKeyColour);
+
+impl<'de> Deserialize<'de> for Colour {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawColour {
+            Hex(String),
+            Rgb([u8; 3]),
+        }
+        match RawColour::deserialize(deserializer) {
+            Ok(RawColour::Rgb([r, g, b])) => Ok(Colour(board::AnimatorKeyColour::new_u(r, g, b))),
+            Ok(RawColour::Hex(hex)) => {
+                let digits = hex.strip_prefix('#').filter(|d| d.len() == 6 && d.is_ascii());
+                match digits.and_then(|d| u32::from_str_radix(d, 16).ok()) {
+                    Some(rgb) => Ok(Colour(board::AnimatorKeyColour::new_u((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))),
+                    None => Err(de::Error::custom(format!("invalid colour {:?}, expected \"#rrggbb\"", hex))),
+                }
+            }
+            Err(_) => Err(de::Error::custom("invalid colour, expected \"#rrggbb\" or [red, green, blue]")),
+        }
+    }
+}
+
+/// The `keys` of a keyframe, in the order they are written
+struct KeyColours(Vec<(KeySelector, Colour)>);
+
+impl<'de> Deserialize<'de> for KeyColours {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyColoursVisitor;
+
+        impl<'de> Visitor<'de> for KeyColoursVisitor {
+            type Value = KeyColours;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of keys to colours")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<KeyColours, A::Error> {
+                let mut entries = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(KeyColours(entries))
+            }
+        }
+
+        deserializer.deserialize_map(KeyColoursVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawKeyframe {
+    time_ms: u32,
+    #[serde(default)]
+    easing: Easing,
+    keys: KeyColours,
+}
+
+/// Reads the keyframes one by one, so keyframes out of order are reported
+/// where they are
+fn ordered_keyframes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<RawKeyframe>, D::Error> {
+    struct KeyframesVisitor;
+
+    impl<'de> Visitor<'de> for KeyframesVisitor {
+        type Value = Vec<RawKeyframe>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of keyframes")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<RawKeyframe>, A::Error> {
+            let mut keyframes: Vec<RawKeyframe> = vec![];
+            while let Some(keyframe) = seq.next_element::<RawKeyframe>()? {
+                if let Some(previous) = keyframes.last() {
+                    if keyframe.time_ms <= previous.time_ms {
+                        return Err(de::Error::custom(format!(
+                            "keyframe at {} ms comes after the one at {} ms, keyframes must be in time order",
+                            keyframe.time_ms, previous.time_ms
+                        )));
+                    }
+                }
+                keyframes.push(keyframe);
+            }
+            if keyframes.is_empty() {
+                return Err(de::Error::custom("there must be at least one keyframe"));
+            }
+            Ok(keyframes)
+        }
+    }
+
+    deserializer.deserialize_seq(KeyframesVisitor)
+}
+
+fn default_loop() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTimeline {
+    duration_ms: Option<u32>,
+    #[serde(rename = "loop", default = "default_loop")]
+    looping: bool,
+    #[serde(deserialize_with = "ordered_keyframes")]
+    keyframes: Vec<RawKeyframe>,
+}
+
+struct Keyframe {
+    time_ms: f32,
+    easing: Easing,
+    colours: [board::AnimatorKeyColour; KEYS],
+}
+
+/// A parsed animation, with the colour of every key at every keyframe
+pub struct Timeline {
+    duration_ms: f32,
+    looping: bool,
+    keyframes: Vec<Keyframe>,
+}
+
+impl Timeline {
+    /// Parses an animation, errors mention the line and column they are at
+    pub fn parse(json: &str) -> Result<Timeline, String> {
+        let raw: RawTimeline = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let last_ms = raw.keyframes.last().map_or(0, |k| k.time_ms);
+        let duration_ms = raw.duration_ms.unwrap_or(last_ms);
+        if duration_ms < last_ms {
+            return Err(format!("duration_ms {} is before the last keyframe at {} ms", duration_ms, last_ms));
+        }
+        if raw.looping && duration_ms == 0 {
+            return Err("looping animations need a duration_ms above 0".into());
+        }
+
+        let mut colours = [board::AnimatorKeyColour::new_f(0.0, 0.0, 0.0); KEYS];
+        let mut keyframes = vec![];
+        for raw_keyframe in raw.keyframes {
+            for (selector, colour) in raw_keyframe.keys.0 {
+                for key in selector.0 {
+                    colours[key] = colour.0;
+                }
+            }
+            keyframes.push(Keyframe {
+                time_ms: raw_keyframe.time_ms as f32,
+                easing: raw_keyframe.easing,
+                colours,
+            });
+        }
+        Ok(Timeline {
+            duration_ms: duration_ms as f32,
+            looping: raw.looping,
+            keyframes,
+        })
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    pub fn duration_ms(&self) -> f32 {
+        self.duration_ms
+    }
+
+    /// Colours of the keyboard `time_ms` after the animation started
+    pub fn frame_at(&self, time_ms: f32) -> board::KeyboardData {
+        let time_ms = if self.looping { time_ms % self.duration_ms } else { time_ms };
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+
+        // Keyframes around the time, and how far apart they are
+        let next_idx = self.keyframes.iter().position(|k| k.time_ms > time_ms);
+        let (from, to, from_ms, span_ms) = match next_idx {
+            Some(0) if self.looping => {
+                // Before the first keyframe, still on the way from the last
+                let span = self.duration_ms - last.time_ms + first.time_ms;
+                (last, first, last.time_ms - self.duration_ms, span)
+            }
+            Some(0) => (first, first, 0.0, 1.0),
+            Some(idx) => {
+                let from = &self.keyframes[idx - 1];
+                let to = &self.keyframes[idx];
+                (from, to, from.time_ms, to.time_ms - from.time_ms)
+            }
+            None if self.looping => (last, first, last.time_ms, self.duration_ms - last.time_ms + first.time_ms),
+            None => (last, last, last.time_ms, 1.0),
+        };
+        let t = if span_ms > 0.0 { ((time_ms - from_ms) / span_ms).clamp(0.0, 1.0) } else { 0.0 };
+        let t = from.easing.apply(t);
+
+        let mut kbd = board::KeyboardData::new();
+        for key in 0..KEYS {
+            let c = (from.colours[key] * (1.0 - t) + to.colours[key] * t).get_clamped_colour();
+            kbd.set_key_colour(key / board::KEYS_PER_ROW, key % board::KEYS_PER_ROW, c.red, c.green, c.blue);
+        }
+        kbd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(frame: board::KeyboardData, key: usize) -> (u8, u8, u8) {
+        let c = frame.get_key_at(key);
+        (c.red, c.green, c.blue)
+    }
+
+    #[test]
+    fn keyframes_out_of_order() {
+        let json = r##"{ "keyframes": [
+            { "time_ms": 500, "keys": { "all": "#ff0000" } },
+            { "time_ms": 500, "keys": { "all": "#00ff00" } }
+        ] }"##;
+        let error = Timeline::parse(json).err().unwrap();
+        assert!(error.contains("keyframe at 500 ms comes after the one at 500 ms"), "{}", error);
+        assert!(error.contains("line 4"), "{}", error);
+
+        let error = Timeline::parse(r#"{ "keyframes": [] }"#).err().unwrap();
+        assert!(error.contains("at least one keyframe"), "{}", error);
+        let json = r#"{ "duration_ms": 100, "keyframes": [{ "time_ms": 200, "keys": {} }] }"#;
+        assert!(Timeline::parse(json).is_err());
+        let json = r#"{ "keyframes": [{ "time_ms": 0, "keys": {} }] }"#;
+        assert!(Timeline::parse(json).err().unwrap().contains("duration_ms above 0"));
+    }
+
+    #[test]
+    fn bad_keys_and_colours() {
+        for keys in [r##"{ "row6": "#ffffff" }"##, r##"{ "44-30": "#ffffff" }"##, r##"{ "90": "#ffffff" }"##, r##"{ "all": "#fff" }"##] {
+            let json = format!(r#"{{ "loop": false, "keyframes": [{{ "time_ms": 0, "keys": {} }}] }}"#, keys);
+            assert!(Timeline::parse(&json).is_err(), "{} should not parse", keys);
+        }
+        assert_eq!(parse_selector("col14").unwrap(), vec![14, 29, 44, 59, 74, 89]);
+        assert_eq!(parse_selector("30-32").unwrap(), vec![30, 31, 32]);
+    }
+
+    #[test]
+    fn later_keys_win_and_carry_over() {
+        let json = r##"{ "loop": false, "keyframes": [
+            { "time_ms": 0, "easing": "step", "keys": { "all": [10, 20, 30], "wasd": "#ff0000" } },
+            { "time_ms": 100, "easing": "step", "keys": { "row0": "#00ff00" } }
+        ] }"##;
+        let timeline = Timeline::parse(json).unwrap();
+        let frame = timeline.frame_at(100.0);
+        assert_eq!(key(frame, 0), (0, 255, 0));
+        assert_eq!(key(frame, 32), (255, 0, 0));
+        assert_eq!(key(frame, 60), (10, 20, 30));
+    }
+
+    #[test]
+    fn linear_and_step_easing() {
+        let json = r##"{ "loop": false, "keyframes": [
+            { "time_ms": 0, "keys": { "all": "#000000" } },
+            { "time_ms": 100, "easing": "step", "keys": { "all": "#c8c8c8" } },
+            { "time_ms": 200, "keys": { "all": "#000000" } }
+        ] }"##;
+        let timeline = Timeline::parse(json).unwrap();
+        assert_eq!(key(timeline.frame_at(50.0), 0), (100, 100, 100));
+        // Step holds the colours until the next keyframe
+        assert_eq!(key(timeline.frame_at(150.0), 0), (200, 200, 200));
+        assert_eq!(key(timeline.frame_at(199.0), 0), (200, 200, 200));
+        // Stays on the last keyframe when not looping
+        assert_eq!(key(timeline.frame_at(5000.0), 0), (0, 0, 0));
+    }
+
+    #[test]
+    fn looping_wraps_to_first_keyframe() {
+        let json = r##"{ "duration_ms": 400, "keyframes": [
+            { "time_ms": 100, "keys": { "all": "#000000" } },
+            { "time_ms": 300, "keys": { "all": "#c8c8c8" } }
+        ] }"##;
+        let timeline = Timeline::parse(json).unwrap();
+        assert!(timeline.is_looping());
+        assert_eq!(key(timeline.frame_at(200.0), 0), (100, 100, 100));
+        // From 300 ms the last keyframe fades back into the first one at 500 ms
+        assert_eq!(key(timeline.frame_at(400.0), 0), (100, 100, 100));
+        assert_eq!(key(timeline.frame_at(0.0), 0), (100, 100, 100));
+        assert_eq!(key(timeline.frame_at(1100.0), 0), (200, 200, 200));
+    }
+}
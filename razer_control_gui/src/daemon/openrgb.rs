@@ -0,0 +1,437 @@
+//! Server side of the OpenRGB network SDK protocol, presenting the laptop
+//! as a single controller with a keyboard matrix zone and a logo zone
+use lazy_static::lazy_static;
+use log::*;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+
+const MAGIC: &[u8; 4] = b"ORGB";
+const HEADER_SIZE: usize = 16;
+/// Highest protocol version understood. Version 1 adds the vendor string
+const PROTOCOL_VERSION: u32 = 1;
+/// Largest packet accepted from a client
+const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const REQUEST_PROTOCOL_VERSION: u32 = 40;
+const SET_CLIENT_NAME: u32 = 50;
+const RGBCONTROLLER_UPDATELEDS: u32 = 1050;
+const RGBCONTROLLER_UPDATEZONELEDS: u32 = 1051;
+const RGBCONTROLLER_UPDATESINGLELED: u32 = 1052;
+const RGBCONTROLLER_SETCUSTOMMODE: u32 = 1100;
+const RGBCONTROLLER_UPDATEMODE: u32 = 1101;
+const RGBCONTROLLER_SAVEMODE: u32 = 1102;
+
+const DEVICE_TYPE_KEYBOARD: i32 = 5;
+const ZONE_TYPE_SINGLE: i32 = 0;
+const ZONE_TYPE_MATRIX: i32 = 2;
+const MODE_FLAG_HAS_PER_LED_COLOR: u32 = 1 << 5;
+const MODE_FLAG_HAS_MODE_SPECIFIC_COLOR: u32 = 1 << 6;
+const MODE_COLORS_NONE: u32 = 0;
+const MODE_COLORS_PER_LED: u32 = 1;
+const MODE_COLORS_MODE_SPECIFIC: u32 = 2;
+
+const ROWS: usize = 6;
+const COLUMNS: usize = 15;
+pub const KEYBOARD_LEDS: usize = ROWS * COLUMNS;
+/// The logo comes after the keys
+pub const LOGO_LED: usize = KEYBOARD_LEDS;
+const LEDS: usize = KEYBOARD_LEDS + 1;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    Direct, // Colour per LED
+    Static, // One colour for everything
+    Off,
+}
+
+const MODES: [(Mode, &str); 3] = [(Mode::Direct, "Direct"), (Mode::Static, "Static"), (Mode::Off, "Off")];
+
+/// The controller as clients see and change it
+pub struct Controller {
+    pub mode: Mode,
+    pub colours: [[u8; 3]; LEDS],
+    pub static_colour: [u8; 3],
+}
+
+impl Controller {
+    /// Colour of every LED in the current mode
+    pub fn shown_colour(&self, led: usize) -> [u8; 3] {
+        match self.mode {
+            Mode::Direct => self.colours[led],
+            Mode::Static => self.static_colour,
+            Mode::Off => [0, 0, 0],
+        }
+    }
+}
+
+lazy_static! {
+    static ref CONTROLLER: Mutex<Controller> = Mutex::new(Controller {
+        mode: Mode::Direct,
+        colours: [[0, 0, 0]; LEDS],
+        static_colour: [255, 255, 255],
+    });
+}
+
+/// Change made by a client, to be shown on the laptop
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Change {
+    Colours,
+    Mode,
+    Save, // The client asked for the current state to be kept
+}
+
+/// Answers the requests of a client until it disconnects. `apply` is called
+/// with the controller for every change
+pub fn serve_client(mut stream: TcpStream, name: &str, apply: &dyn Fn(&Controller, Change)) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; HEADER_SIZE];
+        stream.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad packet magic"));
+        }
+        let device = read_u32(&header, 4);
+        let packet_id = read_u32(&header, 8);
+        let size = read_u32(&header, 12) as usize;
+        if size > MAX_PACKET_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet too large"));
+        }
+        let mut data = vec![0u8; size];
+        stream.read_exact(&mut data)?;
+
+        match packet_id {
+            REQUEST_CONTROLLER_COUNT => reply(&mut stream, 0, packet_id, &1u32.to_le_bytes())?,
+            REQUEST_CONTROLLER_DATA if device == 0 => {
+                let version = if data.len() >= 4 { read_u32(&data, 0) } else { 0 };
+                let description = describe(&CONTROLLER.lock().unwrap(), name, version.min(PROTOCOL_VERSION));
+                reply(&mut stream, device, packet_id, &description)?;
+            }
+            REQUEST_PROTOCOL_VERSION => reply(&mut stream, 0, packet_id, &PROTOCOL_VERSION.to_le_bytes())?,
+            SET_CLIENT_NAME => {
+                let client = String::from_utf8_lossy(&data);
+                info!("OpenRGB client: {}", client.trim_end_matches('\0'));
+            }
+            _ if device != 0 => warn!("OpenRGB packet {} for unknown controller {}", packet_id, device),
+            RGBCONTROLLER_UPDATELEDS => {
+                // Data size, colour count, colours
+                let mut controller = CONTROLLER.lock().unwrap();
+                for (led, colour) in read_colours(&data, 4).into_iter().take(LEDS).enumerate() {
+                    controller.colours[led] = colour;
+                }
+                apply(&controller, Change::Colours);
+            }
+            RGBCONTROLLER_UPDATEZONELEDS => {
+                // Data size, zone, colour count, colours
+                let first = match data.get(4..8).map(|_| read_u32(&data, 4)) {
+                    Some(0) => 0,
+                    Some(1) => LOGO_LED,
+                    _ => continue,
+                };
+                let mut controller = CONTROLLER.lock().unwrap();
+                for (led, colour) in read_colours(&data, 8).into_iter().take(LEDS - first).enumerate() {
+                    controller.colours[first + led] = colour;
+                }
+                apply(&controller, Change::Colours);
+            }
+            RGBCONTROLLER_UPDATESINGLELED if data.len() >= 8 => {
+                let led = read_u32(&data, 0) as usize;
+                if led < LEDS {
+                    let mut controller = CONTROLLER.lock().unwrap();
+                    controller.colours[led] = colour_from(&data[4..8]);
+                    apply(&controller, Change::Colours);
+                }
+            }
+            RGBCONTROLLER_SETCUSTOMMODE => {
+                let mut controller = CONTROLLER.lock().unwrap();
+                controller.mode = Mode::Direct;
+                apply(&controller, Change::Mode);
+            }
+            RGBCONTROLLER_UPDATEMODE | RGBCONTROLLER_SAVEMODE => {
+                // Data size, mode index, mode description
+                let mode = data.get(4..8).map(|_| read_u32(&data, 4) as usize);
+                let mut controller = CONTROLLER.lock().unwrap();
+                if let Some(&(mode, _)) = mode.and_then(|m| MODES.get(m)) {
+                    controller.mode = mode;
+                    if let Some(colour) = read_mode_colour(&data[8..]) {
+                        controller.static_colour = colour;
+                    }
+                    apply(&controller, Change::Mode);
+                }
+                if packet_id == RGBCONTROLLER_SAVEMODE {
+                    apply(&controller, Change::Save);
+                }
+            }
+            _ => debug!("Ignoring OpenRGB packet {}", packet_id),
+        }
+    }
+}
+
+fn reply(stream: &mut TcpStream, device: u32, packet_id: u32, data: &[u8]) -> io::Result<()> {
+    let mut packet = MAGIC.to_vec();
+    packet.extend_from_slice(&device.to_le_bytes());
+    packet.extend_from_slice(&packet_id.to_le_bytes());
+    packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    packet.extend_from_slice(data);
+    stream.write_all(&packet)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// OpenRGB colours are 0x00BBGGRR
+fn colour_from(bytes: &[u8]) -> [u8; 3] {
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+/// Reads a colour count (2 bytes) followed by the colours
+fn read_colours(data: &[u8], pos: usize) -> Vec<[u8; 3]> {
+    let count = match data.get(pos..pos + 2) {
+        Some(count) => u16::from_le_bytes([count[0], count[1]]) as usize,
+        None => return vec![],
+    };
+    data[pos + 2..].chunks_exact(4).take(count).map(colour_from).collect()
+}
+
+/// First colour of a mode description, as sent with mode changes
+fn read_mode_colour(mode: &[u8]) -> Option<[u8; 3]> {
+    let name_len = u16::from_le_bytes([*mode.first()?, *mode.get(1)?]) as usize;
+    // Value, flags, speed min and max, colours min and max, speed, direction
+    // and colour mode, 4 bytes each
+    let colours = 2 + name_len + 9 * 4;
+    read_colours(mode, colours).first().copied()
+}
+
+/// Builds controller descriptions in the SDK format
+struct Description(Vec<u8>);
+
+impl Description {
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Length including the null terminator, then the string
+    fn string(&mut self, value: &str) {
+        self.u16(value.len() as u16 + 1);
+        self.0.extend_from_slice(value.as_bytes());
+        self.0.push(0);
+    }
+
+    fn colour(&mut self, colour: [u8; 3]) {
+        self.0.extend_from_slice(&[colour[0], colour[1], colour[2], 0]);
+    }
+}
+
+fn describe(controller: &Controller, name: &str, version: u32) -> Vec<u8> {
+    let mut d = Description(vec![]);
+    d.i32(DEVICE_TYPE_KEYBOARD);
+    d.string(name);
+    if version >= 1 {
+        d.string("Razer");
+    }
+    d.string("Laptop keyboard and logo, controlled by razercontrol");
+    d.string(env!("CARGO_PKG_VERSION"));
+    d.string(""); // Serial
+    d.string("razercontrol daemon");
+
+    d.u16(MODES.len() as u16);
+    let active = MODES.iter().position(|(mode, _)| *mode == controller.mode).unwrap_or(0);
+    d.i32(active as i32);
+    for (value, (mode, name)) in MODES.iter().enumerate() {
+        let (flags, colour_mode, colours) = match mode {
+            Mode::Direct => (MODE_FLAG_HAS_PER_LED_COLOR, MODE_COLORS_PER_LED, 0),
+            Mode::Static => (MODE_FLAG_HAS_MODE_SPECIFIC_COLOR, MODE_COLORS_MODE_SPECIFIC, 1),
+            Mode::Off => (0, MODE_COLORS_NONE, 0),
+        };
+        d.string(name);
+        d.i32(value as i32);
+        d.u32(flags);
+        d.u32(0); // Speed min
+        d.u32(0); // Speed max
+        d.u32(colours); // Colours min
+        d.u32(colours); // Colours max
+        d.u32(0); // Speed
+        d.u32(0); // Direction
+        d.u32(colour_mode);
+        d.u16(colours as u16);
+        if colours > 0 {
+            d.colour(controller.static_colour);
+        }
+    }
+
+    d.u16(2); // Zones
+    d.string("Keyboard");
+    d.i32(ZONE_TYPE_MATRIX);
+    for _ in 0..3 {
+        d.u32(KEYBOARD_LEDS as u32); // LEDs min, max and count
+    }
+    d.u16((8 + 4 * KEYBOARD_LEDS) as u16); // Matrix size in bytes
+    d.u32(ROWS as u32);
+    d.u32(COLUMNS as u32);
+    for led in 0..KEYBOARD_LEDS {
+        d.u32(led as u32);
+    }
+    d.string("Logo");
+    d.i32(ZONE_TYPE_SINGLE);
+    for _ in 0..3 {
+        d.u32(1);
+    }
+    d.u16(0); // No matrix
+
+    d.u16(LEDS as u16);
+    for led in 0..KEYBOARD_LEDS {
+        d.string(&format!("Key {}", led));
+        d.u32(led as u32);
+    }
+    d.string("Logo");
+    d.u32(LOGO_LED as u32);
+
+    d.u16(LEDS as u16);
+    for colour in controller.colours.iter() {
+        d.colour(*colour);
+    }
+
+    // The size comes first and counts itself
+    let mut description = ((d.0.len() + 4) as u32).to_le_bytes().to_vec();
+    description.extend(d.0);
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    fn packet(device: u32, packet_id: u32, data: &[u8]) -> Vec<u8> {
+        let mut packet = MAGIC.to_vec();
+        for value in [device, packet_id, data.len() as u32] {
+            packet.extend_from_slice(&value.to_le_bytes());
+        }
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// Data size, colour count, colours
+    fn colours(prefix: &[u8], colours: &[[u8; 3]]) -> Vec<u8> {
+        let mut d = Description(vec![0; 4]);
+        d.0.extend_from_slice(prefix);
+        d.u16(colours.len() as u16);
+        for colour in colours {
+            d.colour(*colour);
+        }
+        d.0
+    }
+
+    fn mode(index: u32, colour: Option<[u8; 3]>) -> Vec<u8> {
+        let mut d = Description(vec![0; 4]);
+        d.u32(index);
+        d.string(MODES[index as usize].1);
+        for _ in 0..9 {
+            d.u32(0);
+        }
+        d.u16(colour.is_some() as u16);
+        if let Some(colour) = colour {
+            d.colour(colour);
+        }
+        d.0
+    }
+
+    fn read_reply(stream: &mut TcpStream) -> (u32, Vec<u8>) {
+        let mut header = [0u8; HEADER_SIZE];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(&header[0..4], MAGIC);
+        let mut data = vec![0u8; read_u32(&header, 12) as usize];
+        stream.read_exact(&mut data).unwrap();
+        (read_u32(&header, 8), data)
+    }
+
+    #[test]
+    fn colour_lists() {
+        let data = colours(&[], &[[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(read_colours(&data, 4), [[1, 2, 3], [4, 5, 6]]);
+        // Short packets only give the colours they have
+        assert_eq!(read_colours(&data[..data.len() - 1], 4), [[1, 2, 3]]);
+        assert!(read_colours(&data, data.len()).is_empty());
+        assert_eq!(read_mode_colour(&mode(1, Some([9, 8, 7]))[8..]), Some([9, 8, 7]));
+        assert_eq!(read_mode_colour(&mode(2, None)[8..]), None);
+        assert_eq!(read_mode_colour(&[]), None);
+    }
+
+    #[test]
+    fn descriptions() {
+        let controller = Controller {
+            mode: Mode::Static,
+            colours: [[0, 0, 0]; LEDS],
+            static_colour: [255, 0, 0],
+        };
+        let description = describe(&controller, "Blade", 1);
+        assert_eq!(read_u32(&description, 0) as usize, description.len());
+        assert_eq!(read_u32(&description, 4) as i32, DEVICE_TYPE_KEYBOARD);
+        assert_eq!(&description[8..16], b"\x06\0Blade\0");
+        assert_eq!(&description[16..23], b"\x06\0Razer");
+        // Version 0 has no vendor
+        let old = describe(&controller, "Blade", 0);
+        assert_eq!(old.len(), description.len() - 8);
+        assert_ne!(&old[16..23], b"\x06\0Razer");
+    }
+
+    #[test]
+    fn client_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let apply = |controller: &Controller, change: Change| {
+                let shown = [0, 5, LOGO_LED].map(|led| controller.shown_colour(led));
+                tx.send((change, shown)).unwrap();
+            };
+            serve_client(stream, "Blade", &apply)
+        });
+
+        client.write_all(&packet(0, REQUEST_CONTROLLER_COUNT, &[])).unwrap();
+        assert_eq!(read_reply(&mut client), (REQUEST_CONTROLLER_COUNT, 1u32.to_le_bytes().to_vec()));
+        client.write_all(&packet(0, REQUEST_PROTOCOL_VERSION, &5u32.to_le_bytes())).unwrap();
+        assert_eq!(read_reply(&mut client), (REQUEST_PROTOCOL_VERSION, PROTOCOL_VERSION.to_le_bytes().to_vec()));
+        client.write_all(&packet(0, REQUEST_CONTROLLER_DATA, &0u32.to_le_bytes())).unwrap();
+        let (packet_id, description) = read_reply(&mut client);
+        assert_eq!(packet_id, REQUEST_CONTROLLER_DATA);
+        assert_eq!(read_u32(&description, 0) as usize, description.len());
+
+        client.write_all(&packet(0, SET_CLIENT_NAME, b"test\0")).unwrap();
+        client.write_all(&packet(0, RGBCONTROLLER_SETCUSTOMMODE, &[])).unwrap();
+        assert_eq!(rx.recv().unwrap().0, Change::Mode);
+        client.write_all(&packet(0, RGBCONTROLLER_UPDATELEDS, &colours(&[], &[[1, 1, 1]; 6]))).unwrap();
+        assert_eq!(rx.recv().unwrap(), (Change::Colours, [[1, 1, 1], [1, 1, 1], [0, 0, 0]]));
+        client.write_all(&packet(0, RGBCONTROLLER_UPDATEZONELEDS, &colours(&1u32.to_le_bytes(), &[[2, 2, 2]]))).unwrap();
+        assert_eq!(rx.recv().unwrap(), (Change::Colours, [[1, 1, 1], [1, 1, 1], [2, 2, 2]]));
+        let mut single = 5u32.to_le_bytes().to_vec();
+        single.extend_from_slice(&[3, 3, 3, 0]);
+        client.write_all(&packet(0, RGBCONTROLLER_UPDATESINGLELED, &single)).unwrap();
+        assert_eq!(rx.recv().unwrap(), (Change::Colours, [[1, 1, 1], [3, 3, 3], [2, 2, 2]]));
+
+        // Packets for other controllers and unknown zones are ignored
+        client.write_all(&packet(1, RGBCONTROLLER_UPDATELEDS, &colours(&[], &[[9, 9, 9]]))).unwrap();
+        client.write_all(&packet(0, RGBCONTROLLER_UPDATEZONELEDS, &colours(&2u32.to_le_bytes(), &[[9, 9, 9]]))).unwrap();
+        client.write_all(&packet(0, RGBCONTROLLER_UPDATEMODE, &mode(1, Some([4, 4, 4])))).unwrap();
+        assert_eq!(rx.recv().unwrap(), (Change::Mode, [[4, 4, 4]; 3]));
+        client.write_all(&packet(0, RGBCONTROLLER_SAVEMODE, &mode(2, None))).unwrap();
+        assert_eq!(rx.recv().unwrap(), (Change::Mode, [[0, 0, 0]; 3]));
+        assert_eq!(rx.recv().unwrap().0, Change::Save);
+
+        client.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+        assert_eq!(server.join().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(rx.try_recv().is_err());
+    }
+}
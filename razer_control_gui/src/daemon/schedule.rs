@@ -0,0 +1,307 @@
+//! Time of day schedules. Rules are checked in order and the first one that
+//! matches the current time is active. Times are read from a `Clock`, so the
+//! schedule can be checked against any time
+use crate::comms;
+use crate::kbd;
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, Timelike};
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: i32 = 24 * 60;
+
+/// Source of the current local time
+pub trait Clock: Send {
+    fn now(&self) -> DateTime<FixedOffset>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        Local::now().fixed_offset()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Time {
+    Clock(i32), // Minutes after midnight
+    Sunrise(i32), // Offset in minutes
+    Sunset(i32),
+}
+
+struct Rule {
+    days: [bool; 7], // Monday first
+    start: Time,
+    end: Time,
+}
+
+/// A parsed schedule
+pub struct Schedule {
+    rules: Vec<Rule>,
+    location: Option<[f64; 2]>,
+}
+
+impl Schedule {
+    /// Parses the days and times of every rule, errors name the rule
+    pub fn new(rules: &[comms::ScheduleRule], location: Option<[f64; 2]>) -> Result<Schedule, String> {
+        if let Some([lat, lon]) = location {
+            if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                return Err(format!("invalid location {}, {}", lat, lon));
+            }
+        }
+        let mut parsed = Vec::with_capacity(rules.len());
+        for rule in rules {
+            parsed.push(parse_rule(rule, location.is_some()).map_err(|e| format!("rule {:?}: {}", rule.name, e))?);
+        }
+        Ok(Schedule { rules: parsed, location })
+    }
+
+    /// Index of the rule active at `now`, the first one that matches
+    pub fn active_rule(&self, now: DateTime<FixedOffset>) -> Option<usize> {
+        let minute = (now.hour() * 60 + now.minute()) as i32;
+        let today = now.date_naive();
+        let yesterday = today - Duration::days(1);
+        let offset = now.offset().local_minus_utc() / 60;
+        self.rules.iter().position(|rule| {
+            let on = |date: NaiveDate| rule.days[date.weekday().num_days_from_monday() as usize];
+            let times = |date: NaiveDate| Some((self.resolve(rule.start, date, offset)?, self.resolve(rule.end, date, offset)?));
+            match times(today) {
+                Some((start, end)) if start == end => on(today), // The whole day
+                Some((start, end)) if start < end => on(today) && (start..end).contains(&minute),
+                // Past midnight, started today or still going from yesterday
+                Some((start, _)) if on(today) && minute >= start => true,
+                _ => on(yesterday) && times(yesterday).is_some_and(|(start, end)| start > end && minute < end),
+            }
+        })
+    }
+
+    /// Minutes after midnight of a time on `date`, None if the sun doesn't
+    /// rise or set that day
+    fn resolve(&self, time: Time, date: NaiveDate, utc_offset: i32) -> Option<i32> {
+        let sun = || {
+            let [lat, lon] = self.location?;
+            sun_times(date, lat, lon)
+        };
+        let minutes = match time {
+            Time::Clock(minutes) => return Some(minutes),
+            Time::Sunrise(offset) => sun()?.0 + offset,
+            Time::Sunset(offset) => sun()?.1 + offset,
+        };
+        Some((minutes + utc_offset).clamp(0, MINUTES_PER_DAY - 1))
+    }
+}
+
+/// Checks a schedule, including what its rules do
+pub fn validate(rules: &[comms::ScheduleRule], location: Option<[f64; 2]>) -> Result<(), String> {
+    Schedule::new(rules, location)?;
+    for rule in rules {
+        for action in &rule.actions {
+            let error = match action {
+                comms::ScheduleAction::Brightness { percent } | comms::ScheduleAction::LogoBrightness { percent } if *percent > 100 => {
+                    Some("brightness goes up to 100%".into())
+                }
+                comms::ScheduleAction::Logo { state } if *state > 3 => Some(format!("invalid logo state {}", state)),
+                comms::ScheduleAction::Power { pwr, cpu, gpu } if *pwr > 4 || *cpu > 3 || *gpu > 2 => {
+                    Some(format!("invalid power mode {} {} {}", pwr, cpu, gpu))
+                }
+                comms::ScheduleAction::Effect { name, params } => kbd::registry::create_effect(name, params.clone()).err(),
+                _ => None,
+            };
+            if let Some(error) = error {
+                return Err(format!("rule {:?}: {}", rule.name, error));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_rule(rule: &comms::ScheduleRule, have_location: bool) -> Result<Rule, String> {
+    let parsed = Rule {
+        days: parse_days(&rule.days)?,
+        start: parse_time(&rule.start)?,
+        end: parse_time(&rule.end)?,
+    };
+    let uses_sun = [parsed.start, parsed.end].iter().any(|t| !matches!(t, Time::Clock(_)));
+    if uses_sun && !have_location {
+        return Err("sunrise and sunset need a location".into());
+    }
+    Ok(parsed)
+}
+
+/// Parses days like "mon-fri", "sat,sun" or "all"
+fn parse_days(days: &str) -> Result<[bool; 7], String> {
+    let day = |name: &str| {
+        DAY_NAMES
+            .iter()
+            .position(|d| *d == name.trim())
+            .ok_or(format!("unknown day {:?}, days are mon, tue, wed, thu, fri, sat and sun", name.trim()))
+    };
+    let mut on = [false; 7];
+    for part in days.to_ascii_lowercase().split(',') {
+        if part.trim() == "all" {
+            on = [true; 7];
+            continue;
+        }
+        match part.split_once('-') {
+            // Ranges can wrap around the week, like sat-mon
+            Some((first, last)) => {
+                let (first, last) = (day(first)?, day(last)?);
+                let mut d = first;
+                loop {
+                    on[d] = true;
+                    if d == last {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => on[day(part)?] = true,
+        }
+    }
+    Ok(on)
+}
+
+/// Parses "HH:MM", or "sunrise" and "sunset" with an optional offset in
+/// minutes like "sunset-30"
+fn parse_time(time: &str) -> Result<Time, String> {
+    let time = time.trim().to_ascii_lowercase();
+    for (name, sun) in [("sunrise", Time::Sunrise as fn(i32) -> Time), ("sunset", Time::Sunset)] {
+        if let Some(offset) = time.strip_prefix(name) {
+            let offset = match offset.trim() {
+                "" => 0,
+                offset => offset
+                    .trim_start_matches('+')
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|o| o.abs() < MINUTES_PER_DAY)
+                    .ok_or(format!("invalid offset {:?}, expected minutes like {}-30", offset, name))?,
+            };
+            return Ok(sun(offset));
+        }
+    }
+    match time.split_once(':').map(|(h, m)| (h.parse::<i32>(), m.parse::<i32>())) {
+        Some((Ok(h), Ok(m))) if (0..24).contains(&h) && (0..60).contains(&m) && time.len() <= 5 => Ok(Time::Clock(h * 60 + m)),
+        _ => Err(format!("invalid time {:?}, expected HH:MM, sunrise or sunset", time)),
+    }
+}
+
+/// Sunrise and sunset in minutes after midnight UTC, from the NOAA solar
+/// equations. None during polar day or night
+fn sun_times(date: NaiveDate, lat: f64, lon: f64) -> Option<(i32, i32)> {
+    let gamma = 2.0 * std::f64::consts::PI / 365.0 * (date.ordinal0() as f64);
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin() - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let decl = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+    let lat = lat.to_radians();
+    let cos_ha = 90.833f64.to_radians().cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+    if !(-1.0..=1.0).contains(&cos_ha) {
+        return None;
+    }
+    let ha = cos_ha.acos().to_degrees();
+    let sunrise = 720.0 - 4.0 * (lon + ha) - eqtime;
+    let sunset = 720.0 - 4.0 * (lon - ha) - eqtime;
+    Some((sunrise.round() as i32, sunset.round() as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Clock stopped at a fixed time
+    struct FakeClock(DateTime<FixedOffset>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<FixedOffset> {
+            self.0
+        }
+    }
+
+    // 2024-01-01 is a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> FakeClock {
+        FakeClock(FixedOffset::east_opt(0).unwrap().with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap())
+    }
+
+    fn rule(name: &str, days: &str, start: &str, end: &str) -> comms::ScheduleRule {
+        comms::ScheduleRule {
+            name: name.into(),
+            days: days.into(),
+            start: start.into(),
+            end: end.into(),
+            actions: Vec::new(),
+        }
+    }
+
+    fn active(schedule: &Schedule, clock: &dyn Clock) -> Option<usize> {
+        schedule.active_rule(clock.now())
+    }
+
+    #[test]
+    fn same_day_rule() {
+        let schedule = Schedule::new(&[rule("work", "mon-fri", "09:00", "17:00")], None).unwrap();
+        assert_eq!(active(&schedule, &at(1, 8, 59)), None);
+        assert_eq!(active(&schedule, &at(1, 9, 0)), Some(0));
+        assert_eq!(active(&schedule, &at(5, 16, 59)), Some(0));
+        assert_eq!(active(&schedule, &at(5, 17, 0)), None);
+        assert_eq!(active(&schedule, &at(6, 12, 0)), None);
+    }
+
+    #[test]
+    fn past_midnight_rule() {
+        let schedule = Schedule::new(&[rule("night", "fri", "22:00", "06:00")], None).unwrap();
+        assert_eq!(active(&schedule, &at(5, 21, 59)), None);
+        assert_eq!(active(&schedule, &at(5, 22, 0)), Some(0));
+        // Still going on saturday morning, but doesn't start again
+        assert_eq!(active(&schedule, &at(6, 5, 59)), Some(0));
+        assert_eq!(active(&schedule, &at(6, 6, 0)), None);
+        assert_eq!(active(&schedule, &at(6, 23, 0)), None);
+        // Thursday night isn't on, so friday morning isn't either
+        assert_eq!(active(&schedule, &at(5, 1, 0)), None);
+    }
+
+    #[test]
+    fn whole_day_and_first_match() {
+        let rules = [rule("evening", "all", "18:00", "23:00"), rule("weekend", "sat,sun", "00:00", "00:00")];
+        let schedule = Schedule::new(&rules, None).unwrap();
+        assert_eq!(active(&schedule, &at(6, 0, 0)), Some(1));
+        assert_eq!(active(&schedule, &at(7, 19, 0)), Some(0));
+        assert_eq!(active(&schedule, &at(7, 23, 59)), Some(1));
+        assert_eq!(active(&schedule, &at(1, 12, 0)), None);
+    }
+
+    #[test]
+    fn day_ranges_wrap() {
+        assert_eq!(parse_days("sat-mon").unwrap(), [true, false, false, false, false, true, true]);
+        assert_eq!(parse_days("Tue, thu").unwrap(), [false, true, false, true, false, false, false]);
+        assert_eq!(parse_days("all").unwrap(), [true; 7]);
+        assert!(parse_days("mon-funday").is_err());
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("07:30").unwrap(), Time::Clock(450));
+        assert_eq!(parse_time("sunset-30").unwrap(), Time::Sunset(-30));
+        assert_eq!(parse_time("Sunrise +15").unwrap(), Time::Sunrise(15));
+        for bad in ["24:00", "7", "07:60", "007:30", "sunset-1440", "noon"] {
+            assert!(parse_time(bad).is_err(), "{:?} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn sun_needs_location() {
+        let rules = [rule("dark", "all", "sunset", "sunrise")];
+        let error = Schedule::new(&rules, None).err().unwrap();
+        assert!(error.contains("\"dark\""), "{}", error);
+        assert!(Schedule::new(&rules, Some([91.0, 0.0])).is_err());
+        let schedule = Schedule::new(&rules, Some([51.5, 0.0])).unwrap();
+        assert_eq!(active(&schedule, &at(1, 12, 0)), None);
+        assert_eq!(active(&schedule, &at(1, 22, 0)), Some(0));
+        assert_eq!(active(&schedule, &at(2, 5, 0)), Some(0));
+        // The sun doesn't set or rise in the arctic winter
+        let schedule = Schedule::new(&rules, Some([80.0, 0.0])).unwrap();
+        assert_eq!(active(&schedule, &at(1, 22, 0)), None);
+    }
+}
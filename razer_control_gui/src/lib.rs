@@ -27,4 +27,9 @@ impl SupportedDevice {
         self.has_feature("logo")
     }
 
+    /// Whether the logo can cycle through colours
+    pub fn has_logo_spectrum(&self) -> bool {
+        self.has_feature("logo_spectrum")
+    }
+
 }
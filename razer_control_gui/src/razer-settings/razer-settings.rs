@@ -1,10 +1,13 @@
+use std::cell::RefCell;
 use std::io::ErrorKind;
+use std::rc::Rc;
 
 use gtk::prelude::*;
 use gtk::{Application, ApplicationWindow};
 use gtk::{
     Box, Label, Scale, Stack, StackSwitcher, Switch, ToolItem, Toolbar,
-    ComboBoxText, Button, ColorButton, LinkButton
+    ColorButton, ComboBoxText, Button, LinkButton, FileChooserAction,
+    FileChooserDialog, MessageDialog, DialogFlags, ResponseType
 };
 use gtk::{glib, glib::clone};
         
@@ -24,7 +27,10 @@ use util::*;
 
 fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
     match comms::try_bind() {
-        Ok(socket) => comms::send_to_daemon(opt, socket),
+        Ok(socket) => match comms::send_to_daemon(opt, socket) {
+            Some(comms::DaemonResponse::Error { message }) => crash_with_msg(message),
+            res => res,
+        },
         Err(error) if error.kind() == ErrorKind::NotFound => {
             crash_with_msg("Can't connect to the daemon");
         }
@@ -85,6 +91,38 @@ fn set_bho(is_on: bool, threshold: u8) -> Option<bool> {
     }
 }
 
+fn get_low_battery() -> Option<(f64, comms::LowBattery)> {
+    let response = send_data(comms::DaemonCommand::GetLowBattery())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetLowBattery { threshold, actions, .. } => {
+            Some((threshold, actions))
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetLowBattery got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_low_battery(threshold: f64, actions: comms::LowBattery) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetLowBattery { threshold, actions })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetLowBattery { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetLowBattery got {response:?}");
+            None
+        }
+    }
+}
+
 fn get_brightness(ac: bool) -> Option<u8> {
     let ac = if ac { 1 } else { 0 };
     let response = send_data(comms::DaemonCommand::GetBrightness{ ac })?;
@@ -102,6 +140,185 @@ fn get_brightness(ac: bool) -> Option<u8> {
     }
 }
 
+fn get_animation_fps(ac: bool) -> Option<u8> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::GetAnimationFps{ ac })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetAnimationFps { fps } => {
+            Some(fps)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetAnimationFps got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_animation_fps(ac: bool, fps: u8) -> Option<bool> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::SetAnimationFps{ ac, fps })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetAnimationFps { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetAnimationFps got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_key_events() -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::GetKeyEvents())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetKeyEvents { enabled } => {
+            Some(enabled)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetKeyEvents got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_key_events(enabled: bool) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetKeyEvents{ enabled })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetKeyEvents { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetKeyEvents got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_lock_indicators() -> Option<comms::LockIndicators> {
+    let response = send_data(comms::DaemonCommand::GetLockIndicators())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetLockIndicators { indicators } => {
+            Some(indicators)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetLockIndicators got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_lock_indicators(indicators: comms::LockIndicators) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetLockIndicators{ indicators })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetLockIndicators { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetLockIndicators got {response:?}");
+            None
+        }
+    }
+}
+
+/// Whether notifications are shown and whether do not disturb is on
+fn get_notifications() -> Option<(bool, bool)> {
+    let response = send_data(comms::DaemonCommand::GetNotifications())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetNotifications { enabled, do_not_disturb, .. } => {
+            Some((enabled, do_not_disturb))
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetNotifications got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_notifications(enabled: bool) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetNotifications{ enabled })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetNotifications { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetNotifications got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_do_not_disturb(enabled: bool) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetDoNotDisturb{ enabled })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetDoNotDisturb { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetDoNotDisturb got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_transitions() -> Option<u32> {
+    let response = send_data(comms::DaemonCommand::GetTransitions())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetTransitions { ms } => {
+            Some(ms)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetTransitions got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_transitions(ms: u32) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetTransitions{ ms })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetTransitions { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetTransitions got {response:?}");
+            None
+        }
+    }
+}
+
 fn set_brightness(ac: bool, val: u8) -> Option<bool> {
     let ac = if ac { 1 } else { 0 };
     let response = send_data(comms::DaemonCommand::SetBrightness { ac, val })?;
@@ -153,6 +370,74 @@ fn set_logo(ac: bool, logo_state: u8) -> Option<bool> {
     }
 }
 
+fn get_logo_brightness(ac: bool) -> Option<u8> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::GetLogoBrightness{ ac })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetLogoBrightness { brightness } => {
+            Some(brightness)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetLogoBrightness got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_logo_brightness(ac: bool, val: u8) -> Option<bool> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::SetLogoBrightness{ ac, val })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetLogoBrightness { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetLogoBrightness got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_logo_on_idle(ac: bool) -> Option<bool> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::GetLogoOnIdle{ ac })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetLogoOnIdle { on } => {
+            Some(on)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetLogoOnIdle got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_logo_on_idle(ac: bool, on: bool) -> Option<bool> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::SetLogoOnIdle{ ac, on })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetLogoOnIdle { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetLogoOnIdle got {response:?}");
+            None
+        }
+    }
+}
+
 fn set_effect(name: &str, values: Vec<u8>) -> Option<bool> {
     let response = send_data(comms::DaemonCommand::SetEffect {
         name: name.into(), params: values
@@ -171,6 +456,22 @@ fn set_effect(name: &str, values: Vec<u8>) -> Option<bool> {
     }
 }
 
+fn list_effects() -> Option<Vec<comms::EffectInfo>> {
+    let response = send_data(comms::DaemonCommand::ListEffects())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        ListEffects { effects } => {
+            Some(effects)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of ListEffects got {response:?}");
+            None
+        }
+    }
+}
+
 fn get_power(ac: bool) -> Option<(u8, u8, u8)> {
     let ac = if ac { 1 } else { 0 };
     let mut result = (0, 0, 0);
@@ -270,6 +571,136 @@ fn set_fan_speed(ac: bool, value: i32) -> Option<bool> {
     }
 }
 
+fn list_layers() -> Option<Vec<comms::LayerInfo>> {
+    let response = send_data(comms::DaemonCommand::ListLayers())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        ListLayers { layers } => {
+            Some(layers)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of ListLayers got {response:?}");
+            None
+        }
+    }
+}
+
+fn add_layer(name: &str, params: Vec<u8>) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::AddLayer {
+        position: None, name: name.into(), params, key_mask: None
+    })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        AddLayer { id } => {
+            Some(id.is_some())
+        }
+        response => {
+            // This should not happen
+            println!("Instead of AddLayer got {response:?}");
+            None
+        }
+    }
+}
+
+fn move_layer(id: u32, position: usize) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::MoveLayer { id, position })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        MoveLayer { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of MoveLayer got {response:?}");
+            None
+        }
+    }
+}
+
+fn remove_layer(id: u32) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::RemoveLayer { id })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        RemoveLayer { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of RemoveLayer got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_layer_visible(id: u32, visible: bool) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetLayerVisible { id, visible })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetLayerVisible { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetLayerVisible got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_layer_blend(id: u32, blend: comms::BlendMode, opacity: u8) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetLayerBlend { id, blend, opacity })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        SetLayerBlend { result } => {
+            Some(result)
+        }
+        response => {
+            // This should not happen
+            println!("Instead of SetLayerBlend got {response:?}");
+            None
+        }
+    }
+}
+
+fn export_bundle() -> Option<String> {
+    let response = send_data(comms::DaemonCommand::ExportBundle())?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        ExportBundle { bundle } => {
+            bundle
+        }
+        response => {
+            // This should not happen
+            println!("Instead of ExportBundle got {response:?}");
+            None
+        }
+    }
+}
+
+fn import_bundle(bundle: String) -> Option<(bool, Vec<String>)> {
+    let response = send_data(comms::DaemonCommand::ImportBundle { bundle })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        ImportBundle { result, notes } => {
+            Some((result, notes))
+        }
+        response => {
+            // This should not happen
+            println!("Instead of ImportBundle got {response:?}");
+            None
+        }
+    }
+}
+
 fn main() {
     setup_panic_hook();
     gtk::init().or_crash("Failed to initialize GTK.");
@@ -363,6 +794,7 @@ fn main() {
 fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
     let fan_speed = get_fan_speed(ac).or_crash("Error reading fan speed");
     let brightness = get_brightness(ac).or_crash("Error reading brightness");
+    let animation_fps = get_animation_fps(ac).or_crash("Error reading animation frame rate");
     let power = get_power(ac);
 
     let min_fan_speed = *device.fan.get(0)
@@ -381,14 +813,37 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
                 logo_options.append_text("Off");
                 logo_options.append_text("On");
                 logo_options.append_text("Breathing");
+                let max_logo = if device.has_logo_spectrum() { logo_options.append_text("Spectrum"); 3 } else { 2 };
                 logo_options.set_active(Some(logo as u32));
             logo_options.connect_changed(move |options| {
                 let logo = options.active().or_crash("Illegal state") as u8;
                 set_logo(ac, logo);
-                let logo = get_logo(ac).or_crash("Error reading logo").clamp(0, 2);
+                let logo = get_logo(ac).or_crash("Error reading logo").clamp(0, max_logo);
                 options.set_active(Some(logo as u32));
             });
         let row = SettingsRow::new(&label, &logo_options);
+        settings_section.add_row(&row.master_container);
+            let label = Label::new(Some("Logo brightness"));
+            let scale = Scale::with_range(gtk::Orientation::Horizontal, 0f64, 100f64, 1f64);
+            scale.set_value(get_logo_brightness(ac).or_crash("Error reading logo brightness") as f64);
+            scale.set_width_request(100);
+            scale.connect_change_value(move |scale, _, value| {
+                let value = value.clamp(0f64, 100f64);
+                set_logo_brightness(ac, value as u8).or_crash("Error setting logo brightness");
+                let brightness = get_logo_brightness(ac).or_crash("Error reading logo brightness");
+                scale.set_value(brightness as f64);
+                gtk::glib::Propagation::Stop
+            });
+        let row = SettingsRow::new(&label, &scale);
+        settings_section.add_row(&row.master_container);
+            let label = Label::new(Some("Keep on when idle"));
+            let switch = Switch::new();
+            switch.set_state(get_logo_on_idle(ac).or_crash("Error reading logo on idle"));
+            switch.connect_changed_active(move |switch| {
+                set_logo_on_idle(ac, switch.is_active()).or_crash("Error setting logo on idle");
+                switch.set_state(get_logo_on_idle(ac).or_crash("Error reading logo on idle"));
+            });
+        let row = SettingsRow::new(&label, &switch);
         settings_section.add_row(&row.master_container);
     }
 
@@ -544,6 +999,19 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
             return gtk::glib::Propagation::Stop;
         });
     let row = SettingsRow::new(&label, &scale);
+    settings_section.add_row(&row.master_container);
+        let label = Label::new(Some("Animation frame rate"));
+        let scale = Scale::with_range(gtk::Orientation::Horizontal, 1f64, 60f64, 1f64);
+        scale.set_value(animation_fps as f64);
+        scale.set_width_request(100);
+        scale.connect_change_value(move |scale, _, value| {
+            let value = value.clamp(1f64, 60f64);
+            set_animation_fps(ac, value as u8).or_crash("Error setting animation frame rate");
+            let fps = get_animation_fps(ac).or_crash("Error reading animation frame rate");
+            scale.set_value(fps as f64);
+            gtk::glib::Propagation::Stop
+        });
+    let row = SettingsRow::new(&label, &scale);
     settings_section.add_row(&row.master_container);
 
     settings_page
@@ -555,91 +1023,214 @@ fn make_general_page() -> SettingsPage {
     let page = SettingsPage::new();
 
     // Keyboard Section
+    let effects = Rc::new(list_effects().or_crash("Error reading effects"));
     let settings_section = page.add_section(Some("Keyboard"));
         let label = Label::new(Some("Effect"));
         let effect_options = ComboBoxText::new();
-            effect_options.append_text("Static");
-            effect_options.append_text("Static Gradient");
-            effect_options.append_text("Wave Gradient");
-            effect_options.append_text("Breathing");
-            effect_options.set_active(Some(0));
+            for effect in effects.iter() {
+                effect_options.append(Some(&effect.id), &effect.display_name);
+            }
     let row = SettingsRow::new(&label, &effect_options);
     settings_section.add_row(&row.master_container);
-        let label = Label::new(Some("Color 1"));
-        let color_picker = ColorButton::new();
-    let row = SettingsRow::new(&label, &color_picker);
-    settings_section.add_row(&row.master_container);
-        let label = Label::new(Some("Color 2"));
-        let color_picker_2 = ColorButton::new();
-    let row = SettingsRow::new(&label, &color_picker_2);
-    settings_section.add_row(&row.master_container);
+    let param_widgets: Rc<RefCell<Vec<EffectParamWidget>>> = Rc::new(RefCell::new(vec![]));
         let label = Label::new(Some("Write effect"));
         let button = Button::with_label("Write");
-        button.connect_clicked(clone!(@weak effect_options, @weak color_picker, @weak color_picker_2 =>
-            move |_| {
-                let color = color_picker.color();
-                let red   = (color.red   / 256) as u8;
-                let green = (color.green / 256) as u8;
-                let blue  = (color.blue  / 256) as u8;
-
-                let color = color_picker_2.color();
-                let red2   = (color.red   / 256) as u8;
-                let green2 = (color.green / 256) as u8;
-                let blue2  = (color.blue  / 256) as u8;
-
-                let effect = effect_options.active().or_crash("Illegal state");
-                match effect {
-                    0 => {
-                        set_effect("static", vec![red, green, blue])
-                            .or_crash("Failed to set effect");
-                    },
-                    1 => {
-                        set_effect(
-                            "static_gradient",
-                            vec![red, green, blue, red2, green2, blue2]
-                        ).or_crash("Failed to set effect");
-                    },
-                    2 => {
-                        set_effect("wave_gradient",
-                            vec![red, green, blue, red2, green2, blue2]
-                        ).or_crash("Failed to set effect");
-                    }
-                    3 => {
-                        set_effect(
-                            "breathing_single",
-                            vec![red, green, blue, 10]
-                        ).or_crash("Failed to set effect");
-                    }
-                    _ => {}
-                }
-            }
-        ));
-    let row = SettingsRow::new(&label, &button);
+        let add_button = Button::with_label("Add as layer");
+        let buttons = Box::new(gtk::Orientation::Horizontal, 5);
+        buttons.add(&add_button);
+        buttons.add(&button);
+    let row = SettingsRow::new(&label, &buttons);
+    settings_section.add_row(&row.master_container);
+        let label = Label::new(Some("React to key presses"));
+        let switch = Switch::new();
+        switch.set_state(get_key_events().or_crash("Error reading key events"));
+        switch.connect_changed_active(|switch| {
+            set_key_events(switch.is_active()).or_crash("Error setting key events");
+            switch.set_state(get_key_events().or_crash("Error reading key events"));
+        });
+    let row = SettingsRow::new(&label, &switch);
+    settings_section.add_row(&row.master_container);
+        let label = Label::new(Some("Transitions (ms)"));
+        let scale = Scale::with_range(gtk::Orientation::Horizontal, 0f64, 2000f64, 50f64);
+        scale.set_value(get_transitions().or_crash("Error reading transitions") as f64);
+        scale.set_width_request(100);
+        scale.connect_change_value(|scale, _, value| {
+            let value = value.clamp(0f64, 2000f64);
+            set_transitions(value as u32).or_crash("Error setting transitions");
+            let ms = get_transitions().or_crash("Error reading transitions");
+            scale.set_value(ms as f64);
+            gtk::glib::Propagation::Stop
+        });
+    let row = SettingsRow::new(&label, &scale);
+    settings_section.add_row(&row.master_container);
+        // Caps, num and scroll lock are turned on and off together here
+        let label = Label::new(Some("Lock key indicators"));
+        let indicators = get_lock_indicators().or_crash("Error reading lock indicators");
+        let [r, g, b] = indicators.colour;
+        let colour = ColorButton::new();
+        colour.set_rgba(&gtk::gdk::RGBA::new(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0, 1.0));
+        let switch = Switch::new();
+        switch.set_state(indicators.caps || indicators.num || indicators.scroll);
+        let apply = clone!(@weak switch, @weak colour => move || {
+            let on = switch.is_active();
+            let rgba = colour.rgba();
+            let channel = |c: f64| (c * 255.0).round() as u8;
+            let indicators = comms::LockIndicators {
+                caps: on,
+                num: on,
+                scroll: on,
+                colour: [channel(rgba.red()), channel(rgba.green()), channel(rgba.blue())],
+            };
+            set_lock_indicators(indicators).or_crash("Error setting lock indicators");
+            let indicators = get_lock_indicators().or_crash("Error reading lock indicators");
+            switch.set_state(indicators.caps || indicators.num || indicators.scroll);
+        });
+        switch.connect_changed_active(clone!(@strong apply => move |_| apply()));
+        colour.connect_color_set(move |_| apply());
+        let buttons = Box::new(gtk::Orientation::Horizontal, 5);
+        buttons.add(&colour);
+        buttons.add(&switch);
+    let row = SettingsRow::new(&label, &buttons);
+    settings_section.add_row(&row.master_container);
+        let label = Label::new(Some("Flash on notifications"));
+        let switch = Switch::new();
+        switch.set_state(get_notifications().or_crash("Error reading notifications").0);
+        switch.connect_changed_active(|switch| {
+            set_notifications(switch.is_active()).or_crash("Error setting notifications");
+            switch.set_state(get_notifications().or_crash("Error reading notifications").0);
+        });
+    let row = SettingsRow::new(&label, &switch);
+    settings_section.add_row(&row.master_container);
+        let label = Label::new(Some("Do not disturb"));
+        let switch = Switch::new();
+        switch.set_state(get_notifications().or_crash("Error reading notifications").1);
+        switch.connect_changed_active(|switch| {
+            set_do_not_disturb(switch.is_active()).or_crash("Error setting do not disturb");
+            switch.set_state(get_notifications().or_crash("Error reading notifications").1);
+        });
+    let row = SettingsRow::new(&label, &switch);
     settings_section.add_row(&row.master_container);
 
-
-    effect_options.connect_changed(clone!(@weak color_picker, @weak color_picker_2 =>
+    // Effect parameters section, rebuilt from the effect's parameter list
+    let params_section = Rc::new(page.add_section(Some("Effect Parameters")));
+    effect_options.connect_changed(clone!(@strong effects, @strong params_section, @strong param_widgets =>
         move |options| {
-            let logo = options.active().or_crash("Illegal state"); // Unwrap: There is always one active
-            
-            match logo {
-                0 => {
-                    // TODO: Color 1 visible
-                },
-                1 => {
-                    // TODO: Color 1 and 2 visible
-                },
-                2 => {
-                    // TODO: Color 1 and 2 visible
-                }
-                3 => {
-                    // TODO: Color 1, 2, and duration visible
-                }
-                _ => {}
+            let effect = options.active().or_crash("Illegal state"); // Unwrap: There is always one active
+            let effect = &effects[effect as usize];
+
+            params_section.clear();
+            let mut widgets = param_widgets.borrow_mut();
+            widgets.clear();
+            for param in effect.params.iter() {
+                let label = Label::new(Some(&param.name));
+                let widget = EffectParamWidget::new(&param.kind);
+                let row = SettingsRow::new(&label, widget.widget());
+                params_section.add_row(&row.master_container);
+                widgets.push(widget);
+            }
+            params_section.master_container.show_all();
+        }
+    ));
+    effect_options.set_active(Some(0));
+
+    // Layers section, rebuilt every time the layers change
+    let layers_section = Rc::new(page.add_section(Some("Layers")));
+    refresh_layers(&layers_section, &effects);
+
+    button.connect_clicked(clone!(@weak effect_options, @strong param_widgets, @strong layers_section, @strong effects =>
+        move |_| {
+            let id = effect_options.active_id().or_crash("Illegal state");
+            let mut values = vec![];
+            for widget in param_widgets.borrow().iter() {
+                values.extend(widget.value());
             }
+            set_effect(id.as_str(), values).or_crash("Failed to set effect");
+            refresh_layers(&layers_section, &effects);
+        }
+    ));
+    add_button.connect_clicked(clone!(@weak effect_options, @strong param_widgets, @strong layers_section, @strong effects =>
+        move |_| {
+            let id = effect_options.active_id().or_crash("Illegal state");
+            let mut values = vec![];
+            for widget in param_widgets.borrow().iter() {
+                values.extend(widget.value());
+            }
+            add_layer(id.as_str(), values).or_crash("Failed to add layer");
+            refresh_layers(&layers_section, &effects);
         }
     ));
 
+    // Settings bundle section
+    let settings_section = page.add_section(Some("Settings Bundle"));
+        let label = Label::new(Some("Export settings and effects to a file"));
+        let button = Button::with_label("Export");
+        button.connect_clicked(|_| {
+            let path = match choose_bundle_file(FileChooserAction::Save) {
+                Some(path) => path,
+                None => return,
+            };
+            let bundle = export_bundle().or_crash("Failed to export settings");
+            match std::fs::write(&path, bundle) {
+                Ok(_) => show_message(gtk::MessageType::Info, "Settings exported"),
+                Err(e) => show_message(gtk::MessageType::Error, &format!("Error writing the file: {e}")),
+            }
+        });
+    let row = SettingsRow::new(&label, &button);
+    settings_section.add_row(&row.master_container);
+        let label = Label::new(Some("Import settings and effects from a file"));
+        let button = Button::with_label("Import");
+        button.connect_clicked(|_| {
+            let path = match choose_bundle_file(FileChooserAction::Open) {
+                Some(path) => path,
+                None => return,
+            };
+            let bundle = match std::fs::read_to_string(&path) {
+                Ok(bundle) => bundle,
+                Err(e) => {
+                    show_message(gtk::MessageType::Error, &format!("Error reading the file: {e}"));
+                    return;
+                }
+            };
+            let (result, notes) = import_bundle(bundle).or_crash("Failed to import settings");
+            if !result {
+                show_message(gtk::MessageType::Error, &format!("Invalid bundle: {}", notes.join("\n")));
+            } else if notes.is_empty() {
+                show_message(gtk::MessageType::Info, "Settings imported");
+            } else {
+                show_message(gtk::MessageType::Info, &format!("Settings imported with changes:\n{}", notes.join("\n")));
+            }
+        });
+    let row = SettingsRow::new(&label, &button);
+    settings_section.add_row(&row.master_container);
+
+    // Low battery section
+    let settings_section = page.add_section(Some("Low Battery"));
+        let label = Label::new(Some("Low battery level (%, 0 for off)"));
+        let scale = Scale::with_range(gtk::Orientation::Horizontal, 0f64, 50f64, 1f64);
+        scale.set_value(get_low_battery().or_crash("Error reading low battery").0);
+        scale.set_width_request(100);
+        scale.connect_change_value(|scale, _, value| {
+            let (_, actions) = get_low_battery().or_crash("Error reading low battery");
+            set_low_battery(value.clamp(0f64, 50f64).round(), actions).or_crash("Error setting low battery");
+            scale.set_value(get_low_battery().or_crash("Error reading low battery").0);
+            gtk::glib::Propagation::Stop
+        });
+    let row = SettingsRow::new(&label, &scale);
+    settings_section.add_row(&row.master_container);
+        let label = Label::new(Some("Brightness on low battery"));
+        let scale = Scale::with_range(gtk::Orientation::Horizontal, 0f64, 100f64, 1f64);
+        scale.set_value(get_low_battery().or_crash("Error reading low battery").1.brightness as f64);
+        scale.set_width_request(100);
+        scale.connect_change_value(|scale, _, value| {
+            let (threshold, mut actions) = get_low_battery().or_crash("Error reading low battery");
+            actions.brightness = value.clamp(0f64, 100f64) as u8;
+            set_low_battery(threshold, actions).or_crash("Error setting low battery");
+            scale.set_value(get_low_battery().or_crash("Error reading low battery").1.brightness as f64);
+            gtk::glib::Propagation::Stop
+        });
+    let row = SettingsRow::new(&label, &scale);
+    settings_section.add_row(&row.master_container);
+
     // Battery Health Optimizer section
     if let Some(bho) = bho {
         let settings_section = page.add_section(Some("Battery Health Optimizer"));
@@ -687,6 +1278,116 @@ fn make_general_page() -> SettingsPage {
     page
 }
 
+/// Fills the layers section with one row per layer, top layer first
+fn refresh_layers(section: &Rc<SettingsSection>, effects: &Rc<Vec<comms::EffectInfo>>) {
+    let layers = list_layers().or_crash("Error reading layers");
+
+    section.clear();
+    if layers.is_empty() {
+        let label = Label::new(Some("No layers"));
+        let row = SettingsRow::new(&label, &Box::new(gtk::Orientation::Horizontal, 0));
+        section.add_row(&row.master_container);
+    }
+    let count = layers.len();
+    for (position, layer) in layers.into_iter().enumerate().rev() {
+        let id = layer.id;
+        let name = effects.iter()
+            .find(|e| e.id == layer.effect)
+            .map(|e| e.display_name.clone())
+            .unwrap_or(layer.effect);
+        let keys = layer.key_mask.iter().filter(|k| **k).count();
+        let label = Label::new(Some(&format!("{} ({} keys)", name, keys)));
+        let controls = Box::new(gtk::Orientation::Horizontal, 5);
+            let switch = Switch::new();
+            switch.set_state(layer.visible);
+            switch.set_valign(gtk::Align::Center);
+            switch.connect_changed_active(move |switch| {
+                set_layer_visible(id, switch.is_active()).or_crash("Error setting layer visibility");
+            });
+            let blend_modes = [
+                (comms::BlendMode::Replace, "Replace"),
+                (comms::BlendMode::Alpha, "Alpha"),
+                (comms::BlendMode::Add, "Add"),
+                (comms::BlendMode::Multiply, "Multiply"),
+                (comms::BlendMode::Max, "Max"),
+            ];
+            let blend = ComboBoxText::new();
+            for (idx, (mode, name)) in blend_modes.iter().enumerate() {
+                blend.append_text(name);
+                if *mode == layer.blend {
+                    blend.set_active(Some(idx as u32));
+                }
+            }
+            blend.set_valign(gtk::Align::Center);
+            let opacity = Scale::with_range(gtk::Orientation::Horizontal, 0f64, 100f64, 1f64);
+            opacity.set_value(layer.opacity as f64);
+            opacity.set_width_request(100);
+            opacity.set_sensitive(layer.blend != comms::BlendMode::Replace);
+            blend.connect_changed(clone!(@weak opacity => move |blend| {
+                let mode = blend_modes[blend.active().or_crash("Illegal state") as usize].0;
+                opacity.set_sensitive(mode != comms::BlendMode::Replace);
+                set_layer_blend(id, mode, opacity.value() as u8).or_crash("Error setting layer blend");
+            }));
+            opacity.connect_change_value(clone!(@weak blend => @default-return gtk::glib::Propagation::Stop, move |_, _, value| {
+                let mode = blend_modes[blend.active().or_crash("Illegal state") as usize].0;
+                set_layer_blend(id, mode, value.clamp(0.0, 100.0) as u8).or_crash("Error setting layer blend");
+                gtk::glib::Propagation::Proceed
+            }));
+            let up = Button::with_label("Up");
+            up.set_sensitive(position + 1 < count);
+            up.connect_clicked(clone!(@strong section, @strong effects => move |_| {
+                move_layer(id, position + 1).or_crash("Error moving layer");
+                refresh_layers(&section, &effects);
+            }));
+            let down = Button::with_label("Down");
+            down.set_sensitive(position > 0);
+            down.connect_clicked(clone!(@strong section, @strong effects => move |_| {
+                move_layer(id, position - 1).or_crash("Error moving layer");
+                refresh_layers(&section, &effects);
+            }));
+            let remove = Button::with_label("Remove");
+            remove.connect_clicked(clone!(@strong section, @strong effects => move |_| {
+                remove_layer(id).or_crash("Error removing layer");
+                refresh_layers(&section, &effects);
+            }));
+        controls.add(&switch);
+        controls.add(&blend);
+        controls.add(&opacity);
+        controls.add(&up);
+        controls.add(&down);
+        controls.add(&remove);
+        let row = SettingsRow::new(&label, &controls);
+        section.add_row(&row.master_container);
+    }
+    section.master_container.show_all();
+}
+
+fn choose_bundle_file(action: FileChooserAction) -> Option<std::path::PathBuf> {
+    let accept = if action == FileChooserAction::Save { "Export" } else { "Import" };
+    let dialog = FileChooserDialog::with_buttons(
+        Some("Settings bundle"), None::<&gtk::Window>, action,
+        &[("Cancel", ResponseType::Cancel), (accept, ResponseType::Accept)]
+    );
+    dialog.set_do_overwrite_confirmation(true);
+    if action == FileChooserAction::Save {
+        dialog.set_current_name("razer-settings.json");
+    }
+    let path = match dialog.run() {
+        ResponseType::Accept => dialog.filename(),
+        _ => None,
+    };
+    dialog.close();
+    path
+}
+
+fn show_message(kind: gtk::MessageType, msg: &str) {
+    let msg_box = MessageDialog::new::<ApplicationWindow>(
+        None, DialogFlags::MODAL, kind, gtk::ButtonsType::Ok, msg
+    );
+    msg_box.run();
+    msg_box.close();
+}
+
 fn make_about_page(device: SupportedDevice) -> SettingsPage {
     let page = SettingsPage::new();
 
@@ -2,9 +2,12 @@ use std::cell::Cell;
 
 use gtk::prelude::*;
 use gtk::{
-    Box, Frame, Label, ListBox, ListBoxRow, Separator, Widget, Grid
+    Box, Frame, Label, ListBox, ListBoxRow, Separator, Widget, Grid,
+    ColorButton, ComboBoxText, Scale, FileChooserButton, FileChooserAction, FileFilter
 };
 
+use crate::comms;
+
 pub struct SettingsPage {
     // TODO: Can I make this a widget? This is self originally
     pub master_container: Box
@@ -147,4 +150,102 @@ impl SettingsSection {
 
         self.need_separator.set(true);
     }
+
+    /// Removes all the rows of the section
+    pub fn clear(&self) {
+        for child in self.container.children() {
+            self.container.remove(&child);
+        }
+        self.need_separator.set(false);
+    }
+}
+
+/// Input widget for one custom effect parameter
+pub enum EffectParamWidget {
+    Colour(ColorButton),
+    Range(Scale),
+    Choice(ComboBoxText),
+    File(FileChooserButton),
+    KeyColours(ColorButton), // Same colour on every key
+    FileContents(FileChooserButton),
+}
+
+impl EffectParamWidget {
+
+    pub fn new(kind: &comms::EffectParamKind) -> EffectParamWidget {
+        match kind {
+            comms::EffectParamKind::Colour => {
+                EffectParamWidget::Colour(ColorButton::new())
+            },
+            comms::EffectParamKind::Range { min, max, default } => {
+                let scale = Scale::with_range(gtk::Orientation::Horizontal, *min as f64, *max as f64, 1f64);
+                scale.set_value(*default as f64);
+                scale.set_width_request(200);
+                EffectParamWidget::Range(scale)
+            },
+            comms::EffectParamKind::Choice { options, default } => {
+                let combo = ComboBoxText::new();
+                for option in options {
+                    combo.append_text(option);
+                }
+                combo.set_active(Some(*default as u32));
+                EffectParamWidget::Choice(combo)
+            },
+            comms::EffectParamKind::File => {
+                let chooser = FileChooserButton::new("Choose a file", FileChooserAction::Open);
+                let filter = FileFilter::new();
+                filter.add_mime_type("image/png");
+                filter.add_mime_type("image/apng");
+                filter.add_mime_type("image/gif");
+                chooser.set_filter(&filter);
+                EffectParamWidget::File(chooser)
+            },
+            comms::EffectParamKind::KeyColours => {
+                EffectParamWidget::KeyColours(ColorButton::new())
+            },
+            comms::EffectParamKind::FileContents => {
+                let chooser = FileChooserButton::new("Choose a file", FileChooserAction::Open);
+                let filter = FileFilter::new();
+                filter.add_mime_type("application/json");
+                chooser.set_filter(&filter);
+                EffectParamWidget::FileContents(chooser)
+            },
+        }
+    }
+
+    pub fn widget(&self) -> &Widget {
+        match self {
+            EffectParamWidget::Colour(w) => w.upcast_ref(),
+            EffectParamWidget::Range(w) => w.upcast_ref(),
+            EffectParamWidget::Choice(w) => w.upcast_ref(),
+            EffectParamWidget::File(w) => w.upcast_ref(),
+            EffectParamWidget::KeyColours(w) => w.upcast_ref(),
+            EffectParamWidget::FileContents(w) => w.upcast_ref(),
+        }
+    }
+
+    /// Bytes of the parameter, as expected by the effect
+    pub fn value(&self) -> Vec<u8> {
+        match self {
+            EffectParamWidget::Colour(w) => {
+                let color = w.color();
+                vec![(color.red / 256) as u8, (color.green / 256) as u8, (color.blue / 256) as u8]
+            },
+            EffectParamWidget::Range(w) => vec![w.value() as u8],
+            EffectParamWidget::Choice(w) => vec![w.active().unwrap_or(0) as u8],
+            EffectParamWidget::File(w) => {
+                let path = w.filename().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+                comms::encode_path(&path)
+            },
+            EffectParamWidget::KeyColours(w) => {
+                let color = w.color();
+                [(color.red / 256) as u8, (color.green / 256) as u8, (color.blue / 256) as u8].repeat(90)
+            },
+            EffectParamWidget::FileContents(w) => {
+                // The daemon reports unreadable or invalid files when the effect is set
+                let text = w.filename().and_then(|p| std::fs::read_to_string(p).ok()).unwrap_or_default();
+                comms::encode_path(&text)
+            },
+        }
+    }
 }